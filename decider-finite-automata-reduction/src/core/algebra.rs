@@ -1,6 +1,7 @@
 //! Definitions of Boolean matrices and their operations, implemented using fast bitwise operations.
+//! The bits may be stored in any `Bits` type; by default, one with room for `MAX_NFA` states.
 
-use super::{BadProof, NFAState, NFAStateMask, ProofResult};
+use super::{BadProof, Bits, NFAState, NFAStateMask, ProofResult};
 use serde::{Deserialize, Serialize};
 use std::cmp::Ordering;
 use std::ops::{BitOr, BitOrAssign, Index, IndexMut, Mul};
@@ -8,31 +9,30 @@ use std::ops::{BitOr, BitOrAssign, Index, IndexMut, Mul};
 /// A Boolean column vector, representing a set of NFAStates (to test against).
 #[derive(Serialize, Deserialize, Copy, Clone, Debug, Default, Eq, PartialEq)]
 #[serde(transparent)]
-pub struct ColVector(NFAStateMask);
+pub struct ColVector<B = NFAStateMask>(B);
 
 /// A Boolean row vector, representing a set of NFAStates (which the NFA has reached).
-#[derive(Serialize, Deserialize, Copy, Clone, Debug, Default, Eq, PartialEq)]
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Default, Eq, Hash, PartialEq)]
 #[serde(transparent)]
-pub struct RowVector(NFAStateMask);
+pub struct RowVector<B = NFAStateMask>(B);
 
 /// A Boolean square matrix, representing a transition.
 #[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
 #[serde(transparent)]
-pub struct Matrix {
-    rows: Vec<RowVector>,
+pub struct Matrix<B = NFAStateMask> {
+    rows: Vec<RowVector<B>>,
 }
 
 /// Iterator for the indices of set bits (i.e., the NFAStates in the set being represented).
-pub struct IterBits(NFAStateMask);
+pub struct IterBits<B>(B);
 
-fn bits_from_iter<I: IntoIterator<Item = NFAState>>(iter: I) -> NFAStateMask {
-    iter.into_iter()
-        .fold(0 as NFAStateMask, |v, i| v | (1 as NFAStateMask) << i)
+fn bits_from_iter<B: Bits, I: IntoIterator<Item = NFAState>>(iter: I) -> B {
+    iter.into_iter().fold(B::default(), |v, i| v | B::bit(i))
 }
 
 /// Ensure mask uses only the first n bits.
-fn validate_mask(mask: NFAStateMask, n: usize) -> ProofResult<()> {
-    if (mask >> n) == 0 {
+fn validate_mask<B: Bits>(mask: B, n: usize) -> ProofResult<()> {
+    if mask.fits(n) {
         Ok(())
     } else {
         Err(BadProof::BadVector)
@@ -40,7 +40,7 @@ fn validate_mask(mask: NFAStateMask, n: usize) -> ProofResult<()> {
 }
 
 /// Implement the partial order given by bitwise inclusion.
-fn bitwise_partial_cmp(l: NFAStateMask, r: NFAStateMask) -> Option<Ordering> {
+fn bitwise_partial_cmp<B: Bits>(l: B, r: B) -> Option<Ordering> {
     let intersection = l & r;
     if l == r {
         Some(Ordering::Equal)
@@ -53,131 +53,135 @@ fn bitwise_partial_cmp(l: NFAStateMask, r: NFAStateMask) -> Option<Ordering> {
     }
 }
 
-impl ColVector {
+impl<B: Bits> ColVector<B> {
     /// The zero vector.
-    pub fn new() -> ColVector {
-        ColVector(0)
+    pub fn new() -> ColVector<B> {
+        ColVector(B::default())
     }
 
     /// The standard basis vectors, representing sets of the form {i}.
-    pub fn e(i: NFAState) -> ColVector {
-        ColVector((1 as NFAStateMask) << i)
+    pub fn e(i: NFAState) -> ColVector<B> {
+        ColVector(B::bit(i))
     }
 
     /// Ensure self is valid as an n-dimensional vector.
     pub fn validate(self, n: usize) -> ProofResult<()> {
         validate_mask(self.0, n)
     }
+
+    /// The same set, stored in another `Bits` type (which must have room for it).
+    pub fn widen<C: Bits>(self) -> ColVector<C> {
+        self.into_iter().collect()
+    }
 }
 
-impl BitOr for ColVector {
+impl<B: Bits> BitOr for ColVector<B> {
     type Output = Self;
     fn bitor(self, rhs: Self) -> Self {
         Self(self.0 | rhs.0)
     }
 }
 
-impl BitOrAssign for ColVector {
+impl<B: Bits> BitOrAssign for ColVector<B> {
     fn bitor_assign(&mut self, rhs: Self) {
         self.0 |= rhs.0;
     }
 }
 
-impl PartialOrd for ColVector {
+impl<B: Bits> PartialOrd for ColVector<B> {
     fn partial_cmp(&self, rhs: &Self) -> Option<Ordering> {
         bitwise_partial_cmp(self.0, rhs.0)
     }
 }
 
-impl RowVector {
+impl<B: Bits> RowVector<B> {
     /// The zero vector.
-    pub fn new() -> RowVector {
-        RowVector(0)
+    pub fn new() -> RowVector<B> {
+        RowVector(B::default())
     }
 
     /// The standard basis vectors, representing sets of the form {i}.
-    pub fn e(i: NFAState) -> RowVector {
-        RowVector((1 as NFAStateMask) << i)
+    pub fn e(i: NFAState) -> RowVector<B> {
+        RowVector(B::bit(i))
     }
 
     /// Ensure self is valid as an n-dimensional vector.
     pub fn validate(self, n: usize) -> ProofResult<()> {
         validate_mask(self.0, n)
     }
+
+    /// The same set, stored in another `Bits` type (which must have room for it).
+    pub fn widen<C: Bits>(self) -> RowVector<C> {
+        self.into_iter().collect()
+    }
 }
 
-impl BitOr for RowVector {
+impl<B: Bits> BitOr for RowVector<B> {
     type Output = Self;
     fn bitor(self, rhs: Self) -> Self {
         Self(self.0 | rhs.0)
     }
 }
 
-impl BitOrAssign for RowVector {
+impl<B: Bits> BitOrAssign for RowVector<B> {
     fn bitor_assign(&mut self, rhs: Self) {
         self.0 |= rhs.0;
     }
 }
 
-impl PartialOrd for RowVector {
+impl<B: Bits> PartialOrd for RowVector<B> {
     fn partial_cmp(&self, rhs: &Self) -> Option<Ordering> {
         bitwise_partial_cmp(self.0, rhs.0)
     }
 }
 
-impl Mul<ColVector> for RowVector {
+impl<B: Bits> Mul<ColVector<B>> for RowVector<B> {
     type Output = bool;
-    fn mul(self, rhs: ColVector) -> bool {
-        (self.0 & rhs.0) != 0
+    fn mul(self, rhs: ColVector<B>) -> bool {
+        !(self.0 & rhs.0).is_empty()
     }
 }
 
-impl Iterator for IterBits {
+impl<B: Bits> Iterator for IterBits<B> {
     type Item = NFAState;
     fn next(&mut self) -> Option<NFAState> {
-        if self.0 == 0 {
-            None
-        } else {
-            let lsb = self.0.trailing_zeros() as NFAState;
-            self.0 &= self.0.wrapping_sub(1);
-            Some(lsb)
-        }
+        self.0.pop_lowest()
     }
 }
 
-impl IntoIterator for ColVector {
+impl<B: Bits> IntoIterator for ColVector<B> {
     type Item = NFAState;
-    type IntoIter = IterBits;
-    fn into_iter(self) -> IterBits {
+    type IntoIter = IterBits<B>;
+    fn into_iter(self) -> IterBits<B> {
         IterBits(self.0)
     }
 }
 
-impl IntoIterator for RowVector {
+impl<B: Bits> IntoIterator for RowVector<B> {
     type Item = NFAState;
-    type IntoIter = IterBits;
-    fn into_iter(self) -> IterBits {
+    type IntoIter = IterBits<B>;
+    fn into_iter(self) -> IterBits<B> {
         IterBits(self.0)
     }
 }
 
-impl FromIterator<NFAState> for ColVector {
+impl<B: Bits> FromIterator<NFAState> for ColVector<B> {
     fn from_iter<I: IntoIterator<Item = NFAState>>(iter: I) -> Self {
         Self(bits_from_iter(iter))
     }
 }
 
-impl FromIterator<NFAState> for RowVector {
+impl<B: Bits> FromIterator<NFAState> for RowVector<B> {
     fn from_iter<I: IntoIterator<Item = NFAState>>(iter: I) -> Self {
         Self(bits_from_iter(iter))
     }
 }
 
-impl Matrix {
+impl<B: Bits> Matrix<B> {
     /// An n x n matrix of zeros.
-    pub fn new(n: usize) -> Matrix {
+    pub fn new(n: usize) -> Matrix<B> {
         Matrix {
-            rows: vec![RowVector(0); n],
+            rows: vec![RowVector::new(); n],
         }
     }
 
@@ -190,53 +194,61 @@ impl Matrix {
     pub fn len(&self) -> usize {
         self.rows.len()
     }
+
+    /// The same matrix, stored in another `Bits` type (which must have room for it).
+    pub fn widen<C: Bits>(&self) -> Matrix<C> {
+        Matrix {
+            rows: self.rows.iter().map(|v| v.widen()).collect(),
+        }
+    }
 }
 
-impl Index<NFAState> for Matrix {
-    type Output = RowVector;
+impl<B: Bits> Index<NFAState> for Matrix<B> {
+    type Output = RowVector<B>;
     fn index(&self, i: NFAState) -> &Self::Output {
         &self.rows[i as usize]
     }
 }
 
-impl IndexMut<NFAState> for Matrix {
+impl<B: Bits> IndexMut<NFAState> for Matrix<B> {
     fn index_mut(&mut self, i: NFAState) -> &mut Self::Output {
         &mut (self.rows[i as usize])
     }
 }
 
-impl<'a> IntoIterator for &'a Matrix {
-    type Item = &'a RowVector;
-    type IntoIter = std::slice::Iter<'a, RowVector>;
+impl<'a, B: Bits> IntoIterator for &'a Matrix<B> {
+    type Item = &'a RowVector<B>;
+    type IntoIter = std::slice::Iter<'a, RowVector<B>>;
     fn into_iter(self) -> Self::IntoIter {
         self.rows.iter()
     }
 }
 
-impl Mul<&Matrix> for RowVector {
-    type Output = RowVector;
-    fn mul(self, rhs: &Matrix) -> RowVector {
-        self.into_iter().fold(RowVector(0), |v, pos| v | rhs[pos])
+impl<B: Bits> Mul<&Matrix<B>> for RowVector<B> {
+    type Output = RowVector<B>;
+    fn mul(self, rhs: &Matrix<B>) -> RowVector<B> {
+        self.into_iter()
+            .fold(RowVector::new(), |v, pos| v | rhs[pos])
     }
 }
 
-impl Mul<ColVector> for &Matrix {
-    type Output = ColVector;
-    fn mul(self, rhs: ColVector) -> ColVector {
+impl<B: Bits> Mul<ColVector<B>> for &Matrix<B> {
+    type Output = ColVector<B>;
+    fn mul(self, rhs: ColVector<B>) -> ColVector<B> {
         self.into_iter()
             .enumerate()
             .filter_map(|(i, &v)| if v * rhs { Some(i as NFAState) } else { None })
-            .collect::<ColVector>()
+            .collect::<ColVector<B>>()
     }
 }
 
 /// Synonym for `RowVector::e(i)`.
-pub fn row(i: NFAState) -> RowVector {
+pub fn row<B: Bits>(i: NFAState) -> RowVector<B> {
     RowVector::e(i)
 }
 
 /// Synonym for `ColVector::e(i)`.
-pub fn col(i: NFAState) -> ColVector {
+pub fn col<B: Bits>(i: NFAState) -> ColVector<B> {
     ColVector::e(i)
 }
 
@@ -244,21 +256,39 @@ pub fn col(i: NFAState) -> ColVector {
 mod tests {
     use super::*;
 
-    #[test]
-    fn test_validation() {
-        assert_eq!(ColVector::new().validate(0), Ok(()));
-        assert_eq!(RowVector::new().validate(0), Ok(()));
+    /// Run a check with each `Bits` type the provers use.
+    macro_rules! for_each_width {
+        ($check:ident) => {
+            $check::<u64>();
+            $check::<u128>();
+            $check::<NFAStateMask>();
+        };
+    }
+
+    fn check_validation<B: Bits>() {
+        assert_eq!(ColVector::<B>::new().validate(0), Ok(()));
+        assert_eq!(RowVector::<B>::new().validate(0), Ok(()));
         for n in 0..3 {
             for i in 0..3 {
-                assert_eq!(row(i).validate(n).is_ok(), (i as usize) < n);
-                assert_eq!(col(i).validate(n).is_ok(), (i as usize) < n);
+                assert_eq!(row::<B>(i).validate(n).is_ok(), (i as usize) < n);
+                assert_eq!(col::<B>(i).validate(n).is_ok(), (i as usize) < n);
             }
         }
+        let top = (B::BITS - 1) as NFAState;
+        assert_eq!(row::<B>(top).validate(B::BITS), Ok(()));
+        assert_eq!(
+            row::<B>(top).validate(B::BITS - 1),
+            Err(BadProof::BadVector)
+        );
     }
 
     #[test]
-    fn test_or() {
-        let (bra_0, bra_1, ket_0, ket_1) = (row(0), row(1), col(0), col(1));
+    fn test_validation() {
+        for_each_width!(check_validation);
+    }
+
+    fn check_or<B: Bits>() {
+        let (bra_0, bra_1, ket_0, ket_1) = (row::<B>(0), row::<B>(1), col::<B>(0), col::<B>(1));
         // Commutative "|" operation, compatible "|=":
         let or_a = bra_0 | bra_1;
         let mut or_b = bra_1;
@@ -271,38 +301,52 @@ mod tests {
     }
 
     #[test]
-    fn test_iteration() {
-        let states_set: Vec<NFAState> = (row(0) | row(2) | row(5)).into_iter().collect();
+    fn test_or() {
+        for_each_width!(check_or);
+    }
+
+    fn check_iteration<B: Bits>() {
+        let states_set: Vec<NFAState> = (row::<B>(0) | row(2) | row(5)).into_iter().collect();
         assert_eq!(states_set, vec![0, 2, 5]);
-        let states_set: Vec<NFAState> = (col(1) | col(3) | col(5)).into_iter().collect();
+        let states_set: Vec<NFAState> = (col::<B>(1) | col(3) | col(5)).into_iter().collect();
         assert_eq!(states_set, vec![1, 3, 5]);
+        let top = (B::BITS - 1) as NFAState;
+        let states_set: Vec<NFAState> = (row::<B>(1) | row(top)).into_iter().collect();
+        assert_eq!(states_set, vec![1, top]);
+    }
+
+    #[test]
+    fn test_iteration() {
+        for_each_width!(check_iteration);
     }
 
     #[test]
     fn test_comparison() {
         let (lt, gt) = (Some(Ordering::Less), Some(Ordering::Greater));
         let eq = Some(Ordering::Equal);
-        assert_eq!(RowVector(0b00).partial_cmp(&RowVector(0b01)), lt);
-        assert_eq!(ColVector(0b01).partial_cmp(&ColVector(0b01)), eq);
-        assert_eq!(RowVector(0b11).partial_cmp(&RowVector(0b10)), gt);
-        assert_eq!(ColVector(0b01).partial_cmp(&ColVector(0b10)), None);
+        assert_eq!(RowVector(0b00u64).partial_cmp(&RowVector(0b01)), lt);
+        assert_eq!(ColVector(0b01u64).partial_cmp(&ColVector(0b01)), eq);
+        assert_eq!(RowVector(0b11u64).partial_cmp(&RowVector(0b10)), gt);
+        assert_eq!(ColVector(0b01u64).partial_cmp(&ColVector(0b10)), None);
+        let (r_0, r_200) = (row::<NFAStateMask>(0), row::<NFAStateMask>(200));
+        assert_eq!(r_0.partial_cmp(&(r_0 | r_200)), lt);
+        assert_eq!(r_200.partial_cmp(&r_0), None);
     }
 
-    #[test]
-    fn test_inner_product() {
+    fn check_inner_product<B: Bits>() {
         for i in 0 as NFAState..2 as NFAState {
             for j in 0 as NFAState..2 as NFAState {
-                assert_eq!(row(i) * col(j), (i == j));
+                assert_eq!(row::<B>(i) * col(j), (i == j));
                 // distributive law
                 for i2 in 0 as NFAState..2 as NFAState {
                     for j2 in 0 as NFAState..2 as NFAState {
                         assert_eq!(
-                            (row(i) | row(i2)) * col(j),
-                            row(i) * col(j) | row(i2) * col(j)
+                            (row::<B>(i) | row(i2)) * col(j),
+                            row::<B>(i) * col(j) | row::<B>(i2) * col(j)
                         );
                         assert_eq!(
-                            row(i) * (col(j) | col(j2)),
-                            row(i) * col(j) | row(i) * col(j2)
+                            row::<B>(i) * (col(j) | col(j2)),
+                            row::<B>(i) * col(j) | row::<B>(i) * col(j2)
                         );
                     }
                 }
@@ -311,13 +355,17 @@ mod tests {
     }
 
     #[test]
-    fn test_matrix_ops() {
+    fn test_inner_product() {
+        for_each_width!(check_inner_product);
+    }
+
+    fn check_matrix_ops<B: Bits>() {
         // Construct the identity matrix [1, 0; 0, 1], bit by bit:
-        let mut id = Matrix::new(2);
+        let mut id = Matrix::<B>::new(2);
         id[0] |= row(0);
         id[1] |= row(1);
         // Construct the upper-triangular matrix [1, 1; 0, 1], bit by bit:
-        let mut upper_tri = Matrix::new(2);
+        let mut upper_tri = Matrix::<B>::new(2);
         upper_tri[0] |= id[0];
         upper_tri[1] |= id[1];
         upper_tri[0] |= row(1);
@@ -329,6 +377,13 @@ mod tests {
                 assert_eq!(row(i) * (&upper_tri * col(j)), (i <= j));
             }
         }
+        // Widening preserves everything:
+        assert_eq!(upper_tri.widen::<NFAStateMask>().widen::<B>(), upper_tri);
+    }
+
+    #[test]
+    fn test_matrix_ops() {
+        for_each_width!(check_matrix_ops);
     }
 
     #[test]
@@ -339,5 +394,9 @@ mod tests {
         assert_eq!(ones[1], row(0) | row(1));
         let oops_2_by_1: Matrix = serde_json::from_str("[3]").unwrap();
         assert!(oops_2_by_1.validate().is_err());
+        // The JSON is the same for any width:
+        let narrow: Matrix<u64> = serde_json::from_str("[3, 3]").unwrap();
+        assert_eq!(narrow.widen::<NFAStateMask>(), ones);
+        assert_eq!(serde_json::to_string(&ones).unwrap(), "[3,3]");
     }
 }
@@ -0,0 +1,307 @@
+//! Fixed-size sets of `NFAState`s, stored as bits. `algebra.rs` builds vectors and matrices on these.
+//! Primitive integers are the fast path; `WideMask` extends the same operations to more states.
+
+use super::{NFAState, NFAStateMask};
+use serde::de::{self, DeserializeOwned, Deserializer, SeqAccess, Visitor};
+use serde::ser::{SerializeTuple, Serializer};
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Debug};
+use std::hash::Hash;
+use std::ops::{BitAnd, BitOr, BitOrAssign};
+
+/// A set of NFA states: state i is included if bit `1<<i` is set.
+pub trait Bits:
+    Copy
+    + Debug
+    + Default
+    + Eq
+    + Hash
+    + Send
+    + Sync
+    + 'static
+    + BitAnd<Output = Self>
+    + BitOr<Output = Self>
+    + BitOrAssign
+    + Serialize
+    + DeserializeOwned
+{
+    /// The number of states the set has room for.
+    const BITS: usize;
+
+    /// The set {i}.
+    fn bit(i: NFAState) -> Self;
+
+    /// Whether the set is empty.
+    fn is_empty(self) -> bool;
+
+    /// Whether the set only contains states `< n`.
+    fn fits(self, n: usize) -> bool;
+
+    /// Remove and return the least state in the set, if any.
+    fn pop_lowest(&mut self) -> Option<NFAState>;
+}
+
+macro_rules! impl_bits_for_int {
+    ($($int:ty),*) => {$(
+        impl Bits for $int {
+            const BITS: usize = <$int>::BITS as usize;
+
+            fn bit(i: NFAState) -> Self {
+                1 << i
+            }
+
+            fn is_empty(self) -> bool {
+                self == 0
+            }
+
+            fn fits(self, n: usize) -> bool {
+                n >= <Self as Bits>::BITS || (self >> n) == 0
+            }
+
+            fn pop_lowest(&mut self) -> Option<NFAState> {
+                if *self == 0 {
+                    None
+                } else {
+                    let lsb = self.trailing_zeros() as NFAState;
+                    *self &= self.wrapping_sub(1);
+                    Some(lsb)
+                }
+            }
+        }
+    )*};
+}
+
+impl_bits_for_int!(u64, u128);
+
+/// A set of up to `64*W` states, as `W` little-endian words.
+/// In human-readable formats (i.e., JSON) it's written as one (big) integer, like the others.
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+pub struct WideMask<const W: usize>([u64; W]);
+
+impl<const W: usize> Default for WideMask<W> {
+    fn default() -> Self {
+        Self([0; W])
+    }
+}
+
+impl<const W: usize> BitAnd for WideMask<W> {
+    type Output = Self;
+    fn bitand(self, rhs: Self) -> Self {
+        Self(std::array::from_fn(|j| self.0[j] & rhs.0[j]))
+    }
+}
+
+impl<const W: usize> BitOr for WideMask<W> {
+    type Output = Self;
+    fn bitor(self, rhs: Self) -> Self {
+        Self(std::array::from_fn(|j| self.0[j] | rhs.0[j]))
+    }
+}
+
+impl<const W: usize> BitOrAssign for WideMask<W> {
+    fn bitor_assign(&mut self, rhs: Self) {
+        *self = *self | rhs;
+    }
+}
+
+impl<const W: usize> Bits for WideMask<W> {
+    const BITS: usize = 64 * W;
+
+    fn bit(i: NFAState) -> Self {
+        let mut words = [0; W];
+        words[i as usize / 64] = 1 << (i % 64);
+        Self(words)
+    }
+
+    fn is_empty(self) -> bool {
+        self.0.iter().all(|&word| word == 0)
+    }
+
+    fn fits(self, n: usize) -> bool {
+        self.0
+            .iter()
+            .enumerate()
+            .all(|(j, &word)| word.fits(n.saturating_sub(64 * j)))
+    }
+
+    fn pop_lowest(&mut self) -> Option<NFAState> {
+        let (j, word) = self.0.iter_mut().enumerate().find(|(_, w)| **w != 0)?;
+        Some((64 * j) as NFAState + word.pop_lowest()?)
+    }
+}
+
+/// The `Bits` types a search may use, from narrowest (fastest) to widest (`NFAStateMask`).
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum MaskWidth {
+    U64,
+    U128,
+    Max,
+}
+
+impl MaskWidth {
+    /// The narrowest width with room for `n` NFA states, if any.
+    pub fn fitting(n: usize) -> Option<MaskWidth> {
+        if n <= <u64 as Bits>::BITS {
+            Some(MaskWidth::U64)
+        } else if n <= <u128 as Bits>::BITS {
+            Some(MaskWidth::U128)
+        } else if n <= NFAStateMask::BITS {
+            Some(MaskWidth::Max)
+        } else {
+            None
+        }
+    }
+}
+
+const TEN_19: u128 = 10_000_000_000_000_000_000;
+
+impl<const W: usize> WideMask<W> {
+    /// The decimal representation of the mask, read as an integer.
+    fn to_decimal(self) -> String {
+        // Repeatedly divide out 19-digit chunks, which are produced least significant first.
+        let (mut words, mut chunks) = (self.0, vec![]);
+        loop {
+            let mut rem = 0u128;
+            for word in words.iter_mut().rev() {
+                let cur = (rem << 64) | *word as u128;
+                (*word, rem) = ((cur / TEN_19) as u64, cur % TEN_19);
+            }
+            chunks.push(rem as u64);
+            if words.iter().all(|&word| word == 0) {
+                break;
+            }
+        }
+        let mut s = chunks.pop().unwrap_or_default().to_string();
+        chunks.iter().rev().for_each(|c| s += &format!("{c:019}"));
+        s
+    }
+
+    /// Parse a decimal integer, if it's valid and in range.
+    fn from_decimal(s: &str) -> Option<Self> {
+        if s.is_empty() {
+            return None;
+        }
+        let mut words = [0u64; W];
+        for digit in s.chars() {
+            let mut carry = digit.to_digit(10)? as u128;
+            for word in words.iter_mut() {
+                let cur = *word as u128 * 10 + carry;
+                (*word, carry) = (cur as u64, cur >> 64);
+            }
+            if carry != 0 {
+                return None;
+            }
+        }
+        Some(Self(words))
+    }
+}
+
+impl<const W: usize> Serialize for WideMask<W> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            let n: serde_json::Number = self
+                .to_decimal()
+                .parse()
+                .map_err(serde::ser::Error::custom)?;
+            n.serialize(serializer)
+        } else {
+            let mut tuple = serializer.serialize_tuple(W)?;
+            self.0
+                .iter()
+                .try_for_each(|word| tuple.serialize_element(word))?;
+            tuple.end()
+        }
+    }
+}
+
+impl<'de, const W: usize> Deserialize<'de> for WideMask<W> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if deserializer.is_human_readable() {
+            let n = serde_json::Number::deserialize(deserializer)?;
+            Self::from_decimal(&n.to_string())
+                .ok_or_else(|| de::Error::custom(format!("invalid {}-bit mask: {}", 64 * W, n)))
+        } else {
+            deserializer.deserialize_tuple(W, WordsVisitor)
+        }
+    }
+}
+
+struct WordsVisitor<const W: usize>;
+
+impl<'de, const W: usize> Visitor<'de> for WordsVisitor<W> {
+    type Value = WideMask<W>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "{} mask words", W)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut words = [0; W];
+        for (j, word) in words.iter_mut().enumerate() {
+            *word = seq
+                .next_element()?
+                .ok_or_else(|| de::Error::invalid_length(j, &self))?;
+        }
+        Ok(WideMask(words))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Wide = WideMask<4>;
+
+    fn set<B: Bits>(states: &[NFAState]) -> B {
+        states.iter().fold(B::default(), |v, &i| v | B::bit(i))
+    }
+
+    fn check_ops<B: Bits>() {
+        let top = (B::BITS - 1) as NFAState;
+        let (mut a, b): (B, B) = (set(&[0, 3, top]), set(&[3, 5]));
+        assert_eq!(a & b, set(&[3]));
+        assert_eq!(a | b, set(&[0, 3, 5, top]));
+        assert!(B::default().is_empty() && !b.is_empty());
+        assert!(b.fits(6) && !b.fits(5) && a.fits(B::BITS) && a.fits(1000));
+        let popped: Vec<NFAState> = std::iter::from_fn(|| a.pop_lowest()).collect();
+        assert_eq!(popped, vec![0, 3, top]);
+        assert!(a.is_empty());
+    }
+
+    #[test]
+    fn test_ops() {
+        check_ops::<u64>();
+        check_ops::<u128>();
+        check_ops::<WideMask<1>>();
+        check_ops::<Wide>();
+    }
+
+    #[test]
+    fn test_widths() {
+        assert_eq!(MaskWidth::fitting(1), Some(MaskWidth::U64));
+        assert_eq!(MaskWidth::fitting(64), Some(MaskWidth::U64));
+        assert_eq!(MaskWidth::fitting(65), Some(MaskWidth::U128));
+        assert_eq!(MaskWidth::fitting(129), Some(MaskWidth::Max));
+        assert_eq!(MaskWidth::fitting(NFAStateMask::BITS + 1), None);
+    }
+
+    #[test]
+    fn test_serialization() {
+        // Written as integers, whatever the width:
+        let small: Wide = set(&[0, 5, 10]);
+        assert_eq!(serde_json::to_string(&small).unwrap(), "1057");
+        assert_eq!(serde_json::from_str::<Wide>("1057").unwrap(), small);
+        let big: Wide = set(&[0, 64, 200, 255]);
+        let json = serde_json::to_string(&big).unwrap();
+        let expected = (1u128 << 64) + 1; // The low 128 bits.
+        assert!(json.len() > 70 && !json.contains('"'));
+        assert_eq!(serde_json::from_str::<Wide>(&json).unwrap(), big);
+        let low: WideMask<2> = serde_json::from_str(&expected.to_string()).unwrap();
+        assert_eq!(low, set(&[0, 64]));
+        assert_eq!(serde_json::to_string(&Wide::default()).unwrap(), "0");
+        // Out-of-range or non-integer values are rejected:
+        assert!(serde_json::from_str::<WideMask<1>>("18446744073709551616").is_err());
+        assert!(serde_json::from_str::<Wide>("-1").is_err());
+        assert!(serde_json::from_str::<Wide>("1.5").is_err());
+    }
+}
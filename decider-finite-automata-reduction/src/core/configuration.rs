@@ -0,0 +1,62 @@
+//! A concrete TM configuration: a finite stretch of tape (0-filled beyond it), head and state.
+
+use super::{Machine, Rule, Side, TMState};
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct Configuration {
+    /// The tape contents, from left to right. Cells beyond either end hold 0.
+    pub tape: Vec<u8>,
+    /// The index in `tape` of the cell under the head.
+    pub head: usize,
+    /// The TM's current state.
+    pub state: TMState,
+}
+
+impl Configuration {
+    /// The initial configuration: state A on an all-0 tape.
+    pub fn start() -> Configuration {
+        Configuration {
+            tape: vec![0],
+            head: 0,
+            state: 0,
+        }
+    }
+
+    /// Run one step of `tm`, extending the tape as needed. Returns false (and stays put) on a halt.
+    pub fn step(&mut self, tm: &Machine) -> bool {
+        let r = self.tape[self.head];
+        let (w, d, t) = match tm.rule(self.state, r) {
+            Rule::Halt { .. } => return false,
+            Rule::Move { w, d, t, .. } => (w, d, t),
+        };
+        self.tape[self.head] = w;
+        self.state = t;
+        match d {
+            Side::L if self.head == 0 => self.tape.insert(0, 0),
+            Side::L => self.head -= 1,
+            Side::R => {
+                self.head += 1;
+                if self.head == self.tape.len() {
+                    self.tape.push(0);
+                }
+            }
+        }
+        true
+    }
+}
+
+impl Display for Configuration {
+    /// The tape's symbols, with the head's state letter and symbol in brackets, e.g. `10[B1]1`.
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        for (i, b) in self.tape.iter().enumerate() {
+            if i == self.head {
+                write!(f, "[{}{}]", (b'A' + self.state) as char, b)?;
+            } else {
+                write!(f, "{}", b)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,304 @@
+//! Explaining a rejected proof with a concrete configuration, so the bug can be reproduced.
+//! `Proof::validate()` checks sufficient conditions on matrices; when one fails, we search for
+//! words witnessing an actual failure of what they're meant to guarantee. That's not always
+//! possible: e.g., an unreachable DFA state may fail the closure conditions harmlessly.
+
+use super::{
+    nfa_start, row, BadProof, Bits, Configuration, DFAState, Machine, Proof, RowVector, Rule, Side,
+    TMState,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::fmt::{Display, Formatter};
+use std::hash::Hash;
+
+/// A configuration (or TM step) where a proof's `TapeAutomaton` gets the wrong answer.
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub enum Counterexample {
+    /// The initial configuration, which the automaton accepts.
+    Start(Configuration),
+    /// A configuration which halts immediately, yet is rejected.
+    Halt(Configuration),
+    /// A configuration the TM reaches from the start, which the automaton accepts.
+    Reached(Configuration),
+    /// A configuration which is rejected, though the one after it (the TM's next step) is accepted.
+    Step {
+        before: Configuration,
+        after: Configuration,
+    },
+    /// The search for one gave up, after visiting `SEARCH_LIMIT` automaton states.
+    /// (There may or may not be one.)
+    Unknown,
+}
+
+/// How many automaton states a search for a counterexample may visit. The NFA's sets of states
+/// can be exponentially many, so it has to stop somewhere.
+pub const SEARCH_LIMIT: usize = 1 << 16;
+
+/// The error from a search which reached its limit.
+#[derive(Debug, PartialEq)]
+struct SearchLimit;
+
+impl Display for Counterexample {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            Counterexample::Start(c) => write!(f, "start configuration {} is accepted", c),
+            Counterexample::Halt(c) => write!(f, "halting configuration {} is rejected", c),
+            Counterexample::Reached(c) => write!(f, "reachable configuration {} is accepted", c),
+            Counterexample::Step { before, after } => {
+                write!(
+                    f,
+                    "{} is rejected, but its successor {} is accepted",
+                    before, after
+                )
+            }
+            Counterexample::Unknown => write!(
+                f,
+                "no counterexample within {} automaton states",
+                SEARCH_LIMIT
+            ),
+        }
+    }
+}
+
+/// The shortest word leading from `start` to a state satisfying `goal`, if any,
+/// visiting at most `limit` states in the search.
+fn shortest_word<S: Copy + Eq + Hash>(
+    start: S,
+    symbols: usize,
+    step: impl Fn(S, u8) -> S,
+    goal: impl Fn(S) -> bool,
+    limit: usize,
+) -> Result<Option<Vec<u8>>, SearchLimit> {
+    let mut parent = HashMap::from([(start, None)]);
+    let mut queue = VecDeque::from([start]);
+    while let Some(s) = queue.pop_front() {
+        if goal(s) {
+            let mut word = vec![];
+            let mut s = s;
+            while let Some((prev, b)) = parent[&s] {
+                word.push(b);
+                s = prev;
+            }
+            word.reverse();
+            return Ok(Some(word));
+        }
+        for b in 0..symbols as u8 {
+            let next = step(s, b);
+            if parent.len() >= limit && !parent.contains_key(&next) {
+                return Err(SearchLimit);
+            }
+            parent.entry(next).or_insert_with(|| {
+                queue.push_back(next);
+                Some((s, b))
+            });
+        }
+    }
+    Ok(None)
+}
+
+impl<B: Bits> Proof<B> {
+    /// A concrete counterexample to this proof for `tm`, given the `error` from `validate()`.
+    /// (`None` if there's none to be found, as with bad data rather than a bad automaton,
+    /// or `Counterexample::Unknown` if the search hit `SEARCH_LIMIT`.)
+    pub fn counterexample(&self, tm: &Machine, error: &BadProof) -> Option<Counterexample> {
+        let a = &self.automaton;
+        let (dfa, nfa, n) = (&a.dfa, &a.nfa, tm.states());
+        let start = |q, f| row(nfa_start(q, f, n));
+        let accepts = |v: RowVector<B>| v * nfa.accepted;
+        let symbols = tm.symbols();
+        match error {
+            BadProof::BadStart => Some(Counterexample::Start(Configuration::start())),
+            BadProof::AcceptedReachable { steps } => {
+                let mut c = Configuration::start();
+                for _ in 0..*steps {
+                    c.step(tm);
+                }
+                Some(Counterexample::Reached(c))
+            }
+            BadProof::NotClosed { q, rule } => {
+                let q = *q;
+                // (The DFA is far smaller than the search limit.)
+                let dfa_step = |q, b| dfa.step(q, b) as DFAState;
+                let u = shortest_word(0, symbols, dfa_step, |p| p == q, SEARCH_LIMIT).ok()??;
+                let cat = |a: &[u8], b: &[u8]| [a, b].concat();
+                match *rule {
+                    Rule::Halt { f, r } => {
+                        let v0 = nfa.step_vec(start(q, f), r);
+                        let nfa_step = |v, b| nfa.step_vec(v, b);
+                        match shortest_word(v0, symbols, nfa_step, |v| !accepts(v), SEARCH_LIMIT) {
+                            Ok(v) => Some(Counterexample::Halt(self.configuration(
+                                &u,
+                                f,
+                                &cat(&[r], &v?),
+                            ))),
+                            Err(SearchLimit) => Some(Counterexample::Unknown),
+                        }
+                    }
+                    Rule::Move { f, r, w, d, t } => {
+                        // Scan-order words such that the TM step is:
+                        //   moving forward:  u [f r] v  ->  u w [t v]
+                        //   moving backward: u b [f r] v  ->  u [t b] w v
+                        // Each case gives the DFA's word and the NFA's states after "[f r]" before
+                        // the step, then the DFA's word, the NFA's word from "[t" on, and the NFA's
+                        // states after that word, after the step. We then search for v.
+                        let cases = if d == a.direction {
+                            let q_w = dfa.step(q, w) as DFAState;
+                            let before = (u.clone(), nfa.step_vec(start(q, f), r));
+                            vec![(before, (cat(&u, &[w]), vec![], start(q_w, t)))]
+                        } else {
+                            (0..symbols as u8)
+                                .map(|b| {
+                                    let q_b = dfa.step(q, b) as DFAState;
+                                    let before = (cat(&u, &[b]), nfa.step_vec(start(q_b, f), r));
+                                    let y = nfa.step_vec(nfa.step_vec(start(q, t), b), w);
+                                    (before, (u.clone(), vec![b, w], y))
+                                })
+                                .collect()
+                        };
+                        let step = |(x, y): (RowVector<B>, RowVector<B>), b| {
+                            (nfa.step_vec(x, b), nfa.step_vec(y, b))
+                        };
+                        let wrong = |(x, y)| !accepts(x) && accepts(y);
+                        let mut gave_up = false;
+                        cases
+                            .into_iter()
+                            .find_map(|((u_before, x), (u_after, w_after, y))| {
+                                let v =
+                                    match shortest_word((x, y), symbols, step, wrong, SEARCH_LIMIT)
+                                    {
+                                        Ok(v) => v?,
+                                        Err(SearchLimit) => {
+                                            gave_up = true;
+                                            return None;
+                                        }
+                                    };
+                                let mut suffix = cat(&w_after, &v);
+                                if suffix.is_empty() {
+                                    suffix.push(0);
+                                }
+                                Some(Counterexample::Step {
+                                    before: self.configuration(&u_before, f, &cat(&[r], &v)),
+                                    after: self.configuration(&u_after, t, &suffix),
+                                })
+                            })
+                            .or_else(|| gave_up.then_some(Counterexample::Unknown))
+                    }
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// The configuration the `TapeAutomaton` scans as `prefix` (DFA), state `f`, then `suffix`
+    /// (NFA, starting with the symbol under the head). Both words are in scan order.
+    fn configuration(&self, prefix: &[u8], f: TMState, suffix: &[u8]) -> Configuration {
+        match self.automaton.direction {
+            Side::R => Configuration {
+                tape: [prefix, suffix].concat(),
+                head: prefix.len(),
+                state: f,
+            },
+            Side::L => {
+                let mut tape = [prefix, suffix].concat();
+                tape.reverse();
+                Configuration {
+                    tape,
+                    head: suffix.len() - 1,
+                    state: f,
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::col;
+    use std::str::FromStr;
+
+    fn accepts(proof: &Proof, tm: &Machine, c: &Configuration) -> bool {
+        proof.automaton.accepts(c, tm.states())
+    }
+
+    #[test]
+    fn test_counterexample() {
+        // The proof for https://bbchallenge.org/12345 from `proof.rs`, which scans leftward.
+        let tm = Machine::from_str("1RB---_0RC---_1RD0RD_0LD1LE_1LC0LB").unwrap();
+        let good: Proof = serde_json::from_str(
+            r#"{
+                "automaton": {
+                    "direction": "L",
+                    "dfa": [[0, 1], [1, 1]],
+                    "nfa": {
+                        "accepted": 1056,
+                        "t": [
+                            [384, 128, 512, 8, 128, 1984, 968, 576, 256, 128, 1024],
+                            [1024, 1024, 8, 512, 2, 1024, 1024, 384, 512, 64, 1024]
+                        ]}},
+                "steady_state": 1024}"#,
+        )
+        .unwrap();
+        let check = |proof: &Proof| {
+            let e = proof.validate(&tm).unwrap_err();
+            let c = proof.counterexample(&tm, &e);
+            match &c {
+                Some(Counterexample::Start(c)) | Some(Counterexample::Reached(c)) => {
+                    assert!(accepts(proof, &tm, c))
+                }
+                Some(Counterexample::Halt(c)) => assert!(!accepts(proof, &tm, c)),
+                Some(Counterexample::Step { before, after }) => {
+                    assert!(!accepts(proof, &tm, before) && accepts(proof, &tm, after))
+                }
+                Some(Counterexample::Unknown) | None => {}
+            }
+            c
+        };
+
+        let mut proof = good.clone();
+        proof.automaton.nfa.accepted |= col(0);
+        proof.automaton.nfa.t[0][0] |= row(0);
+        let start = Configuration::start();
+        assert_eq!(check(&proof), Some(Counterexample::Start(start)));
+
+        let mut proof = good.clone();
+        proof.automaton.nfa.t[1][0] = row(0);
+        let halt = check(&proof).expect("a rejected halting configuration");
+        assert_eq!(halt.to_string(), "halting configuration [A1] is rejected");
+
+        let mut proof = good.clone();
+        proof.automaton.nfa.t[0][4] = row(0);
+        let step = check(&proof).expect("a bad TM step");
+        let msg = "10[E0] is rejected, but its successor 1[C0]1 is accepted";
+        assert_eq!(step.to_string(), msg);
+
+        let mut proof = good;
+        proof.automaton.nfa.t[0][1] |= row(0);
+        let step = check(&proof).expect("a bad TM step");
+        let before = Configuration {
+            tape: vec![0, 0],
+            head: 0,
+            state: 0,
+        };
+        let after = Configuration {
+            tape: vec![1, 0],
+            head: 1,
+            state: 1,
+        };
+        assert_eq!(step, Counterexample::Step { before, after });
+    }
+
+    #[test]
+    fn test_search_limit() {
+        // Binary words, as numbers with a leading 1: finding k bits visits ~2^(k+1) states.
+        let step = |s: u32, b| 2 * s + b as u32;
+        let goal = |s| s == 0b1011;
+        assert_eq!(shortest_word(1, 2, step, goal, 32), Ok(Some(vec![0, 1, 1])));
+        assert_eq!(shortest_word(1, 2, step, goal, 8), Err(SearchLimit));
+        // An exhaustive search that finds nothing is conclusive.
+        assert_eq!(shortest_word(1, 2, |s, _| s, goal, 8), Ok(None));
+        let msg = "no counterexample within 65536 automaton states";
+        assert_eq!(Counterexample::Unknown.to_string(), msg);
+    }
+}
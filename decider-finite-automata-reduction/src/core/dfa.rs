@@ -3,16 +3,20 @@ use crate::core::NFAState;
 use serde::{Deserialize, Serialize};
 
 /// A Deterministic Finite Automaton, with states indexed by ``DFAState`s and initial state 0.
+/// The transition table `t[q][b]` has a column for each symbol `b` of the (TM tape) alphabet.
 #[derive(Serialize, Deserialize, Clone, Debug)]
 #[serde(transparent)]
 pub struct DFA {
-    pub t: Vec<[DFAState; 2]>,
+    pub t: Vec<Vec<DFAState>>,
 }
 
 impl DFA {
-    /// A DFA with n states (initialized with all transitions leading to the initial state).
-    pub fn new(n: usize) -> DFA {
-        DFA { t: vec![[0, 0]; n] }
+    /// A DFA with n states, reading the given number of symbols
+    /// (initialized with all transitions leading to the initial state).
+    pub fn new(n: usize, symbols: usize) -> DFA {
+        DFA {
+            t: vec![vec![0; symbols]; n],
+        }
     }
 
     /// The number of states.
@@ -20,6 +24,11 @@ impl DFA {
         self.t.len()
     }
 
+    /// The number of symbols in the alphabet.
+    pub fn symbols(&self) -> usize {
+        self.t.first().map_or(0, Vec::len)
+    }
+
     /// The outcome of a single step.
     pub fn step(&self, q: DFAState, b: u8) -> NFAState {
         self.t[q as usize][b as usize]
@@ -29,6 +38,8 @@ impl DFA {
     pub fn validate(&self) -> ProofResult<()> {
         if self.t.is_empty() {
             Err(BadProof::BadDFASize)
+        } else if self.symbols() == 0 || self.t.iter().any(|row| row.len() != self.symbols()) {
+            Err(BadProof::BadDimensions)
         } else if self.t.iter().flatten().any(|&v| (v as usize) >= self.len()) {
             Err(BadProof::BadDFATransition)
         } else {
@@ -52,14 +63,20 @@ mod tests {
 
     #[test]
     fn test_validation() {
-        let dfa = DFA::new(1);
+        let dfa = DFA::new(1, 2);
         assert_eq!(dfa.validate(), Ok(()));
+        let dfa: DFA = serde_json::from_str("[[0, 1, 1], [1, 0, 0]]").unwrap();
+        assert_eq!((dfa.symbols(), dfa.validate()), (3, Ok(())));
         let dfa: DFA = serde_json::from_str("[[0, 1], [0, 2], [0, 0]]").unwrap();
         assert_eq!(dfa.validate(), Ok(()));
         let dfa: DFA = serde_json::from_str("[]").unwrap();
         assert_eq!(dfa.validate(), Err(BadProof::BadDFASize));
         let dfa: DFA = serde_json::from_str("[[0, 42]]").unwrap();
         assert_eq!(dfa.validate(), Err(BadProof::BadDFATransition));
+        let dfa: DFA = serde_json::from_str("[[0, 1], [0]]").unwrap();
+        assert_eq!(dfa.validate(), Err(BadProof::BadDimensions));
+        let dfa: DFA = serde_json::from_str("[[]]").unwrap();
+        assert_eq!(dfa.validate(), Err(BadProof::BadDimensions));
     }
 
     #[test]
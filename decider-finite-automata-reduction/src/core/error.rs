@@ -15,6 +15,8 @@ pub enum BadProof {
     BadDFASize,
     #[error("NFA too small to address the states nfa_start(q, f)")]
     BadNFASize,
+    #[error("DFA or NFA has more states than the limits (max_dfa(), MAX_NFA) allow")]
+    TooLarge,
     #[error("DFA transitions went out of bounds")]
     BadDFATransition,
     #[error("DFA failed to ignore leading zeros")]
@@ -29,6 +31,8 @@ pub enum BadProof {
     BadSteadyState,
     #[error("NFA didn't accept 'steady_state")]
     RejectedSteadyState,
+    #[error("tape automaton accepted the configuration reached after {steps} TM steps")]
+    AcceptedReachable { steps: usize },
 }
 
 pub type ProofResult<T> = Result<T, BadProof>;
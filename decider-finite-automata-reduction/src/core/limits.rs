@@ -1,11 +1,14 @@
 //! Define up the hard, compile-time limits on our `BB(_)` problem and search space.
+//! (The TM's numbers of states and symbols are only known at runtime, up to the limits here.)
 
-/// The exact number of states we expect from Turing Machines.
-pub const TM_STATES: usize = 5;
-/// The maximum number of states in a Proof's DFA.
-pub const MAX_DFA: usize = if cfg!(feature = "u128") { 25 } else { 12 };
-/// The maximum number of states in a Proof's NFA.
-pub const MAX_NFA: usize = if cfg!(feature = "u128") { 128 } else { 64 };
+use super::WideMask;
+
+/// The maximum number of states we accept in Turing Machines. (The text format names them A-Y.)
+pub const MAX_TM_STATES: usize = 25;
+/// The maximum number of symbols we accept in Turing Machines. (The text format uses digits.)
+pub const MAX_SYMBOLS: usize = 10;
+/// The maximum number of states in a Proof's NFA. (Searches use narrower masks when they can.)
+pub const MAX_NFA: usize = 256;
 
 /// A number indexing a TM state.
 pub type TMState = u8;
@@ -13,23 +16,32 @@ pub type TMState = u8;
 pub type DFAState = u8;
 /// A number indexing an NFA state.
 pub type NFAState = u8;
-/// A number indexing a set of NFA states: state i is included if bit `1<<i` is set.
-#[cfg(feature = "u128")]
-pub type NFAStateMask = u128;
-/// A number indexing a set of NFA states: state i is included if bit `1<<i` is set.
-#[cfg(not(feature = "u128"))]
-pub type NFAStateMask = u64;
+/// A set of NFA states with room for all `MAX_NFA`, used to represent finished `Proof`s.
+pub type NFAStateMask = WideMask<{ MAX_NFA / 64 }>;
+
+/// The maximum number of states in a Proof's DFA, for a TM with `tm_states` states.
+/// (The NFA needs room for the states `nfa_start(q, f)`, plus one more as the provers use it.)
+pub const fn max_dfa(tm_states: usize) -> usize {
+    (MAX_NFA - 1) / tm_states
+}
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::core::Bits;
 
     #[test]
     fn test_consistency() {
-        assert!(TMState::MAX as usize + 1 >= TM_STATES);
-        assert!(DFAState::MAX as usize + 1 >= MAX_DFA);
+        assert!(TMState::MAX as usize + 1 >= MAX_TM_STATES);
+        assert!(DFAState::MAX as usize + 1 >= max_dfa(1));
         assert!(NFAState::MAX as usize + 1 >= MAX_NFA);
-        assert!(NFAStateMask::BITS as usize >= MAX_NFA);
-        assert!(MAX_DFA * TM_STATES <= MAX_NFA, "nfa_start values won't fit");
+        const { assert!(NFAStateMask::BITS >= MAX_NFA) };
+        for tm_states in 1..=MAX_TM_STATES {
+            assert!(
+                max_dfa(tm_states) * tm_states < MAX_NFA,
+                "nfa_start values won't fit"
+            );
+        }
+        assert_eq!(max_dfa(5), 51);
     }
 }
@@ -1,16 +1,15 @@
 //! A Turing Machine definition which matches the bbchallenge.org database format.
 
-use super::TM_STATES;
-use crate::core::TMState;
+use crate::core::{TMState, MAX_SYMBOLS, MAX_TM_STATES};
 use serde::{Deserialize, Serialize};
 use serde_with::{DeserializeFromStr, SerializeDisplay};
 use std::fmt::{Display, Formatter};
 use std::str::FromStr;
 use thiserror::Error;
-use zerocopy::{AsBytes, FromBytes};
+use zerocopy::{AsBytes, FromBytes, LayoutVerified, Unaligned};
 
 /// A low-level transition, as in https://bbchallenge.org/method#format
-#[derive(AsBytes, FromBytes, Clone, Debug, Default, Eq, PartialEq)]
+#[derive(AsBytes, FromBytes, Unaligned, Clone, Debug, Default, Eq, Hash, PartialEq)]
 #[repr(C)]
 struct Trans {
     bit: u8,
@@ -22,14 +21,16 @@ struct Trans {
 #[error("use https://discuss.bbchallenge.org/t/standard-tm-text-format")]
 pub struct BadTMText;
 
-impl FromStr for Trans {
-    type Err = BadTMText;
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
+impl Trans {
+    /// Parse a transition of a TM with the given numbers of states and symbols.
+    fn parse(s: &str, states: usize, symbols: usize) -> Result<Self, BadTMText> {
         let mut chars = s.chars();
         let bit = match chars.next() {
-            Some('-') | Some('0') => 0u8,
-            Some('1') => 1u8,
+            Some('-') => 0u8,
+            Some(c) => match c.to_digit(symbols as u32) {
+                Some(digit) => digit as u8,
+                None => return Err(BadTMText {}),
+            },
             _ => return Err(BadTMText {}),
         };
         let dir = match chars.next() {
@@ -37,7 +38,7 @@ impl FromStr for Trans {
             Some('L') => 1u8,
             _ => return Err(BadTMText {}),
         };
-        let new = match chars.next().and_then(|c| c.to_digit(10 + TM_STATES as u32)) {
+        let new = match chars.next().and_then(|c| c.to_digit(10 + states as u32)) {
             Some(b36) if b36 > 9 => (b36 - 9) as u8,
             _ => 0u8,
         };
@@ -64,12 +65,41 @@ impl Display for Trans {
 }
 
 /// A Turing machine definition, as in https://bbchallenge.org/method#format
-#[derive(
-    AsBytes, FromBytes, Clone, SerializeDisplay, DeserializeFromStr, Debug, Default, Eq, PartialEq,
-)]
-#[repr(C)]
+/// The numbers of states and symbols are arbitrary (up to `MAX_TM_STATES` and `MAX_SYMBOLS`).
+#[derive(Clone, SerializeDisplay, DeserializeFromStr, Debug, Eq, Hash, PartialEq)]
 pub struct Machine {
-    code: [[Trans; 2]; TM_STATES],
+    /// The number of tape symbols.
+    symbols: usize,
+    /// The transition for reading symbol `r` in state `f` is at index `f * symbols + r`.
+    code: Vec<Trans>,
+}
+
+/// A TM borrowed from its (seed database) binary format, e.g. a record in a memory-mapped DB.
+/// It's decoded and validated without copying; `to_machine()` makes an owned `Machine`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct MachineRef<'a> {
+    symbols: usize,
+    code: &'a [Trans],
+}
+
+impl Default for Machine {
+    /// A TM with no states, over a binary alphabet.
+    fn default() -> Self {
+        Machine {
+            symbols: 2,
+            code: vec![],
+        }
+    }
+}
+
+/// How a TM halted, when run from the initial configuration.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Halted {
+    /// The number of steps, counting the halting step.
+    pub steps: u64,
+    /// The number of nonzero symbols left on the tape, with the halting step writing a 1 if it read
+    /// a 0. (This is the usual convention, i.e., halting transitions like `1RZ`.)
+    pub sigma: u64,
 }
 
 /// Left or right.
@@ -79,6 +109,16 @@ pub enum Side {
     R,
 }
 
+impl Side {
+    /// The other side.
+    pub fn mirror(self) -> Side {
+        match self {
+            Side::L => Side::R,
+            Side::R => Side::L,
+        }
+    }
+}
+
 impl Display for Side {
     fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
         write!(f, "{:?}", self)
@@ -108,18 +148,167 @@ impl Display for Rule {
 }
 
 impl Machine {
+    /// Decode a TM from its (seed database) binary format, given the number of symbols.
+    pub fn from_bytes(bytes: &[u8], symbols: usize) -> Option<Machine> {
+        MachineRef::from_bytes(bytes, symbols).map(|tm| tm.to_machine())
+    }
+
+    /// A borrowed view of this TM.
+    pub fn view(&self) -> MachineRef<'_> {
+        MachineRef {
+            symbols: self.symbols,
+            code: &self.code,
+        }
+    }
+
+    /// The (seed database) binary format.
+    pub fn as_bytes(&self) -> &[u8] {
+        self.code.as_bytes()
+    }
+
+    /// The number of states.
+    pub fn states(&self) -> usize {
+        self.code.len() / self.symbols
+    }
+
+    /// The number of tape symbols.
+    pub fn symbols(&self) -> usize {
+        self.symbols
+    }
+
     pub fn rules(&self) -> impl Iterator<Item = Rule> + '_ {
-        self.code.iter().flatten().enumerate().map(|(fr, trans)| {
-            let (f, r) = ((fr / 2) as TMState, (fr % 2) as u8);
+        (0..self.code.len())
+            .map(|fr| self.rule((fr / self.symbols) as TMState, (fr % self.symbols) as u8))
+    }
+
+    /// The rule for reading symbol `r` in state `f`.
+    pub fn rule(&self, f: TMState, r: u8) -> Rule {
+        let trans = &self.code[f as usize * self.symbols + r as usize];
+        if trans.new == 0 {
+            Rule::Halt { f, r }
+        } else {
+            let w = trans.bit;
+            let d = if trans.dir == 0 { Side::R } else { Side::L };
+            let t = trans.new - 1;
+            Rule::Move { f, r, w, d, t }
+        }
+    }
+
+    /// The same TM with left and right swapped.
+    pub fn mirror(&self) -> Machine {
+        let code = (self.code.iter())
+            .map(|trans| match trans.new {
+                0 => Trans::default(),
+                _ => Trans {
+                    dir: 1 - trans.dir,
+                    ..trans.clone()
+                },
+            })
+            .collect();
+        Machine {
+            symbols: self.symbols,
+            code,
+        }
+    }
+
+    /// The same TM with each state `f` renamed to `states[f]`. (`states` must be a permutation.)
+    pub fn relabel(&self, states: &[TMState]) -> Machine {
+        let mut code = vec![Trans::default(); self.code.len()];
+        for (fr, trans) in self.code.iter().enumerate() {
+            let (f, r) = (fr / self.symbols, fr % self.symbols);
+            if trans.new != 0 {
+                code[states[f] as usize * self.symbols + r] = Trans {
+                    new: states[trans.new as usize - 1] + 1,
+                    ..trans.clone()
+                };
+            }
+        }
+        Machine {
+            symbols: self.symbols,
+            code,
+        }
+    }
+
+    /// Run the TM for up to `max_steps` steps, as `MachineRef::simulate()` does.
+    pub fn simulate(&self, max_steps: u64) -> Option<Halted> {
+        self.view().simulate(max_steps)
+    }
+
+    fn is_valid(&self) -> bool {
+        self.view().is_valid()
+    }
+}
+
+impl<'a> MachineRef<'a> {
+    /// View a TM in its (seed database) binary format, given the number of symbols.
+    pub fn from_bytes(bytes: &'a [u8], symbols: usize) -> Option<MachineRef<'a>> {
+        let code = LayoutVerified::<_, [Trans]>::new_slice_unaligned(bytes)?.into_slice();
+        let machine = MachineRef { symbols, code };
+        machine.is_valid().then_some(machine)
+    }
+
+    /// The (seed database) binary format.
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.code.as_bytes()
+    }
+
+    /// The number of states.
+    pub fn states(&self) -> usize {
+        self.code.len() / self.symbols
+    }
+
+    /// The number of tape symbols.
+    pub fn symbols(&self) -> usize {
+        self.symbols
+    }
+
+    /// An owned copy of the TM.
+    pub fn to_machine(&self) -> Machine {
+        Machine {
+            symbols: self.symbols,
+            code: self.code.to_vec(),
+        }
+    }
+
+    /// Run the TM from the initial configuration, for up to `max_steps` steps. If it halts, say how.
+    pub fn simulate(&self, max_steps: u64) -> Option<Halted> {
+        let mut tape = vec![0u8; 256];
+        let (mut head, mut state) = (tape.len() / 2, 0usize);
+        for step in 1..=max_steps {
+            let trans = &self.code[state * self.symbols + tape[head] as usize];
             if trans.new == 0 {
-                Rule::Halt { f, r }
+                tape[head] = tape[head].max(1);
+                let sigma = tape.iter().filter(|&&b| b != 0).count() as u64;
+                return Some(Halted { steps: step, sigma });
+            }
+            tape[head] = trans.bit;
+            state = trans.new as usize - 1;
+            if trans.dir == 0 {
+                head += 1;
+                if head == tape.len() {
+                    tape.resize(2 * head, 0);
+                }
             } else {
-                let w = trans.bit;
-                let d = if trans.dir == 0 { Side::R } else { Side::L };
-                let t = trans.new - 1;
-                Rule::Move { f, r, w, d, t }
+                if head == 0 {
+                    // Double the tape by prepending 0s.
+                    head = tape.len();
+                    tape.splice(0..0, std::iter::repeat_n(0, head));
+                }
+                head -= 1;
             }
-        })
+        }
+        None
+    }
+
+    /// Check the dimensions are supported and the transitions are in bounds.
+    fn is_valid(&self) -> bool {
+        let (states, symbols) = (self.states(), self.symbols);
+        (1..=MAX_TM_STATES).contains(&states)
+            && (2..=MAX_SYMBOLS).contains(&symbols)
+            && self.code.len() == states * symbols
+            && self.code.iter().all(|trans| {
+                (trans.bit as usize) < symbols && trans.dir < 2 && (trans.new as usize) <= states
+            })
     }
 }
 
@@ -127,24 +316,39 @@ impl FromStr for Machine {
     type Err = BadTMText;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut tm = Machine::default();
-        for i in 0..TM_STATES {
-            for b in 0..2 {
-                if let Some(t) = s.get(7 * i + 3 * b..7 * i + 3 * (b + 1)) {
-                    tm.code[i][b] = Trans::from_str(t)?;
-                } else {
-                    return Err(BadTMText {});
-                }
-            }
+        let groups: Vec<&str> = s.split('_').collect();
+        let (states, symbols) = (groups.len(), groups[0].len() / 3);
+        if states > MAX_TM_STATES
+            || !(2..=MAX_SYMBOLS).contains(&symbols)
+            || groups.iter().any(|g| g.len() != 3 * symbols)
+        {
+            return Err(BadTMText {});
         }
-        Ok(tm)
+        let code = groups
+            .iter()
+            .flat_map(|g| (0..symbols).map(move |b| g.get(3 * b..3 * (b + 1))))
+            .map(|t| Trans::parse(t.ok_or(BadTMText {})?, states, symbols))
+            .collect::<Result<_, _>>()?;
+        let tm = Machine { symbols, code };
+        tm.is_valid().then_some(tm).ok_or(BadTMText {})
     }
 }
 
 impl Display for Machine {
     fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
-        for (i, t01) in self.code.iter().enumerate() {
-            write!(f, "{}{}{}", if i == 0 { "" } else { "_" }, t01[0], t01[1])?;
+        self.view().fmt(f)
+    }
+}
+
+impl Display for MachineRef<'_> {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        for (i, trans) in self.code.iter().enumerate() {
+            let sep = if i > 0 && i % self.symbols == 0 {
+                "_"
+            } else {
+                ""
+            };
+            write!(f, "{}{}", sep, trans)?;
         }
         Ok(())
     }
@@ -161,7 +365,7 @@ mod tests {
         let champ = Machine::from_str("1RB1LC_1RC1RB_1RD0LE_1LA1LD_---0LA").unwrap();
         let champ_rules: Vec<Rule> = champ.rules().collect();
         assert_eq!(champ_bin, champ.as_bytes());
-        let tm = Machine::read_from(champ_bin as &[u8]);
+        let tm = Machine::from_bytes(champ_bin as &[u8], 2);
         assert_eq!(tm, Some(champ));
         let (f, r, w, d, t) = (0, 0, 1, Side::R, 1);
         assert_eq!(champ_rules[0], Rule::Move { f, r, w, d, t });
@@ -172,4 +376,56 @@ mod tests {
             Machine::from_str("1RB1LC_1RC1RB_1RD0LE_1LA1LD_0RZ0LA").unwrap()
         )
     }
+
+    #[test]
+    fn test_dimensions() {
+        // The BB(2, 3) champion:
+        let text = "1RB2LB---_2LA2RB1LB";
+        let tm = Machine::from_str(text).unwrap();
+        assert_eq!((tm.states(), tm.symbols()), (2, 3));
+        assert_eq!(tm.to_string(), text);
+        assert_eq!(tm.as_bytes().len(), 18);
+        assert_eq!(Machine::from_bytes(tm.as_bytes(), 3), Some(tm.clone()));
+        let view = MachineRef::from_bytes(tm.as_bytes(), 3).unwrap();
+        assert_eq!((view.states(), view.to_string()), (2, String::from(text)));
+        assert_eq!(view.as_bytes().as_ptr(), tm.as_bytes().as_ptr());
+        assert_eq!(view.to_machine(), tm);
+        let rules: Vec<Rule> = tm.rules().collect();
+        let (f, r, w, d, t) = (1, 2, 1, Side::L, 1);
+        assert_eq!(rules[5], Rule::Move { f, r, w, d, t });
+        assert_eq!(rules[2], Rule::Halt { f: 0, r: 2 });
+        // Inconsistent dimensions, or out-of-range symbols/states:
+        assert!(Machine::from_str("1RB1LC_1RC").is_err());
+        assert!(Machine::from_str("1RB2LA_1LA---").is_err());
+        assert!(Machine::from_str("1RB_1LA").is_err());
+        assert!(Machine::from_bytes(tm.as_bytes(), 2).is_none());
+        assert!(Machine::from_bytes(b"\x01\x00\x03\x00\x00\x00", 2).is_none());
+    }
+
+    #[test]
+    fn test_simulate() {
+        // Busy Beaver champions: https://bbchallenge.org/story#what-is-known-about-bb
+        let bb2 = Machine::from_str("1RB1LB_1LA---").unwrap();
+        assert_eq!(bb2.simulate(6), Some(Halted { steps: 6, sigma: 4 }));
+        assert_eq!(bb2.simulate(5), None);
+        let bb4 = Machine::from_str("1RB1LB_1LA0LC_---1LD_1RD0RA").unwrap();
+        assert_eq!(
+            bb4.simulate(1000),
+            Some(Halted {
+                steps: 107,
+                sigma: 13
+            })
+        );
+        let bb2_3 = Machine::from_str("1RB2LB---_2LA2RB1LB").unwrap();
+        assert_eq!(
+            bb2_3.simulate(1000),
+            Some(Halted {
+                steps: 38,
+                sigma: 9
+            })
+        );
+        // A TM which runs off to the left forever, and one which never leaves state A.
+        assert_eq!(Machine::from_str("1LA---").unwrap().simulate(10000), None);
+        assert_eq!(Machine::from_str("0RA---").unwrap().simulate(10000), None);
+    }
 }
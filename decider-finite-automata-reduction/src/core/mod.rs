@@ -6,17 +6,27 @@
 //! `[1]` https://bbchallenge.org/method#reproducibility-and-verifiability-statement
 
 mod algebra;
+mod bits;
+mod configuration;
+mod counterexample;
 mod dfa;
 mod error;
 mod limits;
 mod machine;
 mod nfa;
 mod proof;
+mod symmetry;
 
 pub use algebra::{col, row, ColVector, Matrix, RowVector};
+pub use bits::{Bits, MaskWidth, WideMask};
+pub use configuration::Configuration;
+pub use counterexample::Counterexample;
 pub use dfa::DFA;
 pub use error::{BadProof, ProofResult};
-pub use limits::{DFAState, NFAState, NFAStateMask, TMState, MAX_DFA, MAX_NFA, TM_STATES};
-pub use machine::{Machine, Rule, Side};
+pub use limits::{
+    max_dfa, DFAState, NFAState, NFAStateMask, TMState, MAX_NFA, MAX_SYMBOLS, MAX_TM_STATES,
+};
+pub use machine::{Halted, Machine, MachineRef, Rule, Side};
 pub use nfa::NFA;
 pub use proof::{nfa_start, Proof, TapeAutomaton};
+pub use symmetry::Symmetry;
@@ -1,45 +1,62 @@
-use super::{row, BadProof, ColVector, Matrix, NFAState, ProofResult, RowVector};
+use super::{
+    row, BadProof, Bits, ColVector, Matrix, NFAState, NFAStateMask, ProofResult, RowVector,
+};
 use serde::{Deserialize, Serialize};
 
 /// A Nondeterministic Finite Automaton, with states indexed by `NFAState`s.
 /// During operation, we can track the `RowVector` of possible `NFAState`s it could have.
 /// Transition matrices act on these `RowVector`s by right-multiplication.
 /// Testing a `RowVector` for acceptance is also matrix multiplication.
+/// There is a transition matrix `t[b]` for each symbol `b` of the (TM tape) alphabet.
 /// Reference: https://planetmath.org/matrixcharacterizationsofautomata
 #[derive(Serialize, Deserialize, Clone, Debug)]
-pub struct NFA {
-    pub t: [Matrix; 2],
-    pub accepted: ColVector,
+pub struct NFA<B = NFAStateMask> {
+    pub t: Vec<Matrix<B>>,
+    pub accepted: ColVector<B>,
 }
 
-impl NFA {
-    /// An NFA with n states (initialized empty transitions and acceptance).
-    pub fn new(states: usize) -> NFA {
+impl<B: Bits> NFA<B> {
+    /// An NFA with n states, reading the given number of symbols
+    /// (initialized empty transitions and acceptance).
+    pub fn new(states: usize, symbols: usize) -> NFA<B> {
         NFA {
-            t: [Matrix::new(states), Matrix::new(states)],
+            t: vec![Matrix::new(states); symbols],
             accepted: ColVector::new(),
         }
     }
 
     /// The number of states.
     pub fn len(&self) -> usize {
-        self.t[0].len()
+        self.t.first().map_or(0, Matrix::len)
+    }
+
+    /// The number of symbols in the alphabet.
+    pub fn symbols(&self) -> usize {
+        self.t.len()
+    }
+
+    /// The same NFA, stored using another `Bits` type (which must have room for its states).
+    pub fn widen<C: Bits>(&self) -> NFA<C> {
+        NFA {
+            t: self.t.iter().map(Matrix::widen).collect(),
+            accepted: self.accepted.widen(),
+        }
     }
 
     /// The outcomes of a single step, from a precise state.
-    pub fn step(&self, q: NFAState, b: u8) -> RowVector {
+    pub fn step(&self, q: NFAState, b: u8) -> RowVector<B> {
         self.step_vec(row(q), b)
     }
 
     /// The outcomes of a single step, from any state in a vector.
-    pub fn step_vec(&self, v: RowVector, b: u8) -> RowVector {
+    pub fn step_vec(&self, v: RowVector<B>, b: u8) -> RowVector<B> {
         v * &self.t[b as usize]
     }
 
     /// Ensure the data define a valid NFA.
     pub fn validate(&self) -> ProofResult<()> {
         self.accepted.validate(self.len())?;
-        if self.t[1].len() != self.len() {
+        if self.t.is_empty() || self.t.iter().any(|m| m.len() != self.len()) {
             Err(BadProof::BadDimensions)
         } else {
             self.t.iter().try_for_each(|m| m.validate())
@@ -57,13 +74,13 @@ impl NFA {
 
     /// Check the assumption that `v` is an "accepted steady state", meaning:
     /// - if the NFA currently reaches all states in `v` (`state >= v`), this remains true after
-    ///   either transition (`state * &self.t[b] >= v` for each bit `b`).
+    ///   any transition (`state * &self.t[b] >= v` for each symbol `b`).
     /// - in a state where all of `v` is reachable, the NFA will accept.
-    pub fn check_accepted_steady_state(&self, v: RowVector) -> ProofResult<()> {
+    pub fn check_accepted_steady_state(&self, v: RowVector<B>) -> ProofResult<()> {
         v.validate(self.len())?;
         if !(v * self.accepted) {
             Err(BadProof::RejectedSteadyState)
-        } else if !(v * &self.t[0] >= v && v * &self.t[1] >= v) {
+        } else if !self.t.iter().all(|m| v * m >= v) {
             Err(BadProof::BadSteadyState)
         } else {
             Ok(())
@@ -78,9 +95,13 @@ mod tests {
 
     #[test]
     fn test_validation() {
-        let nfa = NFA::new(1);
-        assert_eq!(nfa.len(), 1);
+        let nfa: NFA = NFA::new(1, 2);
+        assert_eq!((nfa.len(), nfa.symbols()), (1, 2));
         assert_eq!(nfa.validate(), Ok(()));
+        let nfa: NFA = serde_json::from_str(r#"{"t":[[1],[0],[1]],"accepted":0}"#).unwrap();
+        assert_eq!((nfa.symbols(), nfa.validate()), (3, Ok(())));
+        let nfa: NFA = serde_json::from_str(r#"{"t":[],"accepted":0}"#).unwrap();
+        assert_eq!(nfa.validate(), Err(BadProof::BadDimensions));
         let nfa: NFA = serde_json::from_str(r#"{"t":[[0],[0]],"accepted":0}"#).unwrap();
         assert_eq!(nfa.validate(), Ok(()));
         let nfa: NFA = serde_json::from_str(r#"{"t":[[0],[0,0]],"accepted":0}"#).unwrap();
@@ -1,12 +1,12 @@
 use super::{
-    row, BadProof, DFAState, Machine, NFAState, ProofResult, RowVector, Rule, Side, TMState, DFA,
-    NFA, TM_STATES,
+    max_dfa, row, BadProof, Bits, Configuration, DFAState, Machine, MaskWidth, NFAState,
+    NFAStateMask, ProofResult, RowVector, Rule, Side, TMState, DFA, MAX_NFA, NFA,
 };
 use serde::{Deserialize, Serialize};
 
 /// An automaton for recognizing a subset of TM tape+head configurations, operating as follows:
 #[derive(Serialize, Deserialize, Clone, Debug)]
-pub struct TapeAutomaton {
+pub struct TapeAutomaton<B = NFAStateMask> {
     /// It scans the tape from end to the end, moving in "direction". (More on "ends" in a moment.)
     /// The data type and interpretation of "direction" correspond to machine::Rule.
     pub direction: Side,
@@ -15,20 +15,21 @@ pub struct TapeAutomaton {
     /// To ensure the post-scan state is well-defined, we require the DFA to ignore leading zeros.
     pub dfa: DFA,
     /// Phase 2: from any `DFAState` q", if it sees the symbol for TMState "f", it transitions
-    /// to a unique corresponding NFA state given by the function `nfa_start(q, f)`.
+    /// to a unique corresponding NFA state given by the function `nfa_start(q, f, tm_states)`.
     /// The scan proceeds non-deterministically, from the bit under the head out to the other "end".
     /// We similarly require that trailing zeros not affect the NFA's acceptance status.
-    pub nfa: NFA,
+    pub nfa: NFA<B>,
 }
 
 /// The NFA state in which the `TapeAutomaton` starts phase 2, if it sees "f" in DFA state "q".
-pub fn nfa_start(q: DFAState, f: TMState) -> NFAState {
-    (q as usize * TM_STATES + f as usize) as NFAState
+/// (The numbering depends on the number of states in the TM, `tm_states`.)
+pub fn nfa_start(q: DFAState, f: TMState, tm_states: usize) -> NFAState {
+    (q as usize * tm_states + f as usize) as NFAState
 }
 
-impl TapeAutomaton {
+impl<B: Bits> TapeAutomaton<B> {
     /// A `TapeAutomaton` definition (yet to be validated).
-    pub fn new(direction: Side, dfa: DFA, nfa: NFA) -> TapeAutomaton {
+    pub fn new(direction: Side, dfa: DFA, nfa: NFA<B>) -> TapeAutomaton<B> {
         TapeAutomaton {
             direction,
             dfa,
@@ -36,79 +37,145 @@ impl TapeAutomaton {
         }
     }
 
-    /// Ensure the `TapeAutomaton` satisfies the invariants described in the class doc comments.
-    pub fn validate(&self) -> ProofResult<()> {
+    /// Ensure the `TapeAutomaton` satisfies the invariants described in the class doc comments,
+    /// for scanning the tapes of a TM with `tm_states` states.
+    pub fn validate(&self, tm_states: usize) -> ProofResult<()> {
+        // State numbers must fit their types, and masks their states: check before anything else.
+        if tm_states == 0 || self.dfa.len() > max_dfa(tm_states) || self.nfa.len() > MAX_NFA {
+            return Err(BadProof::TooLarge);
+        }
         self.dfa.validate()?;
         self.dfa.check_leading_zeros()?;
         self.nfa.validate()?;
         self.nfa.check_trailing_zeros()?;
-        if self.nfa.len() < TM_STATES * self.dfa.len() {
+        if self.nfa.symbols() != self.dfa.symbols() {
+            Err(BadProof::BadDimensions)
+        } else if self.nfa.len() < tm_states * self.dfa.len() {
             Err(BadProof::BadNFASize)
         } else {
             Ok(())
         }
     }
+
+    /// Whether the automaton accepts the configuration `c` of a TM with `tm_states` states,
+    /// scanning as described above. (The automaton should be valid, and `c.head` on the tape.)
+    pub fn accepts(&self, c: &Configuration, tm_states: usize) -> bool {
+        let mut tape = c.tape.clone();
+        let mut head = c.head;
+        if self.direction == Side::L {
+            tape.reverse();
+            head = tape.len() - 1 - head;
+        }
+        let q = (tape[..head].iter()).fold(0, |q, &b| self.dfa.step(q, b) as DFAState);
+        let v = row(nfa_start(q, c.state, tm_states));
+        let v = (tape[head..].iter()).fold(v, |v, &b| self.nfa.step_vec(v, b));
+        v * self.nfa.accepted
+    }
 }
 
 /// A certificate that a Turing Machine runs forever from its initial configuration.
 #[derive(Serialize, Deserialize, Clone, Debug)]
-pub struct Proof {
+pub struct Proof<B = NFAStateMask> {
     /// A TapeAutomaton with the property that, if it accepts a configuration after a TM step,
     /// it accepts the preceding configuration as well.
     /// Furthermore, we require that it rejects the initial TM configuration.
-    pub automaton: TapeAutomaton,
+    pub automaton: TapeAutomaton<B>,
     /// To ensure the TapeAutomaton accepts each immediately-halting configurations, we require:
     /// whenever phase 2 starts with the head state-symbol and bit from a halt rule, the NFA must
     /// reach the following accepted steady state. (cf. `NFA::check_accepted_steady_state()`.)
-    pub steady_state: RowVector,
+    pub steady_state: RowVector<B>,
 }
 
-impl Proof {
+impl<B: Bits> Proof<B> {
     /// A purported proof that `tm` is non-halting -- validate()` confirms if it works.
-    pub fn new(direction: Side, dfa: DFA, nfa: NFA, steady_state: RowVector) -> Proof {
+    pub fn new(direction: Side, dfa: DFA, nfa: NFA<B>, steady_state: RowVector<B>) -> Proof<B> {
         Proof {
             automaton: TapeAutomaton::new(direction, dfa, nfa),
             steady_state,
         }
     }
 
+    /// The same proof, stored using another `Bits` type (which must have room for its states).
+    /// Provers search with the narrowest masks they can, then widen their results to `Proof`.
+    pub fn widen<C: Bits>(&self) -> Proof<C> {
+        let a = &self.automaton;
+        Proof::new(
+            a.direction,
+            a.dfa.clone(),
+            a.nfa.widen(),
+            self.steady_state.widen(),
+        )
+    }
+
     /// Ensure the `Proof` satisfies the invariants described in the class doc comments.
     /// (Thus, no sequence of TM steps can lead from the starting TM configuration to a halt!)
     pub fn validate(&self, tm: &Machine) -> ProofResult<()> {
-        self.automaton.validate()?;
+        let n = tm.states();
+        self.automaton.validate(n)?;
+        if self.automaton.dfa.symbols() != tm.symbols() {
+            // The automata must read the TM's alphabet.
+            return Err(BadProof::BadDimensions);
+        }
         self.automaton
             .nfa
             .check_accepted_steady_state(self.steady_state)?;
-        if row(nfa_start(0, 0)) * self.automaton.nfa.accepted {
-            Err(BadProof::BadStart)
-        } else {
-            tm.rules().try_for_each(|rule| {
-                (0..self.automaton.dfa.len() as DFAState).try_for_each(|q| {
-                    if self.closed(0, &rule) {
-                        Ok(())
-                    } else {
-                        Err(BadProof::NotClosed {
-                            q,
-                            rule: rule.clone(),
-                        })
-                    }
-                })
+        if row(nfa_start(0, 0, n)) * self.automaton.nfa.accepted {
+            return Err(BadProof::BadStart);
+        }
+        // Every mask is now known to fit the NFA. Closure is the costly part to check, and it's
+        // much faster with the narrowest masks which fit (as in the provers' searches).
+        match MaskWidth::fitting(self.automaton.nfa.len()) {
+            Some(MaskWidth::U64) if B::BITS > 64 => self.widen::<u64>().check_closure(tm),
+            Some(MaskWidth::U128) if B::BITS > 128 => self.widen::<u128>().check_closure(tm),
+            _ => self.check_closure(tm),
+        }
+    }
+
+    /// The last part of `validate()`: ensure the automaton is closed under each TM rule.
+    fn check_closure(&self, tm: &Machine) -> ProofResult<()> {
+        tm.rules().try_for_each(|rule| {
+            (0..self.automaton.dfa.len()).try_for_each(|q| {
+                let q = q as DFAState;
+                if self.closed(q, &rule, tm.states()) {
+                    Ok(())
+                } else {
+                    Err(BadProof::NotClosed {
+                        q,
+                        rule: rule.clone(),
+                    })
+                }
             })
+        })
+    }
+
+    /// An empirical sanity check, independent of `validate()`: run `tm` for up to `steps` steps,
+    /// and ensure the automaton rejects each configuration reached (as a valid proof must).
+    pub fn cross_check(&self, tm: &Machine, steps: usize) -> ProofResult<()> {
+        let mut c = Configuration::start();
+        for step in 0..=steps {
+            if self.automaton.accepts(&c, tm.states()) {
+                return Err(BadProof::AcceptedReachable { steps: step });
+            }
+            if !c.step(tm) {
+                break;
+            }
         }
+        Ok(())
     }
 
-    fn closed(&self, q: DFAState, rule: &Rule) -> bool {
+    fn closed(&self, q: DFAState, rule: &Rule, tm_states: usize) -> bool {
         let a = &self.automaton;
         let (fwd, dfa, nfa) = (a.direction, &a.dfa, &a.nfa);
+        let start = |q, f| nfa_start(q, f, tm_states);
         match *rule {
-            Rule::Halt { f, r } => nfa.step(nfa_start(q, f), r) >= self.steady_state,
+            Rule::Halt { f, r } => nfa.step(start(q, f), r) >= self.steady_state,
             Rule::Move { f, r, w, d, t } => {
                 if d == fwd {
-                    nfa.step(nfa_start(q, f), r) >= row(nfa_start(dfa.step(q, w), t))
+                    nfa.step(start(q, f), r) >= row(start(dfa.step(q, w), t))
                 } else {
-                    (0..2u8).all(|b| {
-                        nfa.step(nfa_start(dfa.step(q, b), f), r)
-                            >= nfa.step_vec(nfa.step(nfa_start(q, t), b), w)
+                    (0..dfa.symbols() as u8).all(|b| {
+                        nfa.step(start(dfa.step(q, b), f), r)
+                            >= nfa.step_vec(nfa.step(start(q, t), b), w)
                     })
                 }
             }
@@ -201,6 +268,16 @@ mod tests {
             })
         );
         proof.automaton.nfa.t[1][0] = row(10);
+        // ... for every DFA state.
+        proof.automaton.nfa.t[1][5] = row(0);
+        assert_eq!(
+            proof.validate(&tm),
+            Err(BadProof::NotClosed {
+                q: 1,
+                rule: Rule::Halt { f: 0, r: 1 }
+            })
+        );
+        proof.automaton.nfa.t[1][5] = row(10);
 
         proof.automaton.nfa.t[0][4] = row(0);
         assert_eq!(
@@ -233,4 +310,144 @@ mod tests {
             })
         );
     }
+
+    #[test]
+    fn test_accepts_and_cross_check() {
+        // The proof for https://bbchallenge.org/1 from `test_simple_proof()`.
+        let tm = Machine::from_str("1RB---_0RC---_0RD---_0RE---_0LE1RB").unwrap();
+        let mut proof: Proof = serde_json::from_str(
+            r#"{
+                "automaton": {
+                    "direction": "R",
+                    "dfa": [[0, 0]],
+                    "nfa": {"accepted": 32, "t": [[2, 4, 8, 16, 28, 32], [32, 32, 32, 32, 2, 32]]}},
+                "steady_state": 32}"#,
+        )
+        .unwrap();
+        let config = |tape: &[u8], head, state| Configuration {
+            tape: tape.to_vec(),
+            head,
+            state,
+        };
+        let a = &proof.automaton;
+        assert!(!a.accepts(&Configuration::start(), 5));
+        // Halting configurations are accepted, however much tape surrounds them.
+        assert!(a.accepts(&config(&[1], 0, 0), 5));
+        assert!(a.accepts(&config(&[0, 1, 1, 0], 2, 0), 5));
+        // This TM never writes a 1 to the right of a state-E head, so:
+        assert!(!a.accepts(&config(&[0, 0, 0], 1, 4), 5));
+        assert!(a.accepts(&config(&[0, 0, 1], 1, 4), 5));
+        assert_eq!(proof.cross_check(&tm, 1000), Ok(()));
+        // A corrupt automaton which accepts everything fails at once...
+        proof.automaton.nfa.t[0][0] = row(0);
+        proof.automaton.nfa.accepted = ColVector::from_iter(0..6);
+        assert_eq!(
+            proof.cross_check(&tm, 1000),
+            Err(BadProof::AcceptedReachable { steps: 0 })
+        );
+        // ... and this one first accepts the configuration after 3 steps, 100[D0].
+        proof.automaton.nfa.accepted = col(4) | col(5);
+        assert_eq!(
+            proof.cross_check(&tm, 1000),
+            Err(BadProof::AcceptedReachable { steps: 3 })
+        );
+    }
+
+    #[test]
+    fn test_other_dimensions() {
+        // A 1-state TM, which moves right over 0s and so never reaches its halt rule (A, 1):
+        let tm = Machine::from_str("0RA---").unwrap();
+        let proof: Proof = serde_json::from_str(
+            r#"{
+                "automaton": {"direction": "R", "dfa": [[0, 0]], "nfa": {"accepted": 2, "t": [[1, 2], [2, 2]]}},
+                "steady_state": 2}"#,
+        )
+        .unwrap();
+        assert_eq!(proof.validate(&tm), Ok(()));
+        // With 5 TM states, the proof would be too small (and its states mean something else).
+        let tm = Machine::from_str("0RA---_0RA---_0RA---_0RA---_0RA---").unwrap();
+        assert_eq!(proof.validate(&tm), Err(BadProof::BadNFASize));
+        // A 3-symbol TM needs automata reading 3 symbols.
+        let tm = Machine::from_str("0RA------").unwrap();
+        assert_eq!(proof.validate(&tm), Err(BadProof::BadDimensions));
+        let mut proof: Proof = serde_json::from_str(
+            r#"{
+                "automaton": {"direction": "R", "dfa": [[0, 0, 0]], "nfa": {"accepted": 2, "t": [[1, 2], [2, 2], [2, 2]]}},
+                "steady_state": 2}"#,
+        )
+        .unwrap();
+        assert_eq!(proof.validate(&tm), Ok(()));
+        let t_2 = proof.automaton.nfa.t.pop().unwrap();
+        assert_eq!(proof.validate(&tm), Err(BadProof::BadDimensions));
+        proof.automaton.nfa.t.push(t_2);
+        proof.automaton.nfa.t[2][0] = RowVector::new();
+        assert_eq!(
+            proof.validate(&tm),
+            Err(BadProof::NotClosed {
+                q: 0,
+                rule: Rule::Halt { f: 0, r: 2 }
+            })
+        );
+    }
+
+    #[test]
+    fn test_mask_widths() {
+        // `validate()` checks closure with narrower masks, when they fit. Pad an NFA past them,
+        // and check each width reaches the same verdict.
+        let tm = Machine::from_str("0RA---").unwrap();
+        let proof: Proof = serde_json::from_str(
+            r#"{
+                "automaton": {"direction": "R", "dfa": [[0, 0]], "nfa": {"accepted": 2, "t": [[1, 2], [2, 2]]}},
+                "steady_state": 2}"#,
+        )
+        .unwrap();
+        for len in [2, 100, 200] {
+            let mut padded = proof.clone();
+            for m in padded.automaton.nfa.t.iter_mut() {
+                let mut big = Matrix::new(len);
+                (0..2).for_each(|i| big[i] = m[i]);
+                *m = big;
+            }
+            let mut broken = padded.clone();
+            broken.automaton.nfa.t[0][0] = RowVector::new();
+            let not_closed = Err(BadProof::NotClosed {
+                q: 0,
+                rule: Rule::Move {
+                    f: 0,
+                    r: 0,
+                    w: 0,
+                    d: Side::R,
+                    t: 0,
+                },
+            });
+            assert_eq!(padded.validate(&tm), Ok(()));
+            assert_eq!(broken.validate(&tm), not_closed);
+            if len <= 128 {
+                assert_eq!(padded.widen::<u128>().validate(&tm), Ok(()));
+                assert_eq!(broken.widen::<u128>().validate(&tm), not_closed);
+            }
+        }
+    }
+
+    #[test]
+    fn test_oversized() {
+        // A forged "proof" for a halting TM, too big for its state numbers: it must not get as far
+        // as the closure checks (whose loops would wrap around to nothing).
+        let tm = Machine::from_str("1RB1LB_1LA---").unwrap();
+        let mut nfa = NFA::new(2 * MAX_NFA, 2);
+        nfa.accepted = (1..=255).collect();
+        for m in nfa.t.iter_mut() {
+            (1..=255).for_each(|i| m[i] = row(1));
+        }
+        let forged: Proof = Proof::new(Side::R, DFA::new(256, 2), nfa, row(1));
+        assert_eq!(forged.validate(&tm), Err(BadProof::TooLarge));
+        // Likewise for either automaton just over its limit.
+        let n = max_dfa(tm.states());
+        let too_many_dfa_states: Proof =
+            Proof::new(Side::R, DFA::new(n + 1, 2), NFA::new(MAX_NFA, 2), row(1));
+        assert_eq!(too_many_dfa_states.validate(&tm), Err(BadProof::TooLarge));
+        let too_many_nfa_states: Proof =
+            Proof::new(Side::R, DFA::new(1, 2), NFA::new(MAX_NFA + 1, 2), row(1));
+        assert_eq!(too_many_nfa_states.validate(&tm), Err(BadProof::TooLarge));
+    }
 }
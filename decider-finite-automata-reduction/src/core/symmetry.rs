@@ -0,0 +1,222 @@
+//! Symmetries between equivalent TMs, and the corresponding transformations of their proofs.
+//! Mirroring a TM (swapping L and R) mirrors every configuration, so a `TapeAutomaton` scanning
+//! the other way reads exactly the same words. Renaming TM states (other than the start state A)
+//! just renames the NFA states `nfa_start(q, f)`. Either way, the transformed proof is valid iff
+//! the original is. These two operations commute, so a `Symmetry` is a pair of them.
+
+use super::{nfa_start, Bits, DFAState, Machine, NFAState, Proof, RowVector, Rule, TMState, NFA};
+
+/// A map from TMs to equivalent TMs: optionally mirror, then rename each state `f` to `states[f]`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Symmetry {
+    pub mirror: bool,
+    pub states: Vec<TMState>,
+}
+
+impl Symmetry {
+    /// The symmetry which does nothing to TMs with `n` states.
+    pub fn identity(n: usize) -> Symmetry {
+        Symmetry {
+            mirror: false,
+            states: (0..n as TMState).collect(),
+        }
+    }
+
+    /// The symmetry which undoes this one.
+    pub fn inverse(&self) -> Symmetry {
+        let mut states = vec![0; self.states.len()];
+        for (f, &g) in self.states.iter().enumerate() {
+            states[g as usize] = f as TMState;
+        }
+        Symmetry {
+            mirror: self.mirror,
+            states,
+        }
+    }
+
+    /// The symmetry which applies this one, then `other`.
+    pub fn then(&self, other: &Symmetry) -> Symmetry {
+        Symmetry {
+            mirror: self.mirror != other.mirror,
+            states: (self.states.iter())
+                .map(|&f| other.states[f as usize])
+                .collect(),
+        }
+    }
+
+    /// The image of `tm`.
+    pub fn apply(&self, tm: &Machine) -> Machine {
+        let tm = if self.mirror { tm.mirror() } else { tm.clone() };
+        tm.relabel(&self.states)
+    }
+
+    /// The image of a proof for a TM `tm` (which is valid for the image of the TM iff it's valid).
+    pub fn apply_proof<B: Bits>(&self, proof: &Proof<B>, tm: &Machine) -> Proof<B> {
+        let (n, a) = (tm.states(), &proof.automaton);
+        let starts = a.dfa.len() * n;
+        let rename = |i: NFAState| match i as usize {
+            i if i < starts => nfa_start((i / n) as DFAState, self.states[i % n], n),
+            _ => i,
+        };
+        let rename_vec = |v: RowVector<B>| v.into_iter().map(rename).collect();
+        let mut nfa = NFA::new(a.nfa.len(), a.nfa.symbols());
+        for (m_new, m_old) in nfa.t.iter_mut().zip(a.nfa.t.iter()) {
+            for i in 0..a.nfa.len() {
+                let i = i as NFAState;
+                m_new[rename(i)] = rename_vec(m_old[i]);
+            }
+        }
+        nfa.accepted = a.nfa.accepted.into_iter().map(rename).collect();
+        let direction = if self.mirror {
+            a.direction.mirror()
+        } else {
+            a.direction
+        };
+        Proof::new(
+            direction,
+            a.dfa.clone(),
+            nfa,
+            rename_vec(proof.steady_state),
+        )
+    }
+}
+
+impl Machine {
+    /// The canonical representative of the TMs equivalent to this one under mirroring and renaming
+    /// states, and the `Symmetry` mapping this TM to it. The canonical form numbers the states in
+    /// Tree Normal Form order (by first appearance in a breadth-first traversal of the transitions
+    /// from A), then picks whichever of its mirror images has the smaller binary encoding.
+    pub fn canonical(&self) -> (Machine, Symmetry) {
+        let tnf = self.tnf();
+        let mirrored = Symmetry {
+            mirror: true,
+            ..tnf.clone()
+        };
+        let (a, b) = (tnf.apply(self), mirrored.apply(self));
+        if b.as_bytes() < a.as_bytes() {
+            (b, mirrored)
+        } else {
+            (a, tnf)
+        }
+    }
+
+    /// A symmetry mapping this TM to one which agrees with `other` on every state reachable from A.
+    /// (Their transitions from unreachable states may differ arbitrarily.)
+    pub fn symmetry_to(&self, other: &Machine) -> Option<Symmetry> {
+        if (self.states(), self.symbols()) != (other.states(), other.symbols()) {
+            return None;
+        }
+        let (to_other, reachable) = (other.tnf().inverse(), other.reachable_states());
+        [false, true].into_iter().find_map(|mirror| {
+            let symmetry = Symmetry {
+                mirror,
+                ..self.tnf()
+            }
+            .then(&to_other);
+            let image = symmetry.apply(self);
+            let agree = |f: TMState| {
+                (0..self.symbols() as u8).all(|r| image.rule(f, r) == other.rule(f, r))
+            };
+            reachable.iter().all(|&f| agree(f)).then_some(symmetry)
+        })
+    }
+
+    /// The states reachable from A via the transition graph, in breadth-first order.
+    pub fn reachable_states(&self) -> Vec<TMState> {
+        let (mut order, mut seen) = (vec![0 as TMState], vec![false; self.states()]);
+        seen[0] = true;
+        let mut i = 0;
+        while i < order.len() {
+            for r in 0..self.symbols() as u8 {
+                if let Rule::Move { t, .. } = self.rule(order[i], r) {
+                    if !seen[t as usize] {
+                        seen[t as usize] = true;
+                        order.push(t);
+                    }
+                }
+            }
+            i += 1;
+        }
+        order
+    }
+
+    /// The renaming of states into Tree Normal Form order: the reachable states in breadth-first
+    /// order, then the rest.
+    fn tnf(&self) -> Symmetry {
+        let reachable = self.reachable_states();
+        let unreachable = (0..self.states() as TMState).filter(|f| !reachable.contains(f));
+        let order: Vec<TMState> = reachable.iter().copied().chain(unreachable).collect();
+        let mut tnf = Symmetry::identity(self.states());
+        for (g, &f) in order.iter().enumerate() {
+            tnf.states[f as usize] = g as TMState;
+        }
+        tnf
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{Matrix, MAX_NFA};
+    use crate::provers::{DirectProver, Prover, ProverOptions};
+    use std::str::FromStr;
+
+    #[test]
+    fn test_canonical() {
+        let tm = Machine::from_str("1RB1LC_1RC1RB_1RD0LE_1LA1LD_---0LA").unwrap();
+        // Swap C and D, then mirror: these are all the same TM.
+        let variants = [
+            "1RB1LD_1RD1RB_1LA1LC_1RC0LE_---0LA",
+            "1LB1RC_1LC1LB_1LD0RE_1RA1RD_---0RA",
+            "1LB1RD_1LD1LB_1RA1RC_1LC0RE_---0RA",
+        ];
+        let (canonical, symmetry) = tm.canonical();
+        assert_eq!(symmetry.apply(&tm), canonical);
+        assert_eq!(symmetry.inverse().apply(&canonical), tm);
+        for text in variants {
+            let variant = Machine::from_str(text).unwrap();
+            assert_eq!(variant.canonical().0, canonical);
+        }
+        // Unreachable states go last, and halting transitions are normalized.
+        let tm = Machine::from_str("1RC---_0LA1RZ_1LA0RA").unwrap();
+        let expected = Machine::from_str("1RB---_1LA0RA_0LA---").unwrap();
+        assert_eq!(tm.canonical().0, expected);
+    }
+
+    #[test]
+    fn test_apply_proof() {
+        let base = Machine::from_str("1RB1LC_0LA0LB_1RC0RA").unwrap();
+        let tm = Symmetry {
+            mirror: true,
+            states: vec![0, 2, 1],
+        }
+        .apply(&base);
+        let proof = DirectProver::new(3)
+            .prove(&tm)
+            .expect("solvable at depth 3");
+        let (canonical, symmetry) = tm.canonical();
+        assert_eq!(canonical, base);
+        let image = symmetry.apply_proof(&proof, &tm);
+        assert_eq!(image.validate(&canonical), Ok(()));
+        let back = symmetry.inverse().apply_proof(&image, &canonical);
+        assert_eq!(back.validate(&tm), Ok(()));
+        let mirror = Symmetry {
+            mirror: true,
+            ..Symmetry::identity(tm.states())
+        };
+        assert_eq!(
+            mirror.apply_proof(&proof, &tm).validate(&tm.mirror()),
+            Ok(())
+        );
+        assert!(mirror.apply_proof(&proof, &tm).validate(&tm).is_err());
+        // The largest NFAs carry over too: pad this one with unused states, up to `MAX_NFA`.
+        let mut padded = proof.clone();
+        for m in padded.automaton.nfa.t.iter_mut() {
+            let mut big = Matrix::new(MAX_NFA);
+            (0..m.len()).for_each(|i| big[i as NFAState] = m[i as NFAState]);
+            *m = big;
+        }
+        let image = symmetry.apply_proof(&padded, &tm);
+        assert_eq!(image.validate(&canonical), Ok(()));
+    }
+}
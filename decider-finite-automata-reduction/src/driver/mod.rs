@@ -1,7 +1,9 @@
 //! Features that help humans operate the decider, like distributed computing and progress tracking.
 
 mod node_crunch;
+mod outputs;
 mod progress;
 
 pub use self::node_crunch::{process_remote, run_node};
+pub use outputs::{canonical_classes, Attempt, AttemptOptions, Outcome, Outputs, TMClass};
 pub use progress::{DeciderProgress, DeciderProgressIterator};
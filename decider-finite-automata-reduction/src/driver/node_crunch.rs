@@ -1,6 +1,6 @@
-use super::DeciderProgress;
-use crate::core::{BadProof, Side, DFA};
-use crate::io::{Database, DeciderVerificationFile, Index, MachineID, OutputFile};
+use super::{Attempt, AttemptOptions, DeciderProgress, Outcome, Outputs};
+use crate::core::Machine;
+use crate::io::{Database, Index, MachineID, MachineSource, OutputFile};
 use crate::provers::{prover_by_name, ProverBox};
 use crate::DeciderArgs;
 use indicatif::ProgressBar;
@@ -13,19 +13,28 @@ use std::cmp::{max, min};
 use std::collections::{HashMap, VecDeque};
 use std::time::Instant;
 
-type DeciderResult = Result<(Side, DFA), BadProof>;
+/// What every node needs to know about the job, up front.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobSetup {
+    prover_names: Vec<String>,
+    options: AttemptOptions,
+    /// The number of states in each TM.
+    states: usize,
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NewData {
     stage: usize,
     batch_id: usize,
     ids: Vec<MachineID>,
+    /// The TMs themselves, unless the nodes can read them from their own copies of the DB.
+    tms: Option<Vec<Machine>>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ProcessedData {
     batch_id: usize,
-    results: Vec<(MachineID, DeciderResult)>,
+    attempts: Vec<Attempt>,
 }
 
 struct WorkStats {
@@ -49,29 +58,97 @@ fn update(s: &mut WorkStats) {
 }
 
 struct Server {
+    source: MachineSource,
     index: Index,
     progress: DeciderProgress,
     prover_names: Vec<String>,
-    out: OutputFile,
-    dvf: DeciderVerificationFile,
+    options: AttemptOptions,
+    outputs: Outputs,
     stage: usize,
     batch_id: usize,
     ids: VecDeque<MachineID>,
     retry_batches: Vec<usize>,
     bars: Vec<ProgressBar>,
+    attempts: Vec<OutputFile>,
     stats: HashMap<NodeID, WorkStats>,
     batches_out: HashMap<usize, NewData>,
     tms_out_this_stage: usize,
 }
 
+impl Server {
+    fn new(
+        source: MachineSource,
+        index: Index,
+        progress: DeciderProgress,
+        prover_names: Vec<String>,
+        options: AttemptOptions,
+        outputs: Outputs,
+    ) -> Server {
+        Server {
+            source,
+            index,
+            progress,
+            prover_names,
+            options,
+            outputs,
+            stage: 0,
+            batch_id: 0,
+            ids: VecDeque::new(),
+            retry_batches: Vec::new(),
+            bars: Vec::new(),
+            attempts: Vec::new(),
+            stats: HashMap::new(),
+            batches_out: HashMap::new(),
+            tms_out_this_stage: 0,
+        }
+    }
+
+    /// Queue up the next TMs to send, moving on through the stages until some are left.
+    /// If none are, return the status to report instead.
+    fn fill_ids(&mut self) -> Result<Option<NCJobStatus<NewData>>, NCError> {
+        while self.ids.is_empty() {
+            // Each stage is opened once: leave it once it's out of TMs.
+            if self.attempts.len() > self.stage {
+                self.stage += 1;
+            }
+            self.index.read_decided(self.outputs.dir())?;
+            self.progress.set_solved(self.index.len_solved());
+            if self.stage < self.prover_names.len() {
+                // Skip the TMs this prover already failed on, in an earlier (interrupted) run.
+                let name = &self.prover_names[self.stage];
+                self.ids = self.index.untried(self.outputs.dir(), name)?.into();
+                self.attempts
+                    .push(OutputFile::append(self.outputs.dir().attempts(name))?);
+                self.tms_out_this_stage = 0;
+                self.bars.push(
+                    self.progress
+                        .prover_progress(self.ids.len(), self.prover_names[self.stage].clone()),
+                );
+            } else if self.batches_out.is_empty() {
+                self.outputs.finalize()?;
+                self.progress.println("Done! Worker shutdown takes ~60s.")?;
+                self.progress.finish();
+                return Ok(Some(NCJobStatus::Finished));
+            } else {
+                return Ok(Some(NCJobStatus::Waiting));
+            }
+        }
+        Ok(None)
+    }
+}
+
 impl NCServer for Server {
-    type InitialDataT = Vec<String>;
+    type InitialDataT = JobSetup;
     type NewDataT = NewData;
     type ProcessedDataT = ProcessedData;
     type CustomMessageT = ();
 
     fn initial_data(&mut self) -> Result<Option<Self::InitialDataT>, NCError> {
-        Ok(Some(self.prover_names.clone()))
+        Ok(Some(JobSetup {
+            prover_names: self.prover_names.clone(),
+            options: self.options,
+            states: self.source.states(),
+        }))
     }
 
     fn prepare_data_for_node(
@@ -87,32 +164,26 @@ impl NCServer for Server {
                 return Ok(NCJobStatus::Unfinished(batch.clone()));
             }
         }
-        while self.ids.is_empty() {
-            self.stage += (self.batch_id != 0) as usize;
-            self.index.read_decided()?;
-            self.progress.set_solved(self.index.len_solved());
-            if self.stage < self.prover_names.len() {
-                self.ids = self.index.iter().collect();
-                self.tms_out_this_stage = 0;
-                self.bars.push(
-                    self.progress
-                        .prover_progress(self.ids.len(), self.prover_names[self.stage].clone()),
-                );
-            } else if self.batches_out.is_empty() {
-                self.progress.println("Done! Worker shutdown takes ~60s.")?;
-                self.progress.finish();
-                return Ok(NCJobStatus::Finished);
-            } else {
-                return Ok(NCJobStatus::Waiting);
-            }
+        if let Some(status) = self.fill_ids()? {
+            return Ok(status);
         }
+        let entry = self.stats.entry(node_id).or_default();
         let len = min(self.ids.len(), entry.target_size);
         self.tms_out_this_stage += len;
         self.batch_id += 1;
+        let ids: Vec<MachineID> = self.ids.drain(0..len).collect();
+        let tms = match &self.source {
+            MachineSource::Database(_) => None,
+            MachineSource::List(list) => {
+                let tm = |i| list.get(i).expect("the index only has listed TMs").clone();
+                Some(ids.iter().map(|&i| tm(i)).collect())
+            }
+        };
         let new_data = NewData {
             stage: self.stage,
             batch_id: self.batch_id,
-            ids: self.ids.drain(0..len).collect(),
+            ids,
+            tms,
         };
         entry.todo.push(self.batch_id);
         self.batches_out.insert(self.batch_id, new_data.clone());
@@ -148,9 +219,9 @@ impl NCServer for Server {
                 self.tms_out_this_stage -= size_out;
             } else if self.stage < self.prover_names.len() {
                 let done = node_data
-                    .results
+                    .attempts
                     .iter()
-                    .filter_map(|(id, result)| result.is_ok().then_some(*id));
+                    .filter_map(|a| matches!(a.outcome, Outcome::Solved(_)).then_some(a.id));
                 self.ids = self
                     .ids
                     .iter()
@@ -167,18 +238,13 @@ impl NCServer for Server {
                 );
             }
         });
-        for (i, result) in node_data.results.iter() {
-            match &result {
-                Ok((direction, dfa)) => {
-                    self.out.insert(*i)?;
-                    self.dvf.insert(*i, *direction, dfa)?;
-                    self.progress.solve(1);
-                }
-                Err(e) => {
-                    let msg = format!("Rejected proof of {}: {:?}", i, e);
-                    self.progress.println(msg)?;
-                }
-            }
+        let name = &self.prover_names[sent.stage];
+        // The node had the TMs, but only sent back the attempts: look them up again.
+        let ids = node_data.attempts.iter().map(|attempt| attempt.id);
+        for ((_, tm), attempt) in self.source.read(ids).zip(node_data.attempts.iter()) {
+            let attempts = &mut self.attempts[sent.stage];
+            self.outputs
+                .record(&self.progress, name, attempts, &tm, attempt)?;
         }
         Ok(())
     }
@@ -199,20 +265,26 @@ impl NCServer for Server {
 }
 
 struct Node {
-    db: Database,
+    /// The DB, if this node has one. (It's not needed to work on a list of TMs.)
+    db: Option<Database>,
     prover_names: Vec<String>,
+    options: AttemptOptions,
+    states: usize,
     current_prover: Option<ProverBox>,
     current_stage: usize,
 }
 
 impl NCNode for Node {
-    type InitialDataT = Vec<String>;
+    type InitialDataT = JobSetup;
     type NewDataT = NewData;
     type ProcessedDataT = ProcessedData;
     type CustomMessageT = ();
 
-    fn set_initial_data(&mut self, _: NodeID, data: Option<Vec<String>>) -> Result<(), NCError> {
-        self.prover_names = data.ok_or(NCError::ServerMsgMismatch)?;
+    fn set_initial_data(&mut self, _: NodeID, data: Option<JobSetup>) -> Result<(), NCError> {
+        let setup = data.ok_or(NCError::ServerMsgMismatch)?;
+        self.prover_names = setup.prover_names;
+        self.options = setup.options;
+        self.states = setup.states;
         Ok(())
     }
 
@@ -222,28 +294,25 @@ impl NCNode for Node {
     ) -> Result<Self::ProcessedDataT, NCError> {
         if self.current_prover.is_none() || self.current_stage != data.stage {
             self.current_stage = data.stage;
-            self.current_prover = prover_by_name(&self.prover_names[data.stage]);
+            self.current_prover = prover_by_name(&self.prover_names[data.stage], self.states);
         }
         let prover = match &mut self.current_prover {
             Some(p) => p,
             None => return Err(NCError::ServerMsgMismatch),
         };
         let batch_id = data.batch_id;
-        let results = self
-            .db
-            .read(data.ids.iter().copied())
-            .filter_map(|(i, tm)| {
-                prover
-                    .prove(&tm)
-                    .map(|proof| {
-                        proof
-                            .validate(&tm)
-                            .map(|()| (proof.automaton.direction, proof.automaton.dfa))
-                    })
-                    .map(|r| (i, r))
-            })
+        let tms: Box<dyn Iterator<Item = (MachineID, Machine)>> = match (&data.tms, &self.db) {
+            (Some(tms), _) => Box::new(data.ids.iter().copied().zip(tms.iter().cloned())),
+            (None, Some(db)) => Box::new(db.read(data.ids.iter().copied())),
+            (None, None) => {
+                let msg = "the server needs this node to read TMs from the DB (see --db)";
+                return Err(std::io::Error::new(std::io::ErrorKind::NotFound, msg).into());
+            }
+        };
+        let attempts = tms
+            .map(|(i, tm)| Attempt::run(prover, i, &tm, self.options))
             .collect();
-        Ok(ProcessedData { batch_id, results })
+        Ok(ProcessedData { batch_id, attempts })
     }
 }
 
@@ -256,7 +325,7 @@ fn config_from_args(args: DeciderArgs) -> NCConfiguration {
     }
 }
 
-pub fn run_node(args: DeciderArgs, db: Database) {
+pub fn run_node(args: DeciderArgs, db: Option<Database>) {
     let prover_names = Vec::new();
     let current_prover = None;
     let current_stage = 0;
@@ -264,6 +333,8 @@ pub fn run_node(args: DeciderArgs, db: Database) {
         .start(Node {
             db,
             prover_names,
+            options: AttemptOptions::default(),
+            states: 0,
             current_prover,
             current_stage,
         })
@@ -272,27 +343,69 @@ pub fn run_node(args: DeciderArgs, db: Database) {
 
 pub fn process_remote(
     args: DeciderArgs,
+    source: MachineSource,
     index: Index,
     progress: DeciderProgress,
     prover_names: Vec<String>,
-    out: OutputFile,
-    dvf: DeciderVerificationFile,
+    outputs: Outputs,
 ) {
+    let options = AttemptOptions::from_args(&args);
     NCServerStarter::new(config_from_args(args))
-        .start(Server {
+        .start(Server::new(
+            source,
             index,
             progress,
             prover_names,
-            out,
-            dvf,
-            stage: 0,
-            batch_id: 0,
-            ids: VecDeque::new(),
-            retry_batches: Vec::new(),
-            bars: Vec::new(),
-            stats: HashMap::new(),
-            batches_out: HashMap::new(),
-            tms_out_this_stage: 0,
-        })
+            options,
+            outputs,
+        ))
         .expect("Quit due to server error!");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::{ListFormat, MachineList, OutputDir};
+
+    /// A server for the `direct-1` and `direct-2` provers, on three 2-state TMs,
+    /// with its output in `dir`.
+    fn server(dir: &OutputDir) -> Server {
+        let text = "0 1RB1LB_1LA---\n1 1RB1LB_1LA0LB\n2 1RB---_1LB0LA\n";
+        let list = MachineList::read(text.as_bytes(), &ListFormat::Text, 2, 2).unwrap();
+        let source = MachineSource::List(list);
+        let index = source.index("");
+        let progress = DeciderProgress::new(index.len_initial());
+        let outputs = Outputs::append(dir, false, None).unwrap();
+        let prover_names = vec![String::from("direct-1"), String::from("direct-2")];
+        let options = AttemptOptions::default();
+        Server::new(source, index, progress, prover_names, options, outputs)
+    }
+
+    #[test]
+    fn test_resume() -> std::io::Result<()> {
+        let path = std::env::temp_dir().join(format!("far_test_server_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path)?;
+        let dir = OutputDir::new(&path);
+        // A fresh server starts with the first prover.
+        let mut fresh = server(&dir);
+        assert!(fresh.fill_ids().unwrap().is_none());
+        assert_eq!((fresh.stage, fresh.ids.len()), (0, 3));
+        drop(fresh);
+        // An interrupted run solved TM 1, and the first prover tried the rest.
+        OutputFile::append(dir.index())?.insert(1)?;
+        let mut tried = OutputFile::append(dir.attempts("direct-1"))?;
+        [0, 2].into_iter().try_for_each(|i| tried.insert(i))?;
+        let mut resumed = server(&dir);
+        assert!(resumed.fill_ids().unwrap().is_none());
+        assert_eq!(resumed.stage, 1);
+        assert_eq!(resumed.ids, [0, 2]);
+        assert_eq!((resumed.attempts.len(), resumed.bars.len()), (2, 2));
+        // Once the last stage's TMs are all back, the job is done.
+        resumed.ids.clear();
+        let status = resumed.fill_ids().unwrap();
+        assert!(matches!(status, Some(NCJobStatus::Finished)));
+        assert_eq!(resumed.attempts.len(), 2);
+        std::fs::remove_dir_all(&path)
+    }
+}
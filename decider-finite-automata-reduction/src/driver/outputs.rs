@@ -0,0 +1,474 @@
+//! Recording what became of each prover's attempt on each TM, in our output files.
+
+use super::DeciderProgress;
+use crate::core::{BadProof, Counterexample, Machine, Proof, Symmetry};
+use crate::io::{
+    DeciderVerificationFile, MachineID, MachineSource, OutputDir, OutputFile, ProofCache,
+    ReportFile, ReportRecord,
+};
+use crate::provers::{minimize, Deadline, DirectProver, ProverBox, ProverResult, TimedOut};
+use crate::DeciderArgs;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::ops::Range;
+use std::time::{Duration, Instant};
+
+/// What became of a prover's attempt to solve a TM.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Outcome {
+    Solved(Proof),
+    /// The error, and a counterexample if `Proof::counterexample()` finds one.
+    Rejected(BadProof, Option<Counterexample>),
+    Failed,
+    TimedOut,
+}
+
+impl Outcome {
+    /// Classify a prover's result, checking any proof it returned.
+    pub fn check(tm: &Machine, result: ProverResult) -> Outcome {
+        match result {
+            Ok(Some(proof)) => match proof.validate(tm) {
+                Ok(()) => Outcome::Solved(proof),
+                Err(e) => Outcome::Rejected(e.clone(), proof.counterexample(tm, &e)),
+            },
+            Ok(None) => Outcome::Failed,
+            Err(TimedOut) => Outcome::TimedOut,
+        }
+    }
+
+    /// Reject a proof of `tm` if `Proof::cross_check()` fails for the given number of steps.
+    /// (With 0 steps, it's left alone.)
+    pub fn cross_check(self, tm: &Machine, steps: usize) -> Outcome {
+        match self {
+            Outcome::Solved(proof) if steps > 0 => match proof.cross_check(tm, steps) {
+                Ok(()) => Outcome::Solved(proof),
+                Err(e) => Outcome::Rejected(e.clone(), proof.counterexample(tm, &e)),
+            },
+            outcome => outcome,
+        }
+    }
+
+    /// For `--deterministic` runs: replace a proof of `tm` with `DirectProver::first_proof()`,
+    /// which depends only on the TM. The search gets a `time_limit` of its own; if it runs out of
+    /// time (or somehow fails), the proof we had stands, and the returned flag is set.
+    pub fn first_proof(self, tm: &Machine, time_limit: Option<Duration>) -> (Outcome, bool) {
+        match self {
+            Outcome::Solved(proof) => {
+                let depth = proof.automaton.dfa.len();
+                let deadline = Deadline::after(time_limit);
+                match Outcome::check(tm, DirectProver::first_proof(tm, depth, deadline)) {
+                    Outcome::Solved(first) => (Outcome::Solved(first), false),
+                    _ => (Outcome::Solved(proof), true),
+                }
+            }
+            outcome => (outcome, false),
+        }
+    }
+}
+
+/// Settings for each `Attempt`.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct AttemptOptions {
+    /// How long a prover may spend on a TM, if limited.
+    pub time_limit: Option<Duration>,
+    /// Whether to post-process proofs with `provers::minimize()`.
+    pub minimize: bool,
+    /// How many TM steps to `Proof::cross_check()` proofs for (0 to skip).
+    pub cross_check: usize,
+    /// Whether to attempt one TM per equivalence class (see `canonical_classes()`).
+    pub canonical: bool,
+    /// Whether to record `Outcome::first_proof()` for each TM, rather than the proof found.
+    pub deterministic: bool,
+}
+
+impl AttemptOptions {
+    pub fn from_args(args: &DeciderArgs) -> AttemptOptions {
+        AttemptOptions {
+            time_limit: args.timeout,
+            minimize: args.minimize,
+            cross_check: args.cross_check.unwrap_or(0),
+            canonical: args.canonical,
+            deterministic: args.deterministic,
+        }
+    }
+}
+
+/// A prover's attempt to solve a TM.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Attempt {
+    pub id: MachineID,
+    pub outcome: Outcome,
+    /// The time the prover took. (Excludes checking the proof.)
+    pub elapsed: Duration,
+    /// Whether `Outcome::first_proof()` ran out of time, leaving the prover's own proof.
+    pub fell_back: bool,
+}
+
+impl Attempt {
+    /// Run `prover` on TM `id`, within the time limit (if any), and check the result.
+    pub fn run(
+        prover: &mut ProverBox,
+        id: MachineID,
+        tm: &Machine,
+        options: AttemptOptions,
+    ) -> Attempt {
+        let start = Instant::now();
+        let deadline = Deadline::after(options.time_limit);
+        let result = prover.prove_within(tm, deadline);
+        let elapsed = start.elapsed();
+        let (mut outcome, mut fell_back) = (Outcome::check(tm, result), false);
+        if options.deterministic {
+            (outcome, fell_back) = outcome.first_proof(tm, options.time_limit);
+        }
+        let outcome = match outcome {
+            Outcome::Solved(proof) if options.minimize => Outcome::Solved(minimize(tm, &proof)),
+            outcome => outcome,
+        };
+        let outcome = outcome.cross_check(tm, options.cross_check);
+        Attempt {
+            id,
+            outcome,
+            elapsed,
+            fell_back,
+        }
+    }
+}
+
+/// A class of equivalent TMs: a representative, and the TMs' IDs and `Symmetry`s mapping the
+/// representative to them.
+pub type TMClass = (Machine, Vec<(MachineID, Symmetry)>);
+
+/// Group the given TMs by `Machine::canonical()` form, so each class's representative is its
+/// canonical TM. To keep memory use down, this first reads the TMs just to hash their canonical
+/// forms, then reads each group of TMs with the same hash again as it comes up. So it yields
+/// a group at a time: usually one class, unless distinct canonical forms' hashes collide.
+/// (The groups are in order of their first TMs.)
+pub fn canonical_classes(
+    source: &MachineSource,
+    ids: Vec<MachineID>,
+) -> impl ExactSizeIterator<Item = Vec<TMClass>> + Send + '_ {
+    let hash = |tm: &Machine| {
+        let mut hasher = DefaultHasher::new();
+        tm.canonical().0.hash(&mut hasher);
+        hasher.finish()
+    };
+    let mut hashes: Vec<(u64, MachineID)> = (source.read(ids.into_iter()))
+        .map(|(i, tm)| (hash(&tm), i))
+        .collect();
+    hashes.sort_unstable();
+    let mut groups: Vec<Range<usize>> = vec![];
+    for (j, &(h, _)) in hashes.iter().enumerate() {
+        match groups.last_mut() {
+            Some(group) if hashes[group.start].0 == h => group.end = j + 1,
+            _ => groups.push(j..j + 1),
+        }
+    }
+    groups.sort_unstable_by_key(|group| hashes[group.start].1);
+    groups.into_iter().map(move |group| {
+        let ids: Vec<MachineID> = hashes[group].iter().map(|&(_, i)| i).collect();
+        group_by_canonical(source.read(ids.into_iter()))
+    })
+}
+
+/// Group TMs by `Machine::canonical()` form, in order of their first TMs.
+fn group_by_canonical(tms: impl Iterator<Item = (MachineID, Machine)>) -> Vec<TMClass> {
+    let (mut classes, mut class_of) = (Vec::<TMClass>::new(), HashMap::new());
+    for (i, tm) in tms {
+        let (canonical, symmetry) = tm.canonical();
+        let k = *class_of.entry(canonical.clone()).or_insert(classes.len());
+        if k == classes.len() {
+            classes.push((canonical, vec![]));
+        }
+        classes[k].1.push((i, symmetry.inverse()));
+    }
+    classes
+}
+
+impl Attempt {
+    /// Run `prover` on the representative of a class of TMs (as `Attempt::run()` would), then
+    /// transfer the outcome to each TM, returned along with its attempt. Proofs are transformed,
+    /// and checked again. (Only the first TM's attempt gets the elapsed time, so a report's total
+    /// stays accurate.)
+    pub fn run_class(
+        prover: &mut ProverBox,
+        (tm, members): &TMClass,
+        options: AttemptOptions,
+    ) -> Vec<(Machine, Attempt)> {
+        let attempt = Attempt::run(prover, members[0].0, tm, options);
+        if let [(_, symmetry)] = &members[..] {
+            if *symmetry == Symmetry::identity(tm.states()) {
+                return vec![(tm.clone(), attempt)];
+            }
+        }
+        let attempts = members.iter().map(|(id, symmetry)| {
+            let member = symmetry.apply(tm);
+            let (outcome, fell_back) = match &attempt.outcome {
+                Outcome::Solved(proof) if *symmetry != Symmetry::identity(tm.states()) => {
+                    let proof = symmetry.apply_proof(proof, tm);
+                    let (mut outcome, mut fell_back) =
+                        (Outcome::check(&member, Ok(Some(proof))), false);
+                    if options.deterministic {
+                        (outcome, fell_back) = outcome.first_proof(&member, options.time_limit);
+                    }
+                    (outcome.cross_check(&member, options.cross_check), fell_back)
+                }
+                outcome => (outcome.clone(), attempt.fell_back),
+            };
+            let elapsed = Duration::ZERO;
+            let attempt = Attempt {
+                id: *id,
+                outcome,
+                elapsed,
+                fell_back,
+            };
+            (member, attempt)
+        });
+        let mut attempts: Vec<(Machine, Attempt)> = attempts.collect();
+        attempts[0].1.elapsed = attempt.elapsed;
+        attempts
+    }
+}
+
+/// The files where a run records its results.
+pub struct Outputs {
+    /// Where the files are.
+    dir: OutputDir,
+    out: OutputFile,
+    dvf: DeciderVerificationFile,
+    timeouts: OutputFile,
+    nondeterministic: OutputFile,
+    report: Option<ReportFile>,
+    /// Where to save new proofs, if anywhere.
+    cache: Option<ProofCache>,
+    /// Whether to sort the DVF file when finalizing it.
+    sort_dvf: bool,
+}
+
+impl Outputs {
+    /// Open our output files for appending, plus the report file (if any).
+    pub fn append(dir: &OutputDir, full_proofs: bool, report: Option<&str>) -> io::Result<Outputs> {
+        Ok(Outputs {
+            dir: dir.clone(),
+            out: OutputFile::append(dir.index())?,
+            dvf: DeciderVerificationFile::append(dir.dvf(), full_proofs)?,
+            timeouts: OutputFile::append(dir.timeouts())?,
+            nondeterministic: OutputFile::append(dir.nondeterministic())?,
+            report: report.map(ReportFile::append).transpose()?,
+            cache: None,
+            sort_dvf: false,
+        })
+    }
+
+    /// Sort the DVF file's entries by `MachineID` when finalizing it (for `--deterministic`).
+    pub fn with_sorted_dvf(self) -> Outputs {
+        Outputs {
+            sort_dvf: true,
+            ..self
+        }
+    }
+
+    /// Also save each proof found to the given `ProofCache`.
+    pub fn with_cache(self, cache: ProofCache) -> Outputs {
+        Outputs {
+            cache: Some(cache),
+            ..self
+        }
+    }
+
+    /// The directory the output files are in.
+    pub fn dir(&self) -> &OutputDir {
+        &self.dir
+    }
+
+    /// The `ProofCache` (if any).
+    pub fn cache(&self) -> Option<&ProofCache> {
+        self.cache.as_ref()
+    }
+
+    /// Record a proof of TM `id` (from a prover, or the `ProofCache`).
+    pub fn record_solved(
+        &mut self,
+        progress: &DeciderProgress,
+        id: MachineID,
+        proof: &Proof,
+    ) -> io::Result<()> {
+        self.out.insert(id)?;
+        self.dvf.insert(id, proof)?;
+        progress.solve(1);
+        Ok(())
+    }
+
+    /// Note that TM `id`'s proof isn't its first proof, since `Outcome::first_proof()` ran out of
+    /// time, so a rerun with `--deterministic` may record a different one.
+    pub fn record_fell_back(
+        &mut self,
+        progress: &DeciderProgress,
+        id: MachineID,
+    ) -> io::Result<()> {
+        self.nondeterministic.insert(id)?;
+        progress.println(format!("No time to find the first proof of {}", id))
+    }
+
+    /// Record the named prover's `attempt` on `tm`, logging a failure to its `attempts` file.
+    pub fn record(
+        &mut self,
+        progress: &DeciderProgress,
+        prover_name: &str,
+        attempts: &mut OutputFile,
+        tm: &Machine,
+        attempt: &Attempt,
+    ) -> io::Result<()> {
+        let i = attempt.id;
+        match &attempt.outcome {
+            Outcome::Solved(proof) => {
+                self.record_solved(progress, i, proof)?;
+                if attempt.fell_back {
+                    self.record_fell_back(progress, i)?;
+                }
+                if let Some(cache) = &mut self.cache {
+                    cache.insert(tm, proof)?;
+                }
+            }
+            Outcome::Rejected(e, c) => {
+                let mut msg = format!("Rejected {} proof of {}: {:?}", prover_name, i, e);
+                if let Some(c) = c {
+                    msg = format!("{} ({})", msg, c);
+                }
+                progress.println(msg)?;
+            }
+            Outcome::Failed => attempts.insert(i)?,
+            Outcome::TimedOut => self.timeouts.insert(i)?,
+        }
+        if let Some(report) = &mut self.report {
+            let (prover, depth) = ReportRecord::split_prover_name(prover_name);
+            let solved = match &attempt.outcome {
+                Outcome::Solved(proof) => Some(proof),
+                _ => None,
+            };
+            report.insert(&ReportRecord {
+                id: i,
+                prover,
+                depth,
+                outcome: match attempt.outcome {
+                    Outcome::Solved(_) => "solved",
+                    Outcome::Rejected(..) => "rejected",
+                    Outcome::Failed => "failed",
+                    Outcome::TimedOut => "timeout",
+                },
+                seconds: attempt.elapsed.as_secs_f64(),
+                dfa_states: solved.map(|proof| proof.automaton.dfa.len()),
+                nfa_states: solved.map(|proof| proof.automaton.nfa.len()),
+                error: match &attempt.outcome {
+                    Outcome::Rejected(e, _) => Some(e),
+                    _ => None,
+                },
+                counterexample: match &attempt.outcome {
+                    Outcome::Rejected(_, c) => c.as_ref(),
+                    _ => None,
+                },
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Finish writing the output files.
+    pub fn finalize(&mut self) -> io::Result<()> {
+        if self.sort_dvf {
+            self.dvf.finalize_sorted().and(Ok(()))
+        } else {
+            self.dvf.finalize().and(Ok(()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::{Index, ListFormat, MachineList};
+    use crate::provers::prover_by_name;
+    use itertools::Itertools;
+
+    #[test]
+    fn test_canonical_classes() {
+        // TMs 0 and 2 are mirror images, and TM 1 is different.
+        let text = "0 1RB1LB_1LA0LB\n1 1RB---_1LB0LA\n2 1LB1RB_1RA0RB\n";
+        let list = MachineList::read(text.as_bytes(), &ListFormat::Text, 2, 2).unwrap();
+        let source = MachineSource::List(list);
+        let groups: Vec<Vec<TMClass>> = canonical_classes(&source, vec![0, 1, 2]).collect();
+        let ids: Vec<Vec<Vec<MachineID>>> = (groups.iter())
+            .map(|classes| {
+                (classes.iter())
+                    .map(|(_, m)| m.iter().map(|p| p.0).collect())
+                    .collect()
+            })
+            .collect();
+        assert_eq!(ids, [vec![vec![0, 2]], vec![vec![1]]]);
+        // The representative's proof carries over to the mirror image, and is checked there.
+        let mut prover = prover_by_name("direct-2", 2).unwrap();
+        let options = AttemptOptions {
+            cross_check: 100,
+            ..AttemptOptions::default()
+        };
+        let attempts = Attempt::run_class(&mut prover, &groups[0][0], options);
+        let solved = |(tm, a): &(Machine, Attempt)| match &a.outcome {
+            Outcome::Solved(proof) => proof.validate(tm).is_ok(),
+            _ => false,
+        };
+        assert_eq!(attempts.len(), 2);
+        assert!(attempts.iter().all(solved));
+        let members: Vec<Machine> = (source.read(vec![0, 2].into_iter()))
+            .map(|(_, tm)| tm)
+            .collect();
+        assert_eq!(
+            members,
+            attempts.into_iter().map(|(tm, _)| tm).collect_vec()
+        );
+    }
+
+    #[test]
+    fn test_first_proof() {
+        let tm: Machine = "1RB1LC_0LA0LB_1RC0RA".parse().unwrap();
+        let json = |outcome: &Outcome| serde_json::to_string(outcome).unwrap();
+        let proof = prover_by_name("direct-4", 3).unwrap().prove(&tm).unwrap();
+        let first = DirectProver::first_proof(&tm, 4, Deadline::default());
+        let first = Outcome::Solved(first.unwrap().unwrap());
+        let (outcome, fell_back) = Outcome::Solved(proof.clone()).first_proof(&tm, None);
+        assert_eq!((json(&outcome), fell_back), (json(&first), false));
+        // Out of time to search, it keeps the proof it was given, rather than timing out.
+        let found = Outcome::Solved(proof);
+        let (outcome, fell_back) = found.clone().first_proof(&tm, Some(Duration::ZERO));
+        assert_eq!((json(&outcome), fell_back), (json(&found), true));
+    }
+
+    #[test]
+    fn test_record_fell_back() -> io::Result<()> {
+        let path = std::env::temp_dir().join(format!("far_test_fell_back_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path)?;
+        let dir = OutputDir::new(&path);
+        let tm: Machine = "1RB1LC_0LA0LB_1RC0RA".parse().unwrap();
+        let proof = prover_by_name("direct-4", 3).unwrap().prove(&tm).unwrap();
+        let (outcome, fell_back) = Outcome::Solved(proof).first_proof(&tm, Some(Duration::ZERO));
+        let progress = DeciderProgress::new(2);
+        let mut outputs = Outputs::append(&dir, false, None)?;
+        let mut attempts = OutputFile::append(dir.attempts("direct-4"))?;
+        for (id, fell_back) in [(0, false), (1, fell_back)] {
+            let attempt = Attempt {
+                id,
+                outcome: outcome.clone(),
+                elapsed: Duration::ZERO,
+                fell_back,
+            };
+            outputs.record(&progress, "direct-4", &mut attempts, &tm, &attempt)?;
+        }
+        drop(outputs);
+        // Both are solved, but only the one whose first proof we never found is listed.
+        let ids = |path| Ok::<_, io::Error>(Index::open(path)?.iter().collect_vec());
+        assert_eq!(ids(dir.index())?, [0, 1]);
+        assert_eq!(ids(dir.nondeterministic())?, [1]);
+        std::fs::remove_dir_all(&path)
+    }
+}
@@ -0,0 +1,143 @@
+//! Export a `Proof` as a self-contained Coq file, which re-checks it with `vm_compute`.
+//! The file defines the TM's rules and the proof's data as plain lists of `nat`s, followed by
+//! executable versions of the conditions in `core/proof.rs`: NFA states are lists of successors,
+//! vectors are lists of states, and the closure conditions are checked for every DFA state `q`.
+//! (Connecting these conditions to non-halting is the job of the soundness argument in README.md.)
+
+use crate::core::{Machine, Proof, RowVector, Rule, Side};
+
+/// The conditions to check, in terms of the definitions `to_coq()` outputs before it.
+const CHECKER: &str = r#"
+Definition mem (x : nat) (v : list nat) : bool := existsb (Nat.eqb x) v.
+Definition incl (v w : list nat) : bool := forallb (fun x => mem x w) v.
+Definition all_below (n : nat) (v : list nat) : bool := forallb (fun x => Nat.ltb x n) v.
+Definition dfa_states : nat := length dfa.
+Definition nfa_states : nat := length (nth 0 nfa []).
+Definition dfa_step (q b : nat) : nat := nth b (nth q dfa []) 0.
+Definition nfa_step (i b : nat) : list nat := nth i (nth b nfa []) [].
+Definition nfa_step_vec (v : list nat) (b : nat) : list nat := flat_map (fun i => nfa_step i b) v.
+Definition accepts (v : list nat) : bool := existsb (fun i => mem i accepted) v.
+Definition start (q f : nat) : nat := q * tm_states + f.
+
+(* The DFA is well-formed and ignores leading zeros. *)
+Definition dfa_ok : bool :=
+  Nat.ltb 0 dfa_states
+  && forallb (fun row => Nat.eqb (length row) symbols && all_below dfa_states row) dfa
+  && Nat.eqb (dfa_step 0 0) 0.
+
+(* The NFA is well-formed, has room for the states `start q f`, and ignores trailing zeros. *)
+Definition nfa_ok : bool :=
+  Nat.eqb (length nfa) symbols
+  && forallb (fun m => Nat.eqb (length m) nfa_states && forallb (all_below nfa_states) m) nfa
+  && all_below nfa_states accepted
+  && Nat.leb (dfa_states * tm_states) nfa_states
+  && forallb (fun i => Bool.eqb (accepts (nfa_step i 0)) (mem i accepted)) (seq 0 nfa_states).
+
+(* The steady state is accepted, and preserved by every transition. *)
+Definition steady_ok : bool :=
+  all_below nfa_states steady
+  && accepts steady
+  && forallb (fun b => incl steady (nfa_step_vec steady b)) (seq 0 symbols).
+
+(* The initial configuration is rejected. *)
+Definition start_ok : bool := negb (mem (start 0 0) accepted).
+
+(* If the automaton accepts a configuration after a TM step, it accepts the one before it. *)
+Definition closed (q : nat) (x : rule) : bool :=
+  match x with
+  | Halt f r => incl steady (nfa_step (start q f) r)
+  | Move f r w d t =>
+    if Bool.eqb d scan_right then
+      incl [start (dfa_step q w) t] (nfa_step (start q f) r)
+    else
+      forallb (fun b => incl (nfa_step_vec (nfa_step (start q t) b) w)
+                             (nfa_step (start (dfa_step q b) f) r)) (seq 0 symbols)
+  end.
+
+Definition closure_ok : bool :=
+  forallb (fun x => forallb (fun q => closed q x) (seq 0 dfa_states)) tm.
+
+Definition check : bool := dfa_ok && nfa_ok && steady_ok && start_ok && closure_ok.
+
+Theorem far_certificate : check = true.
+Proof. vm_compute. reflexivity. Qed.
+"#;
+
+/// A Coq file which checks `proof` for `tm`.
+pub fn to_coq(tm: &Machine, proof: &Proof) -> String {
+    let a = &proof.automaton;
+    let list = |items: Vec<String>| format!("[{}]", items.join("; "));
+    let vec = |v: RowVector| list(v.into_iter().map(|i| i.to_string()).collect());
+    let rules = tm.rules().map(|rule| match rule {
+        Rule::Halt { f, r } => format!("Halt {} {}", f, r),
+        Rule::Move { f, r, w, d, t } => format!("Move {} {} {} {} {}", f, r, w, d == Side::R, t),
+    });
+    let dfa = (a.dfa.t.iter())
+        .map(|row| list(row.iter().map(|q| q.to_string()).collect()))
+        .collect();
+    let nfa = (a.nfa.t.iter())
+        .map(|m| list(m.into_iter().map(|&v| vec(v)).collect()))
+        .collect();
+    let accepted = list(a.nfa.accepted.into_iter().map(|i| i.to_string()).collect());
+    format!(
+        "(* A finite automata reduction proof that {tm} doesn't halt. *)\n\
+         From Coq Require Import List Bool Arith.\n\
+         Import ListNotations.\n\
+         Local Open Scope bool_scope.\n\
+         \n\
+         (* The TM's rules: in state f reading r, halt, or write w, move (right if d), go to t. *)\n\
+         Inductive rule : Type :=\n\
+         | Halt (f r : nat)\n\
+         | Move (f r w : nat) (d : bool) (t : nat).\n\
+         \n\
+         Definition tm_states : nat := {states}.\n\
+         Definition symbols : nat := {symbols}.\n\
+         Definition tm : list rule := {rules}.\n\
+         \n\
+         (* The proof: scan direction, DFA transitions `dfa[q][b]`, NFA successor lists\n   \
+            `nfa[b][i]`, accepted NFA states, and the accepted steady state. *)\n\
+         Definition scan_right : bool := {scan_right}.\n\
+         Definition dfa : list (list nat) := {dfa}.\n\
+         Definition nfa : list (list (list nat)) := {nfa}.\n\
+         Definition accepted : list nat := {accepted}.\n\
+         Definition steady : list nat := {steady}.\n\
+         {CHECKER}",
+        states = tm.states(),
+        symbols = tm.symbols(),
+        rules = list(rules.collect()),
+        scan_right = a.direction == Side::R,
+        dfa = list(dfa),
+        nfa = list(nfa),
+        steady = vec(proof.steady_state),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{col, row, DFA, NFA};
+
+    #[test]
+    fn test_to_coq() {
+        // A 1-state DFA proof for a TM that bounces between states A and B.
+        let tm: Machine = "1RB---_1LA0LB".parse().unwrap();
+        let mut nfa = NFA::new(3, 2);
+        nfa.t[0][0] = row(0) | row(1);
+        nfa.t[1][2] = row(2);
+        nfa.t[0][2] = row(2);
+        nfa.accepted = col(2);
+        let dfa = DFA {
+            t: vec![vec![0, 0]],
+        };
+        let proof = Proof::new(Side::R, dfa, nfa, row(2));
+        let coq = to_coq(&tm, &proof);
+        assert!(coq.starts_with("(* A finite automata reduction proof that 1RB---_1LA0LB "));
+        assert!(coq.contains("Definition tm : list rule := [Move 0 0 1 true 1; Halt 0 1; "));
+        assert!(coq.contains("Definition dfa : list (list nat) := [[0; 0]].\n"));
+        let nfa = "Definition nfa : list (list (list nat)) := [[[0; 1]; []; [2]]; [[]; []; [2]]].";
+        assert!(coq.contains(nfa));
+        assert!(coq.contains("Definition accepted : list nat := [2].\n"));
+        assert!(coq.contains("Definition steady : list nat := [2].\n"));
+        assert!(coq.ends_with("Proof. vm_compute. reflexivity. Qed.\n"));
+    }
+}
@@ -1,71 +1,173 @@
 //! A seed database as in https://bbchallenge.org/method.
 
 use super::MachineID;
-use crate::core::{Machine, TM_STATES};
+use crate::core::{Machine, MachineRef, MAX_SYMBOLS, MAX_TM_STATES};
+use memmap2::Mmap;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
 use std::fs::File;
-use std::io::{self, BufReader, Read, Seek};
+use std::io::{self, ErrorKind};
 use std::path::Path;
-use zerocopy::FromBytes;
 
-const HEADER_SIZE: i64 = 30;
-const RECORD_SIZE: i64 = 6 * TM_STATES as i64;
+const HEADER_SIZE: usize = 30;
 
 pub struct Database {
-    file: File,
+    map: Mmap,
+    states: usize,
+    symbols: usize,
+    len: usize,
 }
 
 /// A seed database file, as in https://bbchallenge.org/method.
+/// The format doesn't record the TMs' numbers of states and symbols, so the caller must.
+/// The file is memory-mapped, so TMs are read straight from the page cache, by any thread.
 impl Database {
-    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Database> {
-        let file = File::open(path)?;
-        Ok(Database { file })
+    pub fn open<P: AsRef<Path>>(path: P, states: usize, symbols: usize) -> io::Result<Database> {
+        if !(1..=MAX_TM_STATES).contains(&states) || !(2..=MAX_SYMBOLS).contains(&symbols) {
+            let msg = format!(
+                "unsupported TM size: {states} states, {symbols} symbols (see --states, --symbols)"
+            );
+            return Err(io::Error::new(ErrorKind::InvalidData, msg));
+        }
+        let file = File::open(&path)?;
+        let record_size = 3 * states * symbols;
+        let size = file.metadata()?.len() as usize;
+        let len = size.saturating_sub(HEADER_SIZE) / record_size;
+        if size != HEADER_SIZE + len * record_size {
+            let msg = format!(
+                "{}: not a seed DB of {states}-state, {symbols}-symbol TMs (see --states, --symbols)",
+                path.as_ref().display()
+            );
+            return Err(io::Error::new(ErrorKind::InvalidData, msg));
+        }
+        // SAFETY: the map is read-only, and nothing should modify or truncate the seed DB while we
+        // run. (Modified records would at worst be rejected by `MachineRef::from_bytes()`.)
+        let map = unsafe { Mmap::map(&file)? };
+        check_header(&map[..HEADER_SIZE], len).map_err(|msg| {
+            let msg = format!("{}: bad seed DB header: {msg}", path.as_ref().display());
+            io::Error::new(ErrorKind::InvalidData, msg)
+        })?;
+        Ok(Database {
+            map,
+            states,
+            symbols,
+            len,
+        })
     }
 
+    /// The TM with the given ID, without copying it, if it exists (and is valid).
+    pub fn get(&self, id: MachineID) -> Option<MachineRef<'_>> {
+        let record_size = 3 * self.states * self.symbols;
+        let start = HEADER_SIZE + (id as usize).checked_mul(record_size)?;
+        let bytes = self.map.get(start..start + record_size)?;
+        MachineRef::from_bytes(bytes, self.symbols)
+    }
+
+    /// Read the TMs with the given IDs, in order, skipping any that don't exist.
     pub fn read<'a, I: Iterator<Item = MachineID> + 'a>(
         &'a self,
         ids: I,
     ) -> impl Iterator<Item = (MachineID, Machine)> + 'a {
-        let mut reader = BufReader::new(&self.file);
-        let pos = reader.stream_position().unwrap() as i64;
-        Reader {
-            reader,
-            ids,
-            bytes: [0u8; 6 * TM_STATES],
-            pos,
-        }
+        ids.filter_map(|i| Some((i, self.get(i)?.to_machine())))
+    }
+
+    /// Like `read()`, but decoding the TMs in parallel (in rayon's current thread pool).
+    pub fn par_read<'a, I: IntoParallelIterator<Item = MachineID> + 'a>(
+        &'a self,
+        ids: I,
+    ) -> impl ParallelIterator<Item = (MachineID, Machine)> + 'a {
+        (ids.into_par_iter()).filter_map(|i| Some((i, self.get(i)?.to_machine())))
     }
 
     pub fn len(&self) -> usize {
-        ((self.file.metadata().unwrap().len() as i64 - HEADER_SIZE) / RECORD_SIZE) as usize
+        self.len
+    }
+
+    /// The number of states in each TM.
+    pub fn states(&self) -> usize {
+        self.states
     }
-}
 
-/// An iterator of the machines in a database.
-pub struct Reader<'a, I: Iterator<Item = MachineID> + 'a> {
-    reader: BufReader<&'a File>,
-    ids: I,
-    bytes: [u8; 6 * TM_STATES],
-    pos: i64,
+    /// The number of symbols in each TM.
+    pub fn symbols(&self) -> usize {
+        self.symbols
+    }
 }
 
-impl<'a, I: Iterator<Item = MachineID> + 'a> Reader<'a, I> {
-    fn try_seek(&mut self, i: MachineID) -> io::Result<()> {
-        let old = self.pos;
-        let new = HEADER_SIZE + (i as i64) * RECORD_SIZE;
-        self.pos = new + RECORD_SIZE;
-        self.reader.seek_relative(new - old)
+/// Check a seed DB header, for a DB of `len` TMs. It holds the numbers of TMs left undecided by the
+/// time and space limits, and their total (as big-endian `u32`s), then a flag for whether the TMs
+/// are sorted, then zeros. Hand-made DBs may leave it all zero, which we accept.
+fn check_header(header: &[u8], len: usize) -> Result<(), String> {
+    let count = |i: usize| u32::from_be_bytes(header[i..i + 4].try_into().unwrap()) as usize;
+    let (time, space, total) = (count(0), count(4), count(8));
+    if total != 0 && total != len {
+        Err(format!("it counts {total} TMs, but the file has {len}"))
+    } else if time + space != total {
+        Err(format!(
+            "{time} + {space} TMs don't add up to the total, {total}"
+        ))
+    } else if header[12] > 1 || header[13..].iter().any(|&b| b != 0) {
+        Err(String::from("unexpected flags or padding"))
+    } else {
+        Ok(())
     }
 }
 
-impl<'a, I: Iterator<Item = MachineID> + 'a> Iterator for Reader<'a, I> {
-    type Item = (MachineID, Machine);
-    fn next(&mut self) -> Option<Self::Item> {
-        self.ids.next().and_then(|i| {
-            self.try_seek(i)
-                .ok()
-                .and_then(|_| self.reader.read_exact(&mut self.bytes).ok())
-                .and_then(|_| Machine::read_from(&self.bytes as &[u8]))
-                .map(|tm| (i, tm))
-        })
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    const BB2: &str = "1RB1LB_1LA---";
+    const TM_2: &str = "1RB---_1LB0LA";
+
+    /// Write a DB with the given header counts and TMs, and open it.
+    fn database(name: &str, counts: [u32; 3], tms: &[&str]) -> io::Result<Database> {
+        let path = std::env::temp_dir().join(format!("far_test_db_{}_{name}", std::process::id()));
+        let mut file = File::create(&path)?;
+        for count in counts {
+            file.write_all(&count.to_be_bytes())?;
+        }
+        file.write_all(&[1])?; // sorted
+        file.write_all(&[0u8; HEADER_SIZE - 13])?;
+        for tm in tms {
+            file.write_all(tm.parse::<Machine>().unwrap().as_bytes())?;
+        }
+        drop(file);
+        let db = Database::open(&path, 2, 2);
+        std::fs::remove_file(&path)?;
+        db
+    }
+
+    #[test]
+    fn test_read() {
+        let db = database("read", [1, 1, 2], &[BB2, TM_2]).unwrap();
+        assert_eq!(db.len(), 2);
+        assert_eq!(db.get(1).map(|tm| tm.to_string()), Some(String::from(TM_2)));
+        assert_eq!(db.get(2), None);
+        let tms: Vec<(MachineID, String)> = (db.read([1, 7, 0].into_iter()))
+            .map(|(i, tm)| (i, tm.to_string()))
+            .collect();
+        assert_eq!(tms, [(1, String::from(TM_2)), (0, String::from(BB2))]);
+        let par: Vec<(MachineID, Machine)> = db.par_read(vec![1, 7, 0]).collect();
+        assert_eq!(par, db.read([1, 7, 0].into_iter()).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_header() {
+        assert!(database("zeros", [0, 0, 0], &[BB2]).is_ok());
+        for (name, counts) in [("count", [1, 1, 2]), ("sum", [1, 1, 1])] {
+            let e = database(name, counts, &[BB2]).err().unwrap();
+            assert_eq!(e.kind(), ErrorKind::InvalidData);
+        }
+        // A 3-state TM isn't a whole number of 2-state records.
+        let e = database("size", [0, 0, 0], &["1RB1LB_1LA---_---1RA"])
+            .err()
+            .unwrap();
+        assert!(e.to_string().contains("--states"));
+        // Sizes which make no sense are refused before reading anything.
+        for (states, symbols) in [(0, 2), (2, 0), (2, 1), (MAX_TM_STATES + 1, 2)] {
+            let e = Database::open("no-such-db", states, symbols).err().unwrap();
+            assert_eq!(e.kind(), ErrorKind::InvalidData);
+        }
     }
 }
@@ -0,0 +1,150 @@
+//! Render a proof's automata in Graphviz DOT format, e.g. to debug or explain a proof.
+//! DFA states are `q0, q1, ...`; NFA states are numbered, except that `nfa_start(q, f)` is labeled
+//! with DFA state `q` and the TM state letter `f`. Accepted NFA states get double circles, and the
+//! steady state is filled in. Parallel transitions are merged into one edge labeled by all symbols.
+
+use crate::core::{
+    col, nfa_start, row, DFAState, Machine, NFAState, Proof, RowVector, TMState, TapeAutomaton,
+    DFA, NFA,
+};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// A DOT graph of the DFA.
+pub fn dfa_to_dot(dfa: &DFA) -> String {
+    graph("dfa", &dfa_body(dfa))
+}
+
+/// A DOT graph of the NFA (of a proof about TMs with `tm_states` states).
+pub fn nfa_to_dot(nfa: &NFA, dfa_states: usize, tm_states: usize, steady: RowVector) -> String {
+    graph("nfa", &nfa_body(nfa, dfa_states, tm_states, steady))
+}
+
+/// A DOT graph of the whole `TapeAutomaton` in `proof` for `tm`: the DFA and NFA side by side, with
+/// dashed edges from each DFA state `q` to the NFA states `nfa_start(q, f)`.
+pub fn to_dot(tm: &Machine, proof: &Proof) -> String {
+    let TapeAutomaton {
+        direction,
+        dfa,
+        nfa,
+    } = &proof.automaton;
+    let mut body = format!("  label=\"{} (scanning {})\";\n", tm, direction);
+    let nfa_lines = nfa_body(nfa, dfa.len(), tm.states(), proof.steady_state);
+    for (name, label, lines) in [("dfa", "DFA", dfa_body(dfa)), ("nfa", "NFA", nfa_lines)] {
+        writeln!(body, "  subgraph cluster_{} {{\n    label={};", name, label).unwrap();
+        body.extend(lines.lines().map(|line| format!("  {}\n", line)));
+        body.push_str("  }\n");
+    }
+    for q in 0..dfa.len() {
+        for f in 0..tm.states() {
+            let s = nfa_start(q as DFAState, f as TMState, tm.states());
+            let tm_state = (b'A' + f as u8) as char;
+            let style = "style=dashed, color=gray";
+            writeln!(
+                body,
+                "  d{} -> n{} [label=\"{}\", {}];",
+                q, s, tm_state, style
+            )
+            .unwrap();
+        }
+    }
+    graph("far", &body)
+}
+
+fn graph(name: &str, body: &str) -> String {
+    format!("digraph {} {{\n  rankdir=LR;\n{}}}\n", name, body)
+}
+
+/// Write edges, merging parallel ones: `edges` yields (from, symbol, to) triples.
+fn write_edges(out: &mut String, prefix: char, edges: impl Iterator<Item = (usize, u8, usize)>) {
+    let mut labels = BTreeMap::<(usize, usize), Vec<String>>::new();
+    for (from, b, to) in edges {
+        labels.entry((from, to)).or_default().push(b.to_string());
+    }
+    for ((from, to), symbols) in labels {
+        let label = symbols.join(",");
+        writeln!(
+            out,
+            "  {p}{} -> {p}{} [label=\"{}\"];",
+            from,
+            to,
+            label,
+            p = prefix
+        )
+        .unwrap();
+    }
+}
+
+fn dfa_body(dfa: &DFA) -> String {
+    let mut out = String::from("  node [shape=circle];\n");
+    for q in 0..dfa.len() {
+        writeln!(out, "  d{} [label=\"q{}\"];", q, q).unwrap();
+    }
+    let edges = (dfa.t.iter().enumerate())
+        .flat_map(|(q, row)| (row.iter().enumerate()).map(move |(b, &s)| (q, b as u8, s as usize)));
+    write_edges(&mut out, 'd', edges);
+    out
+}
+
+fn nfa_body(nfa: &NFA, dfa_states: usize, tm_states: usize, steady: RowVector) -> String {
+    let mut out = String::from("  node [shape=circle];\n");
+    for s in 0..nfa.len() {
+        let label = if s < dfa_states * tm_states {
+            format!(
+                "{}{}",
+                s / tm_states,
+                (b'A' + (s % tm_states) as u8) as char
+            )
+        } else {
+            s.to_string()
+        };
+        let mut attrs = format!("label=\"{}\"", label);
+        if nfa.accepted >= col(s as NFAState) {
+            attrs.push_str(", shape=doublecircle");
+        }
+        if steady >= row(s as NFAState) {
+            attrs.push_str(", style=filled, fillcolor=lightblue");
+        }
+        writeln!(out, "  n{} [{}];", s, attrs).unwrap();
+    }
+    let edges = (nfa.t.iter().enumerate()).flat_map(|(b, m)| {
+        (0..nfa.len()).flat_map(move |s| {
+            (m[s as NFAState].into_iter()).map(move |t| (s, b as u8, t as usize))
+        })
+    });
+    write_edges(&mut out, 'n', edges);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::Side;
+
+    #[test]
+    fn test_to_dot() {
+        let tm: Machine = "1RB---_1LA0LB".parse().unwrap();
+        let mut nfa = NFA::new(3, 2);
+        nfa.t[0][0] = row(0) | row(1);
+        nfa.t[1][2] = row(2);
+        nfa.t[0][2] = row(2);
+        nfa.accepted = col(2);
+        let dfa = DFA {
+            t: vec![vec![0, 0]],
+        };
+        let proof = Proof::new(Side::R, dfa, nfa, row(2));
+        let dot = dfa_to_dot(&proof.automaton.dfa);
+        assert!(dot.starts_with("digraph dfa {\n"));
+        assert!(dot.contains("  d0 -> d0 [label=\"0,1\"];\n"));
+        let dot = to_dot(&tm, &proof);
+        assert!(dot.contains("    n0 [label=\"0A\"];\n"));
+        assert!(dot.contains("    n1 [label=\"0B\"];\n"));
+        let steady =
+            "    n2 [label=\"2\", shape=doublecircle, style=filled, fillcolor=lightblue];\n";
+        assert!(dot.contains(steady));
+        assert!(dot.contains("    n0 -> n0 [label=\"0\"];\n    n0 -> n1 [label=\"0\"];\n"));
+        assert!(dot.contains("    n2 -> n2 [label=\"0,1\"];\n"));
+        assert!(dot.contains("  d0 -> n1 [label=\"B\", style=dashed, color=gray];\n"));
+        assert!(dot.ends_with("}\n"));
+    }
+}
@@ -1,43 +1,434 @@
 //! A Decider Verification File -- as in https://github.com/TonyGuil/bbchallenge.
 
 use super::MachineID;
-use crate::core::{Side, DFA};
+use crate::core::{max_dfa, NFAState, Proof, Side, DFA, MAX_NFA, NFA};
+use std::collections::HashMap;
 use std::fs::{File, OpenOptions};
-use std::io::{self, BufWriter, Write};
-use std::path::Path;
+use std::io::{self, BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
 
-/// Magic value for specifying the "VerificationEntry" is from this program.
+/// Magic value for specifying the "VerificationEntry" is from this program (DFA only).
 const DECIDER_TYPE: u32 = 10;
+/// Magic value for specifying the "VerificationEntry" is from this program (complete `Proof`).
+const DECIDER_TYPE_FULL: u32 = 12;
+/// Size of the "nEntries" header.
+const HEADER_SIZE: u64 = 4;
+
+/// A "VerificationEntry": `(SeedDatabaseIndex, DeciderType, DeciderSpecificInfo)`.
+pub type DvfEntry = (MachineID, u32, Vec<u8>);
+
+/// The proof data in one of our entries: just the `TapeAutomaton` direction and DFA, or everything.
+pub enum DvfProof {
+    DFA(Side, DFA),
+    Full(Proof),
+}
 
 /// An output file listing decided machine indexes.
 pub struct DeciderVerificationFile {
     out: BufWriter<File>,
+    path: PathBuf,
+    full_proofs: bool,
 }
 
 impl DeciderVerificationFile {
-    /// Open the given DVF file for appending. (The "nEntries" value is left as 0.)
-    pub fn append<P: AsRef<Path>>(path: P) -> io::Result<DeciderVerificationFile> {
+    /// Open the given DVF file for appending. (The "nEntries" value is left stale until `finalize()`.)
+    /// If `full_proofs` is set, entries will record the complete `Proof` rather than just the DFA.
+    pub fn append<P: AsRef<Path>>(
+        path: P,
+        full_proofs: bool,
+    ) -> io::Result<DeciderVerificationFile> {
         if let Ok(mut new_file) = OpenOptions::new().write(true).create_new(true).open(&path) {
-            new_file.write_all(&[0u8; 4])?;
+            new_file.write_all(&[0u8; HEADER_SIZE as usize])?;
         }
-        let inner = OpenOptions::new().append(true).open(path)?;
+        let inner = OpenOptions::new().append(true).open(&path)?;
         let out = BufWriter::new(inner);
-        Ok(DeciderVerificationFile { out })
+        let path = path.as_ref().to_path_buf();
+        Ok(DeciderVerificationFile {
+            out,
+            path,
+            full_proofs,
+        })
     }
 
     /// Mark the given machine as solved.
-    pub fn insert(&mut self, id: MachineID, direction: Side, dfa: &DFA) -> io::Result<()> {
-        for int in [id, DECIDER_TYPE, (1 + 2 * dfa.len()) as u32] {
-            self.out.write_all(&int.to_be_bytes())?;
+    pub fn insert(&mut self, id: MachineID, proof: &Proof) -> io::Result<()> {
+        let (decider_type, info) = if self.full_proofs {
+            (DECIDER_TYPE_FULL, Self::encode_full(proof))
+        } else {
+            (DECIDER_TYPE, Self::encode(proof))
+        };
+        write_entry(&mut self.out, &(id, decider_type, info))?;
+        self.out.flush()
+    }
+
+    /// Count the entries in the file (including any previous runs' entries), and patch the
+    /// "nEntries" header to match. Further inserts are allowed, but make the count stale again.
+    pub fn finalize(&mut self) -> io::Result<u32> {
+        self.out.flush()?;
+        let n_entries =
+            DvfReader::open(&self.path)?.try_fold(0u32, |n, entry| entry.map(|_| n + 1))?;
+        let mut file = OpenOptions::new().write(true).open(&self.path)?;
+        file.write_all(&n_entries.to_be_bytes())?;
+        Ok(n_entries)
+    }
+
+    /// Like `finalize()`, but first rewrite the entries in order of `MachineID` (keeping the file
+    /// order of any duplicates), so the file doesn't depend on the order the TMs were solved in.
+    pub fn finalize_sorted(&mut self) -> io::Result<u32> {
+        self.out.flush()?;
+        let mut entries = DvfReader::open(&self.path)?.collect::<io::Result<Vec<DvfEntry>>>()?;
+        entries.sort_by_key(|&(id, _, _)| id);
+        let mut out = BufWriter::new(File::create(&self.path)?);
+        out.write_all(&(entries.len() as u32).to_be_bytes())?;
+        for entry in entries.iter() {
+            write_entry(&mut out, entry)?;
         }
-        let dir = match direction {
-            Side::R => 0u8,
-            Side::L => 1u8,
+        out.flush()?;
+        self.out = BufWriter::new(OpenOptions::new().append(true).open(&self.path)?);
+        Ok(entries.len() as u32)
+    }
+
+    /// Decode the `DeciderSpecificInfo` of an entry written by `insert()`,
+    /// for a TM with the given numbers of states and symbols. Automata too big for the TM
+    /// (see `max_dfa()`) are refused here, as no prover could have written them.
+    pub fn decode(
+        decider_type: u32,
+        info: &[u8],
+        states: usize,
+        symbols: usize,
+    ) -> Option<DvfProof> {
+        if states == 0 || symbols == 0 {
+            return None;
+        }
+        let (&dir, rest) = info.split_first()?;
+        let direction = match dir {
+            0 => Side::R,
+            1 => Side::L,
+            _ => return None,
         };
-        self.out.write_all(&[dir])?;
-        for pair in &dfa.t {
-            self.out.write_all(pair)?;
+        match decider_type {
+            DECIDER_TYPE => {
+                let n_dfa = rest.len() / symbols;
+                if n_dfa > max_dfa(states) {
+                    return None;
+                }
+                let dfa = decode_dfa(rest, n_dfa, symbols)?;
+                Some(DvfProof::DFA(direction, dfa))
+            }
+            DECIDER_TYPE_FULL => Some(DvfProof::Full(decode_full(
+                direction, rest, states, symbols,
+            )?)),
+            _ => None,
         }
-        self.out.flush()
+    }
+
+    /// Direction, then DFA table.
+    fn encode(proof: &Proof) -> Vec<u8> {
+        let a = &proof.automaton;
+        let mut info = vec![encode_side(a.direction)];
+        info.extend(a.dfa.t.iter().flatten());
+        info
+    }
+
+    /// Direction, DFA and NFA sizes, DFA table, NFA matrices, accepted states, steady state.
+    fn encode_full(proof: &Proof) -> Vec<u8> {
+        let a = &proof.automaton;
+        let mut info = vec![encode_side(a.direction)];
+        info.extend((a.dfa.len() as u16).to_be_bytes());
+        info.extend((a.nfa.len() as u16).to_be_bytes());
+        info.extend(a.dfa.t.iter().flatten());
+        let width = mask_width(a.nfa.len());
+        for m in &a.nfa.t {
+            for &v in m {
+                info.extend(encode_mask(v, width));
+            }
+        }
+        info.extend(encode_mask(a.nfa.accepted, width));
+        info.extend(encode_mask(proof.steady_state, width));
+        info
+    }
+}
+
+fn write_entry<W: Write>(out: &mut W, (id, decider_type, info): &DvfEntry) -> io::Result<()> {
+    for int in [*id, *decider_type, info.len() as u32] {
+        out.write_all(&int.to_be_bytes())?;
+    }
+    out.write_all(info)
+}
+
+fn encode_side(direction: Side) -> u8 {
+    match direction {
+        Side::R => 0u8,
+        Side::L => 1u8,
+    }
+}
+
+/// The number of bytes in a (big-endian) bitmask for an n-state NFA.
+fn mask_width(n: usize) -> usize {
+    n.div_ceil(8)
+}
+
+/// Encode a set of NFA states as a big-endian bitmask: state i is included if bit `1<<i` is set.
+fn encode_mask<V: IntoIterator<Item = NFAState>>(v: V, width: usize) -> Vec<u8> {
+    let mut bytes = vec![0u8; width];
+    for i in v {
+        bytes[width - 1 - (i as usize) / 8] |= 1 << (i % 8);
+    }
+    bytes
+}
+
+/// Decode a set of NFA states from a big-endian bitmask.
+fn decode_mask<V: FromIterator<NFAState>>(bytes: &[u8]) -> V {
+    let bits = bytes.iter().rev().enumerate();
+    bits.flat_map(|(pos, &byte)| {
+        (0..8).filter_map(move |i| (byte >> i & 1 == 1).then_some(8 * pos + i))
+    })
+    .map(|i| i as NFAState)
+    .collect()
+}
+
+fn decode_dfa(table: &[u8], n: usize, symbols: usize) -> Option<DFA> {
+    if table.len() != symbols * n {
+        return None;
+    }
+    let t = table.chunks_exact(symbols).map(<[u8]>::to_vec).collect();
+    Some(DFA { t })
+}
+
+fn decode_full(direction: Side, info: &[u8], states: usize, symbols: usize) -> Option<Proof> {
+    let (sizes, rest) = (info.get(..4)?, info.get(4..)?);
+    let n_dfa = u16::from_be_bytes([sizes[0], sizes[1]]) as usize;
+    let n_nfa = u16::from_be_bytes([sizes[2], sizes[3]]) as usize;
+    if n_dfa > max_dfa(states) || n_nfa > MAX_NFA || n_dfa * states > n_nfa {
+        return None;
+    }
+    let width = mask_width(n_nfa);
+    if rest.len() != symbols * n_dfa + (symbols * n_nfa + 2) * width {
+        return None;
+    }
+    let (table, mut masks) = rest.split_at(symbols * n_dfa);
+    let dfa = decode_dfa(table, n_dfa, symbols)?;
+    let mut nfa = NFA::new(n_nfa, symbols);
+    for m in nfa.t.iter_mut() {
+        for i in 0..n_nfa {
+            m[i as NFAState] = decode_mask(&masks[..width]);
+            masks = &masks[width..];
+        }
+    }
+    nfa.accepted = decode_mask(&masks[..width]);
+    let steady_state = decode_mask(&masks[width..]);
+    Some(Proof::new(direction, dfa, nfa, steady_state))
+}
+
+/// A reader for DVF files (from any decider), which iterates over the `DvfEntry`s in file order.
+/// It can also index the file by `MachineID`, for random access.
+pub struct DvfReader {
+    reader: BufReader<File>,
+    n_entries: u32,
+    offsets: Option<HashMap<MachineID, u64>>,
+}
+
+impl DvfReader {
+    /// Open the given DVF file, positioned at its first entry.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<DvfReader> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut header = [0u8; HEADER_SIZE as usize];
+        reader.read_exact(&mut header)?;
+        let n_entries = u32::from_be_bytes(header);
+        let offsets = None;
+        Ok(DvfReader {
+            reader,
+            n_entries,
+            offsets,
+        })
+    }
+
+    /// The "nEntries" header value. (Not reliable unless the writer called `finalize()`.)
+    pub fn n_entries(&self) -> u32 {
+        self.n_entries
+    }
+
+    /// Move back to the first entry.
+    pub fn rewind(&mut self) -> io::Result<()> {
+        self.reader.seek(SeekFrom::Start(HEADER_SIZE)).map(|_| ())
+    }
+
+    /// Scan the whole file, recording the offset of each `MachineID`'s first entry.
+    /// Returns the number of distinct `MachineID`s. Leaves the position at the end of the file.
+    pub fn index(&mut self) -> io::Result<usize> {
+        let mut offsets = HashMap::new();
+        self.rewind()?;
+        loop {
+            let offset = self.reader.stream_position()?;
+            match self.read_entry()? {
+                Some((id, _, _)) => offsets.entry(id).or_insert(offset),
+                None => break,
+            };
+        }
+        let len = offsets.len();
+        self.offsets = Some(offsets);
+        Ok(len)
+    }
+
+    /// Look up the first entry for `id`, indexing the file first if necessary.
+    /// Afterwards, iteration continues from the entry following the one found.
+    pub fn get(&mut self, id: MachineID) -> io::Result<Option<DvfEntry>> {
+        if self.offsets.is_none() {
+            self.index()?;
+        }
+        match self.offsets.as_ref().and_then(|offsets| offsets.get(&id)) {
+            Some(&offset) => {
+                self.reader.seek(SeekFrom::Start(offset))?;
+                self.read_entry()
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Read one entry, or `None` at a clean end of file.
+    fn read_entry(&mut self) -> io::Result<Option<DvfEntry>> {
+        let mut header = [0u8; 12];
+        match self.reader.read_exact(&mut header[..1]) {
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            other => other?,
+        }
+        self.reader.read_exact(&mut header[1..])?;
+        let int = |i: usize| u32::from_be_bytes(header[4 * i..4 * (i + 1)].try_into().unwrap());
+        let mut info = vec![0u8; int(2) as usize];
+        self.reader.read_exact(&mut info)?;
+        Ok(Some((int(0), int(1), info)))
+    }
+}
+
+impl Iterator for DvfReader {
+    type Item = io::Result<DvfEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_entry().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_proof(dfa: &str) -> Proof {
+        let json = r#"{
+            "automaton": {
+                "direction": "L",
+                "dfa": DFA,
+                "nfa": {"accepted": 1056, "t": [
+                    [384, 128, 512, 8, 128, 1984, 968, 576, 256, 128, 1024],
+                    [1024, 1024, 8, 512, 2, 1024, 1024, 384, 512, 64, 1024]]}},
+            "steady_state": 1024}"#;
+        serde_json::from_str(&json.replace("DFA", dfa)).unwrap()
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("{}-{}.dvf", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let path = temp_path("test-round-trip");
+        let (proof_1, proof_2) = (
+            test_proof("[[0, 0], [0, 0]]"),
+            test_proof("[[0, 1], [1, 1]]"),
+        );
+        let mut dvf = DeciderVerificationFile::append(&path, false).unwrap();
+        dvf.insert(7, &proof_1).unwrap();
+        dvf.insert(3, &proof_2).unwrap();
+        dvf.insert(7, &proof_2).unwrap();
+        assert_eq!(dvf.finalize().unwrap(), 3);
+
+        let mut reader = DvfReader::open(&path).unwrap();
+        assert_eq!(reader.n_entries(), 3);
+        let entries: Vec<DvfEntry> = reader.by_ref().map(Result::unwrap).collect();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[1], (3, DECIDER_TYPE, vec![1, 0, 1, 1, 1]));
+        assert_eq!(reader.index().unwrap(), 2);
+        let (_, decider_type, info) = reader.get(7).unwrap().unwrap();
+        match DeciderVerificationFile::decode(decider_type, &info, 5, 2) {
+            Some(DvfProof::DFA(direction, dfa)) => {
+                assert_eq!((direction, dfa.t), (Side::L, proof_1.automaton.dfa.t))
+            }
+            _ => panic!("expected a DFA-only entry"),
+        }
+        assert_eq!(reader.get(42).unwrap(), None);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_sorted() {
+        let path = temp_path("test-sorted");
+        let (proof_1, proof_2) = (
+            test_proof("[[0, 0], [0, 0]]"),
+            test_proof("[[0, 1], [1, 1]]"),
+        );
+        let mut dvf = DeciderVerificationFile::append(&path, false).unwrap();
+        dvf.insert(7, &proof_1).unwrap();
+        dvf.insert(3, &proof_2).unwrap();
+        dvf.insert(7, &proof_2).unwrap();
+        assert_eq!(dvf.finalize_sorted().unwrap(), 3);
+        dvf.insert(5, &proof_1).unwrap();
+        assert_eq!(dvf.finalize_sorted().unwrap(), 4);
+
+        let mut reader = DvfReader::open(&path).unwrap();
+        assert_eq!(reader.n_entries(), 4);
+        let entries: Vec<DvfEntry> = reader.by_ref().map(Result::unwrap).collect();
+        let ids: Vec<MachineID> = entries.iter().map(|&(id, _, _)| id).collect();
+        assert_eq!(ids, [3, 5, 7, 7]);
+        assert_eq!(entries[2].2, DeciderVerificationFile::encode(&proof_1));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_full_proofs() {
+        let path = temp_path("test-full-proofs");
+        let proof = test_proof("[[0, 1], [1, 1]]");
+        let mut dvf = DeciderVerificationFile::append(&path, true).unwrap();
+        dvf.insert(12345, &proof).unwrap();
+        let (id, decider_type, info) = DvfReader::open(&path).unwrap().next().unwrap().unwrap();
+        assert_eq!((id, decider_type), (12345, DECIDER_TYPE_FULL));
+        // 1 direction byte, 4 size bytes, 4 DFA table bytes, (2 * 11 + 2) 2-byte masks:
+        assert_eq!(info.len(), 1 + 4 + 4 + 24 * 2);
+        match DeciderVerificationFile::decode(decider_type, &info, 5, 2) {
+            Some(DvfProof::Full(decoded)) => assert_eq!(
+                serde_json::to_value(decoded).unwrap(),
+                serde_json::to_value(proof).unwrap()
+            ),
+            _ => panic!("expected a full-proof entry"),
+        }
+        assert!(DeciderVerificationFile::decode(decider_type, &info[1..], 5, 2).is_none());
+        assert!(DeciderVerificationFile::decode(decider_type, &info, 5, 3).is_none());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_oversized_entries() {
+        // Well-formed full-proof entries, for 2-state, 2-symbol TMs, with automata of any size.
+        let entry = |n_dfa: usize, n_nfa: usize| {
+            let mut info = vec![encode_side(Side::R)];
+            info.extend((n_dfa as u16).to_be_bytes());
+            info.extend((n_nfa as u16).to_be_bytes());
+            info.resize(
+                info.len() + 2 * n_dfa + (2 * n_nfa + 2) * mask_width(n_nfa),
+                0,
+            );
+            info
+        };
+        let decode = |info: &[u8]| DeciderVerificationFile::decode(DECIDER_TYPE_FULL, info, 2, 2);
+        assert!(decode(&entry(max_dfa(2), 2 * max_dfa(2))).is_some());
+        assert!(decode(&entry(max_dfa(2) + 1, 2 * max_dfa(2) + 2)).is_none());
+        assert!(decode(&entry(256, 512)).is_none());
+        assert!(decode(&entry(2, 3)).is_none());
+        // Likewise for a DFA-only entry.
+        let mut info = vec![encode_side(Side::R)];
+        info.resize(1 + 2 * (max_dfa(2) + 1), 0);
+        assert!(DeciderVerificationFile::decode(DECIDER_TYPE, &info, 2, 2).is_none());
+        info.truncate(1 + 2 * max_dfa(2));
+        assert!(DeciderVerificationFile::decode(DECIDER_TYPE, &info, 2, 2).is_some());
     }
 }
@@ -1,7 +1,7 @@
 //! An undecided index -- or output file -- as in https://bbchallenge.org/method.
 //! We assume it's allowed to be an unsorted mess.
 
-use super::{MachineID, OWN_INDEX};
+use super::{MachineID, OutputDir};
 use itertools::{EitherOrBoth, Itertools};
 use std::fs::read;
 use std::io::{self, ErrorKind};
@@ -20,8 +20,15 @@ pub struct Index {
 impl Index {
     /// Initialize an index where everything is considered unsolved.
     pub fn new(size: usize) -> Index {
-        let yes = (0..size as MachineID).collect();
-        let no = vec![];
+        Self::from_ids(0..size as MachineID)
+    }
+
+    /// Initialize an index where the given machines are considered unsolved.
+    pub fn from_ids<I: IntoIterator<Item = MachineID>>(ids: I) -> Index {
+        let mut yes: Vec<MachineID> = ids.into_iter().collect();
+        yes.sort();
+        yes.dedup();
+        let (no, size) = (vec![], yes.len());
         Index { yes, no, size }
     }
 
@@ -35,14 +42,29 @@ impl Index {
         Ok(index)
     }
 
-    /// Populate the negative entries from our own output directory.
-    pub fn read_decided(&mut self) -> io::Result<()> {
-        let result = Self::extend_from(&mut self.no, OWN_INDEX);
+    /// Populate the negative entries from our own output directory: TMs proven not to halt,
+    /// and TMs found to halt.
+    pub fn read_decided(&mut self, dir: &OutputDir) -> io::Result<()> {
+        let result = [dir.index(), dir.halts()].into_iter().try_for_each(|path| {
+            match Self::extend_from(&mut self.no, path) {
+                Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+                other => other,
+            }
+        });
         self.clean();
-        match result {
-            Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
-            other => other,
+        result
+    }
+
+    /// List the unsolved `MachineID`s which the named prover's attempt log doesn't rule out.
+    pub fn untried(&self, dir: &OutputDir, prover_name: &str) -> io::Result<Vec<MachineID>> {
+        let mut tried = vec![];
+        match Self::extend_from(&mut tried, dir.attempts(prover_name)) {
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+            _ => {}
         }
+        tried.sort();
+        tried.dedup();
+        Ok(Self::difference(&self.yes, &tried))
     }
 
     /// Yield all unsolved `MachineID`s.
@@ -77,15 +99,47 @@ impl Index {
     fn clean(&mut self) {
         self.no.sort();
         self.no.dedup();
-        self.yes = self
-            .yes
-            .iter()
+        self.yes = Self::difference(&self.yes, &self.no);
+    }
+
+    /// Internal function: the elements of sorted `a` which aren't in sorted `b`.
+    fn difference(a: &[MachineID], b: &[MachineID]) -> Vec<MachineID> {
+        a.iter()
             .copied()
-            .merge_join_by(self.no.iter().copied(), MachineID::cmp)
+            .merge_join_by(b.iter().copied(), MachineID::cmp)
             .filter_map(|lr| match lr {
                 EitherOrBoth::Left(id) => Some(id),
                 _ => None,
             })
-            .collect();
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::OutputFile;
+
+    #[test]
+    fn test_untried() -> io::Result<()> {
+        let path = std::env::temp_dir().join(format!("far_test_index_{}", std::process::id()));
+        std::fs::create_dir_all(&path)?;
+        let dir = OutputDir::new(&path);
+        let write = |path, ids: &[MachineID]| -> io::Result<()> {
+            let mut out = OutputFile::append(path)?;
+            ids.iter().try_for_each(|&i| out.insert(i))
+        };
+        write(dir.index(), &[3, 1])?;
+        write(dir.halts(), &[5])?;
+        write(dir.attempts("direct-1"), &[4, 0, 4, 9])?;
+        let mut index = Index::new(7);
+        // No log means nothing was tried.
+        assert_eq!(index.untried(&dir, "direct-2")?, [0, 1, 2, 3, 4, 5, 6]);
+        assert_eq!(index.untried(&dir, "direct-1")?, [1, 2, 3, 5, 6]);
+        index.read_decided(&dir)?;
+        assert_eq!(index.len_solved(), 3);
+        assert_eq!(index.untried(&dir, "direct-1")?, [2, 6]);
+        assert_eq!(index.untried(&dir, "direct-2")?, [0, 2, 4, 6]);
+        std::fs::remove_dir_all(&path)
     }
 }
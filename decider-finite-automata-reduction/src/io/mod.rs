@@ -1,15 +1,110 @@
 //! An implementation of the DB and index formats from https://bbchallenge.org/method.
 
+mod coq;
 mod db;
+mod dot;
 mod dvf;
 mod index;
 mod output;
+mod proof_cache;
+mod report;
+mod source;
 
+pub use coq::to_coq;
 pub use db::Database;
-pub use dvf::DeciderVerificationFile;
+pub use dot::{dfa_to_dot, nfa_to_dot, to_dot};
+pub use dvf::{DeciderVerificationFile, DvfEntry, DvfProof, DvfReader};
 pub use index::Index;
 pub use output::OutputFile;
+pub use proof_cache::ProofCache;
+pub use report::{ReportFile, ReportRecord};
+pub use source::{ListFormat, MachineList, MachineSource};
+use std::path::{Path, PathBuf};
 
 pub type MachineID = u32;
-pub const OWN_INDEX: &str = "output/finite_automata_reduction.index";
-pub const OWN_DVF: &str = "output/finite_automata_reduction.dvf";
+
+/// Where a run keeps its own output files: the index of TMs it solved, its DVF, and so on.
+#[derive(Clone, Debug)]
+pub struct OutputDir(PathBuf);
+
+impl Default for OutputDir {
+    fn default() -> Self {
+        OutputDir::new("output")
+    }
+}
+
+impl OutputDir {
+    pub fn new<P: Into<PathBuf>>(path: P) -> OutputDir {
+        OutputDir(path.into())
+    }
+
+    /// The output directory for a run on the TMs from the given `--input` list: a subdirectory
+    /// named after the list (e.g. `output/list-holdouts` for `holdouts.txt`, or `output/list-stdin`
+    /// for "-"), since the lists' IDs mean nothing in the DB, or in another list.
+    pub fn for_list(input: &str) -> OutputDir {
+        let name = match Path::new(input).file_stem() {
+            Some(stem) if input != "-" => stem.to_string_lossy(),
+            _ => "stdin".into(),
+        };
+        OutputDir(OutputDir::default().0.join(format!("list-{}", name)))
+    }
+
+    /// The directory itself.
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    /// The index of TMs we've proven not to halt.
+    pub fn index(&self) -> PathBuf {
+        self.file("index")
+    }
+
+    /// The Decider Verification File, with the proofs.
+    pub fn dvf(&self) -> PathBuf {
+        self.file("dvf")
+    }
+
+    /// The index of TMs which some prover ran out of time on.
+    pub fn timeouts(&self) -> PathBuf {
+        self.file("timeouts")
+    }
+
+    /// The index of TMs whose `--deterministic` first-proof search ran out of time, so their
+    /// recorded proofs may differ between runs.
+    pub fn nondeterministic(&self) -> PathBuf {
+        self.file("nondeterministic")
+    }
+
+    /// The index of TMs we've found to halt.
+    pub fn halts(&self) -> PathBuf {
+        self.file("halts")
+    }
+
+    /// Where we log the TMs the named prover has tried and failed to solve (without timing out).
+    pub fn attempts(&self, prover_name: &str) -> PathBuf {
+        self.file(&format!("{}.attempted", prover_name))
+    }
+
+    fn file(&self, extension: &str) -> PathBuf {
+        self.0
+            .join(format!("finite_automata_reduction.{}", extension))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_output_dir() {
+        let output = Path::new("output");
+        let (db, list) = (
+            OutputDir::default(),
+            OutputDir::for_list("lists/holdouts.txt"),
+        );
+        assert_eq!(db.dvf(), output.join("finite_automata_reduction.dvf"));
+        let dvf = output.join("list-holdouts/finite_automata_reduction.dvf");
+        assert_eq!(list.dvf(), dvf);
+        assert_eq!(OutputDir::for_list("-").path(), output.join("list-stdin"));
+    }
+}
@@ -0,0 +1,161 @@
+//! A persistent store of proofs, so a TM proven once (or any TM equivalent to it) needn't be
+//! searched again. The file is newline-delimited JSON: `{"machine": "1RB...", "proof": {...}}`.
+//! Proofs are stored for each TM's `Machine::canonical()` form, and every proof is validated when
+//! it's loaded (and again for the TM it's transferred to), automata sizes included, so a corrupted
+//! or hand-edited cache can cost time but never produce a false decision.
+
+use crate::core::{Machine, Proof};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+
+/// A line of the cache file.
+#[derive(Serialize, Deserialize)]
+struct CacheEntry {
+    machine: Machine,
+    proof: Proof,
+}
+
+/// A proof cache, opened for reading and appending.
+pub struct ProofCache {
+    proofs: HashMap<Machine, Proof>,
+    out: File,
+    rejected: usize,
+}
+
+impl ProofCache {
+    /// Load the given cache file (creating it if absent), skipping any entry which doesn't parse
+    /// or whose proof doesn't validate.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<ProofCache> {
+        let mut cache = ProofCache {
+            proofs: HashMap::new(),
+            out: OpenOptions::new().append(true).create(true).open(&path)?,
+            rejected: 0,
+        };
+        for line in BufReader::new(File::open(&path)?).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str::<CacheEntry>(&line) {
+                Ok(entry) if entry.proof.validate(&entry.machine).is_ok() => {
+                    cache.remember(&entry.machine, &entry.proof);
+                }
+                _ => cache.rejected += 1,
+            }
+        }
+        Ok(cache)
+    }
+
+    /// How many cached proofs there are, for distinct classes of equivalent TMs.
+    pub fn len(&self) -> usize {
+        self.proofs.len()
+    }
+
+    /// How many entries of the file were ignored as unreadable or invalid.
+    pub fn rejected(&self) -> usize {
+        self.rejected
+    }
+
+    /// A proof for `tm`, transferred from the cached proof for its class -- if there's a valid one.
+    pub fn get(&self, tm: &Machine) -> Option<Proof> {
+        let (canonical, symmetry) = tm.canonical();
+        let proof = self.proofs.get(&canonical)?;
+        let proof = symmetry.inverse().apply_proof(proof, &canonical);
+        proof.validate(tm).ok().map(|()| proof)
+    }
+
+    /// Add a (valid) proof for `tm` to the cache, unless its class already has one.
+    pub fn insert(&mut self, tm: &Machine, proof: &Proof) -> io::Result<()> {
+        if let Some((canonical, proof)) = self.remember(tm, proof) {
+            let entry = CacheEntry {
+                machine: canonical,
+                proof,
+            };
+            let mut line = serde_json::to_string(&entry)?;
+            line.push('\n');
+            self.out.write_all(line.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Store the proof for `tm`'s canonical form, returning it if it's new.
+    fn remember(&mut self, tm: &Machine, proof: &Proof) -> Option<(Machine, Proof)> {
+        let (canonical, symmetry) = tm.canonical();
+        if self.proofs.contains_key(&canonical) {
+            return None;
+        }
+        let proof = symmetry.apply_proof(proof, tm);
+        self.proofs.insert(canonical.clone(), proof.clone());
+        Some((canonical, proof))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{row, Side, Symmetry, DFA, MAX_NFA, NFA};
+    use crate::provers::{DirectProver, Prover, ProverOptions};
+    use std::str::FromStr;
+
+    #[test]
+    fn test_cache() {
+        let path = std::env::temp_dir().join(format!("test-cache-{}.ndjson", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let tm = Machine::from_str("1RB1LC_0LA0LB_1RC0RA").unwrap();
+        let proof = DirectProver::new(3)
+            .prove(&tm)
+            .expect("solvable at depth 3");
+        let mut cache = ProofCache::open(&path).unwrap();
+        cache.insert(&tm, &proof).unwrap();
+        cache.insert(&tm, &proof).unwrap();
+        assert_eq!(cache.len(), 1);
+
+        // Garbage, and a proof for the wrong TM, are ignored when the cache is reloaded.
+        let other = Machine::from_str("1RB---_0RC---_0RD---_0RE---_0LE1RB").unwrap();
+        let bad = CacheEntry {
+            machine: other,
+            proof: proof.clone(),
+        };
+        let mut out = OpenOptions::new().append(true).open(&path).unwrap();
+        writeln!(out, "{{\"machine\": \"1RB\"").unwrap();
+        writeln!(out, "{}", serde_json::to_string(&bad).unwrap()).unwrap();
+        let cache = ProofCache::open(&path).unwrap();
+        assert_eq!((cache.len(), cache.rejected()), (1, 2));
+
+        // The proof is found for equivalent TMs, and transformed to suit them.
+        let variant = Symmetry {
+            mirror: true,
+            states: vec![0, 2, 1],
+        }
+        .apply(&tm);
+        for tm in [&tm, &variant] {
+            assert_eq!(cache.get(tm).map(|proof| proof.validate(tm)), Some(Ok(())));
+        }
+        assert!(cache.get(&bad.machine).is_none());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_oversized_entry() {
+        // A hand-made "proof" for a halting TM, whose automata are too big for its state numbers.
+        let path = std::env::temp_dir().join(format!("test-forged-{}.ndjson", std::process::id()));
+        let tm = Machine::from_str("1RB1LB_1LA---").unwrap();
+        let mut nfa = NFA::new(2 * MAX_NFA, 2);
+        nfa.accepted = (1..=255).collect();
+        for m in nfa.t.iter_mut() {
+            (1..=255).for_each(|i| m[i] = row(1));
+        }
+        let forged = CacheEntry {
+            machine: tm.canonical().0,
+            proof: Proof::new(Side::R, DFA::new(256, 2), nfa, row(1)),
+        };
+        std::fs::write(&path, serde_json::to_string(&forged).unwrap() + "\n").unwrap();
+        let cache = ProofCache::open(&path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!((cache.len(), cache.rejected()), (0, 1));
+        assert!(cache.get(&tm).is_none());
+    }
+}
@@ -0,0 +1,113 @@
+//! A run report: one JSON object per line, per attempt by a prover to solve a TM.
+
+use super::MachineID;
+use crate::core::{BadProof, Counterexample};
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+
+/// A line of the report.
+#[derive(Debug, Serialize)]
+pub struct ReportRecord<'a> {
+    /// The TM's ID in the DB.
+    pub id: MachineID,
+    /// The prover's strategy name, e.g. "direct".
+    pub prover: &'a str,
+    /// The prover's search depth, if its name gives one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub depth: Option<usize>,
+    /// "solved", "failed", "timeout", or "rejected".
+    pub outcome: &'a str,
+    /// Time spent by the prover, in seconds. (Excludes checking the proof.)
+    pub seconds: f64,
+    /// Proof size, if solved.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dfa_states: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nfa_states: Option<usize>,
+    /// Why the proof was rejected, if it was.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<&'a BadProof>,
+    /// A configuration demonstrating the error, if one was found.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub counterexample: Option<&'a Counterexample>,
+}
+
+impl ReportRecord<'_> {
+    /// Split a prover's name, e.g. "direct-3", into its strategy and depth (if it ends in one).
+    pub fn split_prover_name(name: &str) -> (&str, Option<usize>) {
+        match name
+            .rsplit_once('-')
+            .map(|(prover, depth)| (prover, depth.parse()))
+        {
+            Some((prover, Ok(depth))) => (prover, Some(depth)),
+            _ => (name, None),
+        }
+    }
+}
+
+/// An output file, listing `ReportRecord`s as newline-delimited JSON.
+pub struct ReportFile {
+    out: File,
+}
+
+impl ReportFile {
+    /// Open the given report file for appending.
+    pub fn append<P: AsRef<Path>>(path: P) -> io::Result<ReportFile> {
+        let out = OpenOptions::new().append(true).create(true).open(path)?;
+        Ok(ReportFile { out })
+    }
+
+    /// Add a record to the report.
+    pub fn insert(&mut self, record: &ReportRecord) -> io::Result<()> {
+        let mut line = serde_json::to_string(record)?;
+        line.push('\n');
+        self.out.write_all(line.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format() {
+        let mut record = ReportRecord {
+            id: 7,
+            prover: "direct",
+            depth: Some(3),
+            outcome: "rejected",
+            seconds: 0.5,
+            dfa_states: None,
+            nfa_states: None,
+            error: Some(&BadProof::BadStart),
+            counterexample: None,
+        };
+        let json = r#"{"id":7,"prover":"direct","depth":3,"outcome":"rejected","seconds":0.5,"error":"BadStart"}"#;
+        assert_eq!(serde_json::to_string(&record).unwrap(), json);
+        (
+            record.outcome,
+            record.dfa_states,
+            record.nfa_states,
+            record.error,
+        ) = ("solved", Some(2), Some(11), None);
+        let json = r#"{"id":7,"prover":"direct","depth":3,"outcome":"solved","seconds":0.5,"dfa_states":2,"nfa_states":11}"#;
+        assert_eq!(serde_json::to_string(&record).unwrap(), json);
+        record.depth = None;
+        let json = r#"{"id":7,"prover":"direct","outcome":"solved","seconds":0.5,"dfa_states":2,"nfa_states":11}"#;
+        assert_eq!(serde_json::to_string(&record).unwrap(), json);
+    }
+
+    #[test]
+    fn test_split_prover_name() {
+        let split = ReportRecord::split_prover_name;
+        assert_eq!(split("direct-3"), ("direct", Some(3)));
+        assert_eq!(
+            split("mitm_dfa@my-solver-12"),
+            ("mitm_dfa@my-solver", Some(12))
+        );
+        assert_eq!(split("mitm_dfa@my-solver"), ("mitm_dfa@my-solver", None));
+        assert_eq!(split("direct"), ("direct", None));
+    }
+}
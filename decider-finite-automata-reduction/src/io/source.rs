@@ -0,0 +1,298 @@
+//! Where a run gets its TMs: the seed DB, or a list of TMs in the standard text format.
+//! A list is a text file (or standard input) with one TM per line, or a CSV file with TMs in one
+//! column. Each TM's `MachineID` is the ID supplied with it, if any, or else its line number
+//! (counting from 1, including any header and blank lines) -- so results refer back to the input.
+
+use super::{Database, Index, MachineID};
+use crate::core::{Machine, MachineRef};
+use rayon::iter::{Either, IntoParallelIterator, ParallelIterator};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, ErrorKind};
+
+/// How a `MachineList` is laid out.
+#[derive(Clone, Debug, Default)]
+pub enum ListFormat {
+    /// One TM per line, optionally with a numeric ID before or after it (separated by whitespace
+    /// or a comma). Blank lines and lines starting with `#` are skipped.
+    #[default]
+    Text,
+    /// CSV with a header row: TMs come from the named column, and IDs from the other one (if any).
+    Csv {
+        column: String,
+        id_column: Option<String>,
+    },
+}
+
+/// TMs read from a text list, by ID.
+#[derive(Clone, Debug)]
+pub struct MachineList {
+    tms: BTreeMap<MachineID, Machine>,
+    states: usize,
+    symbols: usize,
+}
+
+impl MachineList {
+    /// Read the list from the given file, or standard input if the path is `-`.
+    /// Every TM must have the given numbers of states and symbols.
+    pub fn open(
+        path: &str,
+        format: &ListFormat,
+        states: usize,
+        symbols: usize,
+    ) -> io::Result<MachineList> {
+        if path == "-" {
+            Self::read(io::stdin().lock(), format, states, symbols)
+        } else {
+            Self::read(BufReader::new(File::open(path)?), format, states, symbols)
+        }
+    }
+
+    /// Read the list. Any line we can't make sense of is an error, rather than a silent omission.
+    pub fn read<R: BufRead>(
+        input: R,
+        format: &ListFormat,
+        states: usize,
+        symbols: usize,
+    ) -> io::Result<MachineList> {
+        let mut list = MachineList {
+            tms: BTreeMap::new(),
+            states,
+            symbols,
+        };
+        // For CSV: the TM and ID columns, once we've read the header.
+        let mut columns: Option<(usize, Option<usize>)> = None;
+        for (i, line) in input.lines().enumerate() {
+            let (line, line_number) = (line?, i as MachineID + 1);
+            let bad = |msg: String| {
+                io::Error::new(ErrorKind::InvalidData, format!("line {line_number}: {msg}"))
+            };
+            let (tm, id) = match format {
+                ListFormat::Text => {
+                    let line = line.trim();
+                    if line.is_empty() || line.starts_with('#') {
+                        continue;
+                    }
+                    let fields = line.split(|c: char| c == ',' || c.is_whitespace());
+                    let fields: Vec<&str> = fields.filter(|f| !f.is_empty()).collect();
+                    match fields[..] {
+                        [tm] => (tm.to_string(), None),
+                        [a, b] if a.parse::<MachineID>().is_ok() => {
+                            (b.to_string(), Some(a.to_string()))
+                        }
+                        [a, b] => (a.to_string(), Some(b.to_string())),
+                        _ => return Err(bad(format!("expected a TM and optional ID: '{line}'"))),
+                    }
+                }
+                ListFormat::Csv { column, id_column } => {
+                    let fields = split_csv(&line);
+                    let Some((tm_col, id_col)) = columns else {
+                        let find = |name: &str| {
+                            let col = fields.iter().position(|f| f.trim() == name);
+                            col.ok_or_else(|| bad(format!("no column named '{name}'")))
+                        };
+                        columns =
+                            Some((find(column)?, id_column.as_deref().map(find).transpose()?));
+                        continue;
+                    };
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+                    let field = |col: usize| fields.get(col).map(|f| f.trim());
+                    let tm = field(tm_col).ok_or_else(|| bad(format!("no '{column}' field")))?;
+                    let id = id_col.and_then(field).filter(|id| !id.is_empty());
+                    let (tm, id) = (tm.to_string(), id.map(String::from));
+                    (tm, id)
+                }
+            };
+            let tm: Machine = tm.parse().map_err(|_| bad(format!("not a TM: '{tm}'")))?;
+            if (tm.states(), tm.symbols()) != (states, symbols) {
+                let msg = format!("expected {states}-state, {symbols}-symbol TMs: '{tm}'");
+                return Err(bad(msg));
+            }
+            let id = match id {
+                Some(id) => id
+                    .parse()
+                    .map_err(|_| bad(format!("not a numeric ID: '{id}'")))?,
+                None => line_number,
+            };
+            if list.tms.insert(id, tm).is_some() {
+                return Err(bad(format!("duplicate ID {id}")));
+            }
+        }
+        if let (ListFormat::Csv { .. }, None) = (format, columns) {
+            return Err(io::Error::new(ErrorKind::InvalidData, "no CSV header"));
+        }
+        Ok(list)
+    }
+
+    /// The listed TMs' IDs, in order.
+    pub fn ids(&self) -> impl Iterator<Item = MachineID> + '_ {
+        self.tms.keys().copied()
+    }
+
+    /// The TM with the given ID, if listed.
+    pub fn get(&self, id: MachineID) -> Option<&Machine> {
+        self.tms.get(&id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.tms.len()
+    }
+}
+
+/// Split a line of CSV into fields. Fields may be quoted, with `""` for a literal quote.
+/// (Quoted line breaks aren't supported: TMs don't need them.)
+fn split_csv(line: &str) -> Vec<String> {
+    let (mut fields, mut field, mut quoted) = (vec![], String::new(), false);
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+/// The TMs a run works on.
+pub enum MachineSource {
+    Database(Database),
+    List(MachineList),
+}
+
+impl MachineSource {
+    /// Read the TMs with the given IDs, in order, skipping any that don't exist.
+    pub fn read<'a, I: Iterator<Item = MachineID> + Send + 'a>(
+        &'a self,
+        ids: I,
+    ) -> Box<dyn Iterator<Item = (MachineID, Machine)> + Send + 'a> {
+        match self {
+            MachineSource::Database(db) => Box::new(db.read(ids)),
+            MachineSource::List(list) => {
+                Box::new(ids.filter_map(|i| Some((i, list.get(i)?.clone()))))
+            }
+        }
+    }
+
+    /// Like `read()`, but decoding the TMs in parallel (in rayon's current thread pool).
+    pub fn par_read(
+        &self,
+        ids: Vec<MachineID>,
+    ) -> impl ParallelIterator<Item = (MachineID, Machine)> + '_ {
+        match self {
+            MachineSource::Database(db) => Either::Left(db.par_read(ids)),
+            MachineSource::List(list) => Either::Right(
+                ids.into_par_iter()
+                    .filter_map(|i| Some((i, list.get(i)?.clone()))),
+            ),
+        }
+    }
+
+    /// Like `par_read()`, but borrowing each TM rather than copying it -- from the memory-mapped
+    /// file, in the case of a DB.
+    pub fn par_view(
+        &self,
+        ids: Vec<MachineID>,
+    ) -> impl ParallelIterator<Item = (MachineID, MachineRef<'_>)> + '_ {
+        match self {
+            MachineSource::Database(db) => {
+                Either::Left(ids.into_par_iter().filter_map(|i| Some((i, db.get(i)?))))
+            }
+            MachineSource::List(list) => Either::Right(
+                ids.into_par_iter()
+                    .filter_map(|i| Some((i, list.get(i)?.view()))),
+            ),
+        }
+    }
+
+    /// The TMs to work on: those in the given undecided index file, if it exists, or the whole DB.
+    /// (A list is used whole, ignoring the index file.)
+    pub fn index(&self, path: &str) -> Index {
+        match self {
+            MachineSource::Database(db) => {
+                Index::open(path).unwrap_or_else(|_| Index::new(db.len()))
+            }
+            MachineSource::List(list) => Index::from_ids(list.ids()),
+        }
+    }
+
+    /// The number of states in each TM.
+    pub fn states(&self) -> usize {
+        match self {
+            MachineSource::Database(db) => db.states(),
+            MachineSource::List(list) => list.states,
+        }
+    }
+
+    /// The number of symbols in each TM.
+    pub fn symbols(&self) -> usize {
+        match self {
+            MachineSource::Database(db) => db.symbols(),
+            MachineSource::List(list) => list.symbols,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BB2: &str = "1RB1LB_1LA---";
+    const TM_2: &str = "1RB---_1LB0LA";
+
+    fn read(input: &str, format: &ListFormat) -> io::Result<MachineList> {
+        MachineList::read(input.as_bytes(), format, 2, 2)
+    }
+
+    #[test]
+    fn test_text() {
+        let input = format!("# Candidates\n{BB2}\n\n  7 {TM_2}\n{BB2},12\n");
+        let list = read(&input, &ListFormat::Text).unwrap();
+        assert_eq!(list.ids().collect::<Vec<_>>(), [2, 7, 12]);
+        assert_eq!(list.get(7).unwrap().to_string(), TM_2);
+        assert_eq!(list.get(12).unwrap().to_string(), BB2);
+        assert!(list.get(4).is_none());
+        for bad in [
+            format!("{BB2}\n1RB"),
+            format!("{BB2} x"),
+            format!("2 {BB2}\n{BB2}"),
+            String::from("1RB1LC_1RC1RB_1RD0LE_1LA1LD_---0LA"),
+        ] {
+            let e = read(&bad, &ListFormat::Text).unwrap_err();
+            assert_eq!(e.kind(), ErrorKind::InvalidData, "{bad}");
+        }
+        assert!(read(&format!("2 {BB2}\n{BB2}"), &ListFormat::Text)
+            .unwrap_err()
+            .to_string()
+            .starts_with("line 2: duplicate ID 2"));
+    }
+
+    #[test]
+    fn test_csv() {
+        let format = ListFormat::Csv {
+            column: String::from("machine"),
+            id_column: Some(String::from("id")),
+        };
+        let input = format!("id,\"note\",machine\n5,\"BB(2), \"\"champion\"\"\",{BB2}\n,,{TM_2}\n");
+        let list = read(&input, &format).unwrap();
+        assert_eq!(list.ids().collect::<Vec<_>>(), [3, 5]);
+        assert_eq!(list.get(5).unwrap().to_string(), BB2);
+        assert_eq!(
+            split_csv("a,\"b,\"\"c\"\"\",d"),
+            ["a", "b,\"c\"", "d"].map(String::from)
+        );
+        let format = ListFormat::Csv {
+            column: String::from("tm"),
+            id_column: None,
+        };
+        assert!(read(&input, &format).is_err());
+        assert!(read("", &format).is_err());
+    }
+}
@@ -4,12 +4,24 @@ pub mod driver;
 pub mod io;
 pub mod provers;
 
-use crate::core::{Machine, Proof};
+use crate::core::{Halted, Machine, Proof, Symmetry};
 use argh::FromArgs;
-use driver::{process_remote, run_node, DeciderProgress, DeciderProgressIterator};
-use io::{Database, DeciderVerificationFile, Index, MachineID, OutputFile, OWN_DVF, OWN_INDEX};
-use provers::{prover_names, prover_range_by_name, ProverBox};
+use driver::{
+    canonical_classes, process_remote, run_node, Attempt, AttemptOptions, DeciderProgress,
+    DeciderProgressIterator, Outcome, Outputs, TMClass,
+};
+use io::{
+    to_coq, to_dot, Database, DeciderVerificationFile, DvfProof, DvfReader, Index, ListFormat,
+    MachineID, MachineList, MachineSource, OutputDir, OutputFile, ProofCache,
+};
+use provers::{
+    prover_by_name, prover_names, prover_range_by_name, DirectProver, ProverBox, SatBackend,
+};
+use rayon::iter::{ParallelBridge, ParallelIterator};
+use rayon::{ThreadPool, ThreadPoolBuilder};
 use serde_json::{json, to_string_pretty, Value};
+use std::sync::Mutex;
+use std::time::Duration;
 
 const DEFAULT_DB: &str = "../all_5_states_undecided_machines_with_global_header";
 const DEFAULT_INDEX: &str = "../bb5_undecided_index";
@@ -22,7 +34,15 @@ const DEFAULT_INDEX: &str = "../bb5_undecided_index";
     # Parallel processing:\n\
     $ {command_name} --server --ip 10.0.0.1 -p direct -x 0 -l 8 -p mitm_dfa -x 8\n\
     # And in other terminal tabs and/or computers on the network, once per CPU\n\
-    $ {command_name} --ip 10.0.0.1 &"
+    $ {command_name} --ip 10.0.0.1 &\n\
+    # Or, on one computer, use a thread per CPU:\n\
+    $ {command_name} --threads 0 -p direct -x 0 -l 8 -p mitm_dfa -x 8\n\
+    # Audit a Decider Verification File from another run:\n\
+    $ {command_name} --verify output/finite_automata_reduction.dvf\n\
+    # Spend at most 10s per TM and prover, then retry the timed-out TMs with 10 minutes each:\n\
+    $ {command_name} --timeout 10\n\
+    $ mv output/finite_automata_reduction.timeouts retry_index\n\
+    $ {command_name} --timeout 600 -i retry_index"
 )]
 pub struct DeciderArgs {
     /// prover(s) to use: see example
@@ -49,89 +69,384 @@ pub struct DeciderArgs {
     #[argh(option, default = "25122")]
     port: u16,
 
+    /// number of worker threads for a local run, and for checking TMs before any search (see
+    /// --halt-steps, --cache) (default 1; 0 means one per CPU)
+    #[argh(option, default = "1")]
+    threads: usize,
+
     /// analyze only the given TMs/seeds and show any proofs found
     #[argh(option, short = 'a')]
     ad_hoc: Vec<String>,
 
+    /// record complete proofs (not just DFAs) in the DVF file
+    #[argh(switch)]
+    full_proofs: bool,
+
+    /// check the proofs in the given DVF file, instead of searching
+    #[argh(option)]
+    verify: Option<String>,
+
+    /// time limit (in seconds) for each prover on each TM; TMs which run out of time are listed
+    /// in output/finite_automata_reduction.timeouts (built-in SAT solves that time out finish in
+    /// the background, at most one per CPU at a time; others wait for them)
+    #[argh(option, from_str_fn(parse_timeout))]
+    timeout: Option<Duration>,
+
+    /// shrink the proofs found, before recording them
+    #[argh(switch)]
+    minimize: bool,
+
+    /// prove one TM per class of equivalent TMs (under mirroring and renaming states), then
+    /// transform its proof for the rest (local runs only)
+    #[argh(switch)]
+    canonical: bool,
+
+    /// record the first proof of each TM in a fixed search order, whichever prover finds one, and
+    /// sort the DVF file -- so runs with the same results write identical files
+    #[argh(switch)]
+    deterministic: bool,
+
+    /// also run each proof's TM for this many steps, checking the automaton rejects every
+    /// configuration reached
+    #[argh(option)]
+    cross_check: Option<usize>,
+
+    /// also write a report on each prover's attempt on each TM to the given file, as NDJSON
+    #[argh(option)]
+    report: Option<String>,
+
+    /// first run each TM for up to this many steps, listing those which halt in
+    /// output/finite_automata_reduction.halts (rather than trying to prove they don't)
+    #[argh(option)]
+    halt_steps: Option<u64>,
+
+    /// write a Coq file checking each proof (from --ad-hoc or --verify) to the given directory
+    #[argh(option)]
+    coq: Option<String>,
+
+    /// write a Graphviz DOT rendering of each proof found (in --ad-hoc mode) to the given directory
+    #[argh(option)]
+    dot: Option<String>,
+
+    /// write each SAT-based prover's instance for each TM (in --ad-hoc mode), in DIMACS CNF format,
+    /// to the given directory
+    #[argh(option)]
+    cnf: Option<String>,
+
+    /// reuse the proofs in the given file (for equivalent TMs too) instead of searching, and add
+    /// the proofs found to it; cached proofs are checked before use
+    #[argh(option)]
+    cache: Option<String>,
+
     /// path to the DB file
     #[argh(option, short = 'd', default = "String::from(DEFAULT_DB)")]
     db: String,
 
+    /// read TMs from the given text file ("-" for standard input) instead of the DB: one per line,
+    /// in the standard text format, optionally with a numeric ID (else the line number is used)
+    #[argh(option)]
+    input: Option<String>,
+
+    /// read --input as CSV with a header row, taking TMs from the named column
+    #[argh(option)]
+    column: Option<String>,
+
+    /// with --column, take IDs from the named column (else the line number is used)
+    #[argh(option)]
+    id_column: Option<String>,
+
+    /// number of states in the DB's TMs (default 5)
+    #[argh(option, default = "5")]
+    states: usize,
+
+    /// number of symbols in the DB's TMs (default 2)
+    #[argh(option, default = "2")]
+    symbols: usize,
+
     /// path to the undecided index file (used if present)
     #[argh(option, short = 'i', default = "String::from(DEFAULT_INDEX)")]
     index: String,
 }
 
+/// Parse a `--timeout`: a non-negative number of seconds.
+fn parse_timeout(value: &str) -> Result<Duration, String> {
+    let secs: f64 = value
+        .parse()
+        .map_err(|_| format!("'{value}' isn't a number"))?;
+    Duration::try_from_secs_f64(secs).map_err(|_| format!("'{value}' isn't a valid time limit"))
+}
+
 fn main() -> std::io::Result<()> {
     let mut args: DeciderArgs = argh::from_env();
-    let db = Database::open(&args.db)?;
     if !args.ip.is_empty() && !args.server {
+        // A node can work without the DB, if the server sends it TMs from a list.
+        let db = Database::open(&args.db, args.states, args.symbols).ok();
         run_node(args, db);
         return Ok(());
     }
+    let source = open_source(&args)?;
     let mut provers: Vec<ProverBox> = vec![];
     if args.prover.is_empty() {
         args.prover.extend(prover_names());
     }
     for (i, name) in args.prover.iter().enumerate() {
+        if let Some((_, solver)) = name.split_once('@') {
+            SatBackend::external(solver)?;
+        }
         let lo = args.exclude.get(i).map_or(usize::MIN, |x| x + 1);
         let hi = args.limit.get(i).map_or(usize::MAX, |l| l + 1);
-        provers.extend(prover_range_by_name(name, lo..hi));
+        provers.extend(prover_range_by_name(name, lo..hi, args.states));
     }
 
+    for dir in args
+        .coq
+        .iter()
+        .chain(args.dot.iter())
+        .chain(args.cnf.iter())
+    {
+        std::fs::create_dir_all(dir)?;
+    }
+    if let Some(path) = args.verify {
+        return process_verify(path, source, args.coq, args.cross_check.unwrap_or(0));
+    }
+    let options = AttemptOptions::from_args(&args);
+    if options.canonical && args.server {
+        let msg = "--canonical only works for a local run, not with --server";
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, msg));
+    }
+    let cache = args.cache.as_ref().map(open_cache).transpose()?;
     if !args.ad_hoc.is_empty() {
-        return process_ad_hoc(args.ad_hoc, db, provers);
+        return process_ad_hoc(args, source, provers, options, cache);
     }
 
-    let mut index = Index::open(&args.index).unwrap_or_else(|_| Index::new(db.len()));
-    let mut out = OutputFile::append(OWN_INDEX)?;
-    let mut dvf = DeciderVerificationFile::append(OWN_DVF)?;
+    let mut index = source.index(&args.index);
+    // A list's IDs aren't DB indexes: keep its results apart from the DB's.
+    let output = match &args.input {
+        Some(input) => OutputDir::for_list(input),
+        None => OutputDir::default(),
+    };
+    std::fs::create_dir_all(output.path())?;
+    let mut outputs = Outputs::append(&output, args.full_proofs, args.report.as_deref())?;
+    if args.deterministic {
+        outputs = outputs.with_sorted_dvf();
+    }
     let progress = DeciderProgress::new(index.len_initial());
+    let pool = ThreadPoolBuilder::new()
+        .num_threads(args.threads)
+        .build()
+        .map_err(std::io::Error::other)?;
+    if let Some(steps) = args.halt_steps {
+        process_halting(&source, &mut index, &progress, &output, steps, &pool)?;
+    }
+    if let Some(cache) = cache {
+        outputs = outputs.with_cache(cache);
+        process_cached(&source, &mut index, &progress, &mut outputs, options, &pool)?;
+    }
     if !args.server {
+        // With one thread, searches run on this one, in order.
+        let pool = (args.threads != 1).then_some(&pool);
         for prover in provers.iter_mut() {
-            process_local(&db, &mut index, &progress, prover, &mut out, &mut dvf)?;
+            let outputs = &mut outputs;
+            process_local(
+                &source, &mut index, &progress, prover, outputs, options, pool,
+            )?;
         }
+        outputs.finalize()?;
     } else {
         let prover_names = provers.into_iter().map(|p| p.name()).collect();
-        process_remote(args, index, progress, prover_names, out, dvf);
+        process_remote(args, source, index, progress, prover_names, outputs);
     }
     Ok(())
 }
 
+fn process_halting(
+    source: &MachineSource,
+    index: &mut Index,
+    progress: &DeciderProgress,
+    output: &OutputDir,
+    steps: u64,
+    pool: &ThreadPool,
+) -> std::io::Result<()> {
+    index.read_decided(output)?;
+    progress.set_solved(index.len_solved());
+    // Each TM found to halt is listed right away, so an interrupted run loses none.
+    let halts = Mutex::new((OutputFile::append(output.halts())?, 0usize));
+    let ids: Vec<MachineID> = index.iter().collect();
+    let bar = progress.prover_progress(ids.len(), "halting");
+    pool.install(|| {
+        (source.par_view(ids))
+            .inspect(|_| bar.inc(1))
+            .filter(|(_, tm)| tm.simulate(steps).is_some())
+            .try_for_each(|(i, _)| {
+                let (halts, count) = &mut *halts.lock().unwrap();
+                *count += 1;
+                progress.solve(1);
+                halts.insert(i)
+            })
+    })?;
+    let count = halts.into_inner().unwrap().1;
+    progress.println(format!(
+        "{} TMs halt within {} steps: see {}",
+        count,
+        steps,
+        output.halts().display()
+    ))
+}
+
+/// The TMs to work on: from `--input`, if given, or else the DB.
+fn open_source(args: &DeciderArgs) -> std::io::Result<MachineSource> {
+    let (states, symbols) = (args.states, args.symbols);
+    let Some(path) = &args.input else {
+        return Ok(MachineSource::Database(Database::open(
+            &args.db, states, symbols,
+        )?));
+    };
+    let format = match &args.column {
+        Some(column) => ListFormat::Csv {
+            column: column.clone(),
+            id_column: args.id_column.clone(),
+        },
+        None => ListFormat::Text,
+    };
+    let list = MachineList::open(path, &format, states, symbols)?;
+    eprintln!("Read {} TMs from {}.", list.len(), path);
+    Ok(MachineSource::List(list))
+}
+
+/// Load a `ProofCache`, warning about any entries it had to ignore.
+fn open_cache(path: &String) -> std::io::Result<ProofCache> {
+    let cache = ProofCache::open(path)?;
+    if cache.rejected() > 0 {
+        eprintln!(
+            "Ignored {} unreadable or invalid entries in {}.",
+            cache.rejected(),
+            path
+        );
+    }
+    Ok(cache)
+}
+
+fn process_cached(
+    source: &MachineSource,
+    index: &mut Index,
+    progress: &DeciderProgress,
+    outputs: &mut Outputs,
+    options: AttemptOptions,
+    pool: &ThreadPool,
+) -> std::io::Result<()> {
+    index.read_decided(outputs.dir())?;
+    progress.set_solved(index.len_solved());
+    let Some(cache) = outputs.cache() else {
+        return Ok(());
+    };
+    let ids: Vec<MachineID> = index.iter().collect();
+    let bar = progress.prover_progress(ids.len(), "cache");
+    let hits: Vec<(MachineID, Proof, bool)> = pool.install(|| {
+        (source.par_read(ids))
+            .inspect(|_| bar.inc(1))
+            .filter_map(|(i, tm)| {
+                let (proof, fell_back) = cached_proof(cache, &tm, options)?;
+                Some((i, proof, fell_back))
+            })
+            .collect()
+    });
+    for (i, proof, fell_back) in hits.iter() {
+        outputs.record_solved(progress, *i, proof)?;
+        if *fell_back {
+            outputs.record_fell_back(progress, *i)?;
+        }
+    }
+    progress.println(format!("{} TMs were solved by cached proofs", hits.len()))
+}
+
+/// The cached proof for `tm`, if any -- or with `--deterministic`, `Outcome::first_proof()`,
+/// and whether that ran out of time.
+fn cached_proof(
+    cache: &ProofCache,
+    tm: &Machine,
+    options: AttemptOptions,
+) -> Option<(Proof, bool)> {
+    let proof = cache.get(tm)?;
+    if !options.deterministic {
+        return Some((proof, false));
+    }
+    match Outcome::Solved(proof).first_proof(tm, options.time_limit) {
+        (Outcome::Solved(proof), fell_back) => Some((proof, fell_back)),
+        _ => None,
+    }
+}
+
 fn process_local(
-    db: &Database,
+    source: &MachineSource,
     index: &mut Index,
     progress: &DeciderProgress,
     prover: &mut ProverBox,
-    out: &mut OutputFile,
-    dvf: &mut DeciderVerificationFile,
+    outputs: &mut Outputs,
+    options: AttemptOptions,
+    pool: Option<&ThreadPool>,
 ) -> std::io::Result<()> {
-    index.read_decided()?;
+    index.read_decided(outputs.dir())?;
     progress.set_solved(index.len_solved());
-    for (i, tm) in db.read(index.iter().decider_progress_with(progress, prover.name())) {
-        if let Some(proof) = prover.prove(&tm) {
-            match proof.validate(&tm) {
-                Ok(()) => {
-                    out.insert(i)?;
-                    dvf.insert(i, proof.automaton.direction, &proof.automaton.dfa)?;
-                    progress.solve(1);
-                }
-                Err(e) => {
-                    let name = prover.name();
-                    let msg = format!("Rejected {} proof of {} ({}): {:?}", name, i, &tm, e);
-                    progress.println(msg)?;
+    let name = prover.name();
+    // Skip the TMs this prover already failed on, in an earlier (interrupted) run.
+    let untried = index.untried(outputs.dir(), &name)?;
+    let mut attempts = OutputFile::append(outputs.dir().attempts(&name))?;
+    // Each job is a class of TMs: see `--canonical`. Otherwise, each TM is in a class by itself.
+    let jobs: Box<dyn Iterator<Item = TMClass> + Send> = if options.canonical {
+        let groups = canonical_classes(source, untried);
+        Box::new(
+            groups
+                .decider_progress_with(progress, name.clone())
+                .flatten(),
+        )
+    } else {
+        let ids = untried
+            .into_iter()
+            .decider_progress_with(progress, name.clone());
+        let identity = Symmetry::identity(source.states());
+        Box::new(
+            source
+                .read(ids)
+                .map(move |(i, tm)| (tm, vec![(i, identity.clone())])),
+        )
+    };
+    match pool {
+        None => {
+            for class in jobs {
+                for (tm, attempt) in Attempt::run_class(prover, &class, options) {
+                    outputs.record(progress, &name, &mut attempts, &tm, &attempt)?;
                 }
             }
+            Ok(())
+        }
+        Some(pool) => {
+            // The DB is still read in order (by whichever thread asks next),
+            // while each thread runs its own copy of the prover.
+            let files = Mutex::new((outputs, &mut attempts));
+            pool.install(|| {
+                jobs.par_bridge().try_for_each_init(
+                    || prover_by_name(&name, source.states()).expect("prover names round-trip"),
+                    |prover, class| {
+                        let class_attempts = Attempt::run_class(prover, &class, options);
+                        let (outputs, attempts) = &mut *files.lock().unwrap();
+                        (class_attempts.iter()).try_for_each(|(tm, a)| {
+                            outputs.record(progress, &name, attempts, tm, a)
+                        })
+                    },
+                )
+            })
         }
     }
-    Ok(())
 }
 
 fn process_ad_hoc(
-    tm_specs: Vec<String>,
-    db: Database,
+    args: DeciderArgs,
+    source: MachineSource,
     mut provers: Vec<ProverBox>,
+    options: AttemptOptions,
+    mut cache: Option<ProofCache>,
 ) -> std::io::Result<()> {
+    let (tm_specs, halt_steps, coq, dot) = (args.ad_hoc, args.halt_steps, args.coq, args.dot);
     let mut proofs: Vec<Option<Proof>> = vec![None; tm_specs.len()];
     let mut unsolved_pos = vec![usize::default(); 0];
     let mut tms = vec![Machine::default(); tm_specs.len()];
@@ -146,9 +461,11 @@ fn process_ad_hoc(
             tms[pos] = tm;
         }
     }
-    for (&pos, (_, tm)) in seed_pos.iter().zip(db.read(seed_ids.into_iter())) {
-        unsolved_pos.push(pos);
-        tms[pos] = tm;
+    for (&pos, &id) in seed_pos.iter().zip(seed_ids.iter()) {
+        if let Some((_, tm)) = source.read(std::iter::once(id)).next() {
+            unsolved_pos.push(pos);
+            tms[pos] = tm;
+        }
     }
     let progress = DeciderProgress::new(unsolved_pos.len());
     for (pos, tm_spec) in tm_specs.iter().enumerate() {
@@ -156,6 +473,43 @@ fn process_ad_hoc(
             progress.println(format!("Could not understand '{}'. Ignoring.", tm_spec))?;
         }
     }
+    if let Some(dir) = &args.cnf {
+        for prover in provers.iter_mut() {
+            for &pos in unsolved_pos.iter() {
+                if let Some(cnf) = prover.cnf(&tms[pos]) {
+                    export(
+                        dir,
+                        &format!("{}_{}", tm_specs[pos], prover.name()),
+                        "cnf",
+                        cnf,
+                    )?;
+                }
+            }
+        }
+    }
+    let mut halts: Vec<Option<Halted>> = vec![None; tm_specs.len()];
+    if let Some(steps) = halt_steps {
+        for pos in unsolved_pos.clone() {
+            halts[pos] = tms[pos].simulate(steps);
+            if halts[pos].is_some() {
+                progress.solve(1);
+                unsolved_pos.retain(|&p| p != pos);
+            }
+        }
+    }
+    if let Some(cache) = &cache {
+        for pos in unsolved_pos.clone() {
+            if let Some((proof, fell_back)) = cached_proof(cache, &tms[pos], options) {
+                if fell_back {
+                    let msg = format!("No time to find the first proof of {}", tm_specs[pos]);
+                    progress.println(msg)?;
+                }
+                proofs[pos] = Some(proof);
+                progress.solve(1);
+                unsolved_pos.retain(|&p| p != pos);
+            }
+        }
+    }
     for prover in provers.iter_mut() {
         if unsolved_pos.is_empty() {
             break;
@@ -166,28 +520,276 @@ fn process_ad_hoc(
             .decider_progress_with(&progress, prover.name())
         {
             let tm = &tms[pos];
-            if let Some(proof) = prover.prove(tm) {
-                match proof.validate(tm) {
-                    Ok(()) => {
-                        proofs[pos] = Some(proof);
-                        progress.solve(1);
-                        unsolved_pos.retain(|&p| p != pos);
-                    }
-                    Err(e) => {
-                        let name = prover.name();
-                        let msg = format!("Rejected {} proof of {} ({}): {:?}", name, pos, &tm, e);
+            let attempt = Attempt::run(prover, pos as MachineID, tm, options);
+            match attempt.outcome {
+                Outcome::Solved(proof) => {
+                    if attempt.fell_back {
+                        let msg = format!("No time to find the first proof of {}", tm_specs[pos]);
                         progress.println(msg)?;
                     }
+                    if let Some(cache) = &mut cache {
+                        cache.insert(tm, &proof)?;
+                    }
+                    proofs[pos] = Some(proof);
+                    progress.solve(1);
+                    unsolved_pos.retain(|&p| p != pos);
+                }
+                Outcome::Rejected(e, c) => {
+                    let name = prover.name();
+                    let mut msg = format!("Rejected {} proof of {} ({}): {:?}", name, pos, &tm, e);
+                    if let Some(c) = c {
+                        msg = format!("{} ({})", msg, c);
+                    }
+                    progress.println(msg)?;
+                }
+                Outcome::Failed => {}
+                Outcome::TimedOut => {
+                    let msg = format!("{} timed out on {}", prover.name(), tm_specs[pos]);
+                    progress.println(msg)?;
                 }
             }
         }
     }
     progress.finish();
-    let results: Vec<Value> = tm_specs
-        .iter()
-        .zip(proofs)
-        .filter_map(|(spec, proof)| Some(json!({"machine": spec, "proof": proof?})))
+    for (pos, proof) in proofs.iter().enumerate() {
+        if let Some(proof) = proof {
+            if let Some(dir) = &coq {
+                export(dir, &tm_specs[pos], "v", to_coq(&tms[pos], proof))?;
+            }
+            if let Some(dir) = &dot {
+                export(dir, &tm_specs[pos], "dot", to_dot(&tms[pos], proof))?;
+            }
+        }
+    }
+    let results: Vec<Value> = (tm_specs.iter().zip(proofs).zip(halts))
+        .filter_map(|((spec, proof), halted)| match (proof, halted) {
+            (Some(proof), _) => Some(json!({"machine": spec, "proof": proof})),
+            (_, Some(halted)) => Some(json!({"machine": spec, "halted": halted})),
+            _ => None,
+        })
         .collect();
     println!("{}", to_string_pretty(&results).unwrap());
     Ok(())
 }
+
+fn process_verify(
+    path: String,
+    source: MachineSource,
+    coq: Option<String>,
+    cross_check: usize,
+) -> std::io::Result<()> {
+    let (mut passed, mut failed) = (0usize, 0usize);
+    for entry in DvfReader::open(path)? {
+        let (id, decider_type, info) = entry?;
+        let verdict = verify_entry(&source, id, decider_type, &info, cross_check);
+        match verdict {
+            Ok((tm, proof)) => {
+                passed += 1;
+                println!("{}: pass", id);
+                if let Some(dir) = &coq {
+                    export(dir, &id.to_string(), "v", to_coq(&tm, &proof))?;
+                }
+            }
+            Err(reason) => {
+                failed += 1;
+                println!("{}: FAIL: {}", id, reason);
+            }
+        }
+    }
+    eprintln!(
+        "Verified {} entries: {} passed, {} failed.",
+        passed + failed,
+        passed,
+        failed
+    );
+    if failed > 0 {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+fn verify_entry(
+    source: &MachineSource,
+    id: MachineID,
+    decider_type: u32,
+    info: &[u8],
+    cross_check: usize,
+) -> Result<(Machine, Proof), String> {
+    let (_, tm) = source
+        .read(std::iter::once(id))
+        .next()
+        .ok_or_else(|| String::from("not in the DB or input"))?;
+    let decoded = DeciderVerificationFile::decode(decider_type, info, tm.states(), tm.symbols())
+        .ok_or_else(|| format!("unsupported entry (DeciderType {})", decider_type))?;
+    let proof = match decoded {
+        DvfProof::DFA(direction, dfa) => {
+            dfa.validate().map_err(|e| e.to_string())?;
+            DirectProver::complete_unverified(&tm, direction, dfa)
+                .ok_or_else(|| String::from("could not complete the NFA"))?
+        }
+        DvfProof::Full(proof) => proof,
+    };
+    (proof.validate(&tm))
+        .and_then(|()| proof.cross_check(&tm, cross_check))
+        .map_err(|e| match proof.counterexample(&tm, &e) {
+            Some(c) => format!("{} ({})", e, c),
+            None => e.to_string(),
+        })?;
+    Ok((tm, proof))
+}
+
+/// Write an exported proof to `dir/far_<name>.<ext>`, where `name` is the TM's ID or text.
+fn export(dir: &str, name: &str, ext: &str, contents: String) -> std::io::Result<()> {
+    let name: String = (name.chars())
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    let path = std::path::Path::new(dir).join(format!("far_{}.{}", name, ext));
+    std::fs::write(path, contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use itertools::Itertools;
+
+    /// https://bbchallenge.org/12345, and a proof for it with the NFA row `t[1][5]` as given.
+    const TM: &str = "1RB---_0RC---_1RD0RD_0LD1LE_1LC0LB";
+    fn proof(t_1_5: u64) -> Proof {
+        serde_json::from_value(json!({
+            "automaton": {
+                "direction": "L",
+                "dfa": [[0, 1], [1, 1]],
+                "nfa": {
+                    "accepted": 1056,
+                    "t": [
+                        [384, 128, 512, 8, 128, 1984, 968, 576, 256, 128, 1024],
+                        [1024, 1024, 8, 512, 2, t_1_5, 1024, 384, 512, 64, 1024]
+                    ]}},
+            "steady_state": 1024}))
+        .unwrap()
+    }
+
+    /// Write `proof` as a full-proof DVF entry, read it back, and check it against `TM`.
+    fn verify(name: &str, proof: &Proof) -> Result<(Machine, Proof), String> {
+        let path = std::env::temp_dir().join(format!("{}-{}.dvf", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut dvf = DeciderVerificationFile::append(&path, true).unwrap();
+        dvf.insert(12345, proof).unwrap();
+        dvf.finalize().unwrap();
+        let (id, decider_type, info) = DvfReader::open(&path).unwrap().next().unwrap().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let list = MachineList::read(format!("12345 {TM}").as_bytes(), &ListFormat::Text, 5, 2);
+        let source = MachineSource::List(list.unwrap());
+        verify_entry(&source, id, decider_type, &info, 0)
+    }
+
+    #[test]
+    fn test_verify_entry() {
+        let (tm, _) = verify("test-verify-pass", &proof(1024)).unwrap();
+        assert_eq!(tm.to_string(), TM);
+        // This entry's NFA is closed at DFA state 0, but not at state 1.
+        let e = verify("test-verify-fail", &proof(1)).unwrap_err();
+        assert!(
+            e.starts_with("closure under Halt { f: 0, r: 1 } unmet at q=1"),
+            "{e}"
+        );
+    }
+
+    #[test]
+    fn test_parse_timeout() {
+        assert_eq!(parse_timeout("2.5"), Ok(Duration::from_millis(2500)));
+        assert_eq!(parse_timeout("0"), Ok(Duration::ZERO));
+        for bad in ["-1", "NaN", "inf", "1e30", "soon"] {
+            assert!(parse_timeout(bad).is_err(), "{bad}");
+        }
+    }
+
+    /// Run the `direct` provers on some 2-state TMs with `process_local()`, in a fresh output
+    /// directory, and return the sorted IDs each output file lists: solved TMs, then attempts.
+    fn run_local(name: &str, pool: Option<&ThreadPool>) -> Vec<Vec<MachineID>> {
+        let tms = [
+            "1RB1LB_1LA---",
+            "1RB1LB_1LA0LB",
+            "1RB---_1LB0LA",
+            "0RB1LA_1LA0RB",
+        ];
+        let text: String = (tms.iter().enumerate())
+            .map(|(i, tm)| format!("{i} {tm}\n"))
+            .collect();
+        let list = MachineList::read(text.as_bytes(), &ListFormat::Text, 2, 2).unwrap();
+        let source = MachineSource::List(list);
+        let output =
+            OutputDir::new(std::env::temp_dir().join(format!("{}-{}", name, std::process::id())));
+        let _ = std::fs::remove_dir_all(output.index().parent().unwrap());
+        std::fs::create_dir_all(output.index().parent().unwrap()).unwrap();
+        let mut index = source.index("");
+        let progress = DeciderProgress::new(index.len_initial());
+        let mut outputs = Outputs::append(&output, false, None).unwrap();
+        let mut provers = prover_range_by_name("direct", 1..3, 2);
+        for prover in provers.iter_mut() {
+            let options = AttemptOptions::default();
+            process_local(
+                &source,
+                &mut index,
+                &progress,
+                prover,
+                &mut outputs,
+                options,
+                pool,
+            )
+            .unwrap();
+        }
+        outputs.finalize().unwrap();
+        drop(outputs);
+        let mut paths = vec![output.index()];
+        paths.extend(provers.iter().map(|p| output.attempts(&p.name())));
+        let ids = (paths.iter())
+            .map(|path| {
+                let bytes = std::fs::read(path).unwrap_or_default();
+                let chunks = bytes.chunks_exact(4);
+                chunks
+                    .map(|b| MachineID::from_be_bytes(b.try_into().unwrap()))
+                    .sorted()
+                    .collect()
+            })
+            .collect();
+        std::fs::remove_dir_all(output.index().parent().unwrap()).unwrap();
+        ids
+    }
+
+    #[test]
+    fn test_process_halting() {
+        let tms = ["1RB1LB_1LA---", "1RB1LB_1LA0LB", "1RB---_1LB0LA"];
+        let text: String = (tms.iter().enumerate())
+            .map(|(i, tm)| format!("{i} {tm}\n"))
+            .collect();
+        let list = MachineList::read(text.as_bytes(), &ListFormat::Text, 2, 2).unwrap();
+        let expected: Vec<MachineID> = (list.ids())
+            .filter(|&i| list.get(i).unwrap().simulate(100).is_some())
+            .collect();
+        assert!(expected.contains(&0));
+        let source = MachineSource::List(list);
+        let dir = std::env::temp_dir().join(format!("test-halting-{}", std::process::id()));
+        let output = OutputDir::new(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut index = source.index("");
+        let progress = DeciderProgress::new(index.len_initial());
+        let pool = ThreadPoolBuilder::new().num_threads(2).build().unwrap();
+        process_halting(&source, &mut index, &progress, &output, 100, &pool).unwrap();
+        let bytes = std::fs::read(output.halts()).unwrap();
+        let halts: Vec<MachineID> = (bytes.chunks_exact(4))
+            .map(|b| MachineID::from_be_bytes(b.try_into().unwrap()))
+            .sorted()
+            .collect();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(halts, expected);
+    }
+
+    #[test]
+    fn test_process_local_threads() {
+        let serial = run_local("test-local-serial", None);
+        assert_eq!(serial[0], [1, 2, 3]);
+        let pool = ThreadPoolBuilder::new().num_threads(3).build().unwrap();
+        assert_eq!(run_local("test-local-threads", Some(&pool)), serial);
+    }
+}
@@ -1,47 +1,80 @@
-//! Iterators for DFAs (on alphabet {0,1}), and prefixes (incomplete transition tables) thereof.
+//! Iterators for DFAs (on alphabet {0,1,...}), and prefixes (incomplete transition tables) thereof.
 
 use crate::core::{DFAState, DFA};
 
 /// Iterates over possible n-state DFAs, subject to two restrictions:
 /// 1. `DFA::check_leading_zeros()` passes.
 /// 2. The DFA states are ordered the same as a breadth-first search from the initial state
-///    (taking 0-transitions before 1-transitions). In particular, it's connected and the
+///    (taking 0-transitions before 1-transitions, etc.). In particular, it's connected and the
 ///    `DFAState` IDs `0..n` make their first appearances in order in the transition table
 ///    (which in Rust that would be: in `dfa.t.iter().flatten()`.)
 ///
 /// Condition 2 stops us from emitting two isomorphic DFAs.
 ///
+/// The order is canonical: DFAs come in lexicographic order of their transition tables, read
+/// row by row (`t[0][0], t[0][1], ..., t[n-1][k-1]`). The prefixes come in depth-first preorder:
+/// each prefix before its extensions, and those in the same lexicographic order. This order
+/// doesn't depend on build features (`sink_heuristic` only makes `DirectProver` skip subtrees),
+/// so "the first DFA for which ..." is well-defined -- see `DirectProver::first_proof()`.
+///
 /// The iterator has two flavors. `DFAPrefixIterator` yields once per (nonempty) partially-filled
 /// transition table, so long as it's a prefix of a total DFA following rules 1-2.
 /// `DFAIterator` yields once per completed DFA.
 /// `DFAPrefixIterator` supports an additional method, `skip_current_subtree()`, if the caller
 /// is uninterested in DFAs starting with the most recently yielded prefix.
+/// It can also be split into `subtrees()`: iterators which each yield the extensions of one prefix,
+/// to be searched independently (e.g. in parallel).
 ///
 /// We don't yield references to the DFA. (Rust only recently added "LendingIterator" support.)
 /// Instead: the dfa is accessible as a field, DFAPrefixIterator yields the changed `(q, b)` index,
 /// and DFAIterator yields `()`.
+#[derive(Clone)]
 pub struct DFAPrefixIterator {
     /// The DFA under construction.
     pub dfa: DFA,
-    /// The DFA has elements `[q][b]` filled in for `2*q+b < qb`.
+    /// The DFA has elements `[q][b]` filled in for `k*q+b < qb`, where `k` is the alphabet size.
     qb: usize,
-    /// For each qb, `max{dfa.t[q][b] | 2*q+b < qb}`.
+    /// For each qb, `max{dfa.t[q][b] | k*q+b < qb}`.
     tmax: Vec<DFAState>,
     /// Whether we've been asked to skip everything starting with the current prefix.
     skip_current: bool,
+    /// The number of entries fixed in advance (see `subtrees()`), which we yield but never change.
+    fixed: usize,
 }
 
 /// See `DFAPrefixIterator`.
 pub struct DFAIterator(pub DFAPrefixIterator);
 
 impl DFAPrefixIterator {
-    pub fn new(n: usize) -> Self {
+    pub fn new(n: usize, symbols: usize) -> Self {
         Self {
-            dfa: DFA::new(n),
+            dfa: DFA::new(n, symbols),
             qb: 0,
-            tmax: vec![0; 2 * n + 1],
+            tmax: vec![0; symbols * n + 1],
             skip_current: false,
+            fixed: 0,
+        }
+    }
+
+    /// Split the iteration into one iterator per prefix of `len` entries (or less, if that would
+    /// complete the DFA), in order. Each yields its prefix, one entry at a time, then continues
+    /// with the extensions of it this iterator would have yielded. (If the caller skips part of the
+    /// prefix, that iterator is done.) So together they cover the same DFAs, as independent jobs.
+    pub fn subtrees(n: usize, symbols: usize, len: usize) -> Vec<Self> {
+        let len = std::cmp::min(len, n * symbols);
+        let mut it = Self::new(n, symbols);
+        let mut roots = vec![];
+        while it.next().is_some() {
+            if it.qb == len {
+                roots.push(Self {
+                    dfa: it.dfa.clone(),
+                    fixed: len,
+                    ..Self::new(n, symbols)
+                });
+                it.skip_current_subtree();
+            }
         }
+        roots
     }
 
     pub fn skip_current_subtree(&mut self) {
@@ -49,13 +82,14 @@ impl DFAPrefixIterator {
     }
 
     fn qb_pair(&self) -> (usize, usize) {
-        (self.qb / 2, self.qb % 2)
+        let k = self.dfa.symbols();
+        (self.qb / k, self.qb % k)
     }
 }
 
 impl DFAIterator {
-    pub fn new(n: usize) -> Self {
-        Self(DFAPrefixIterator::new(n))
+    pub fn new(n: usize, symbols: usize) -> Self {
+        Self(DFAPrefixIterator::new(n, symbols))
     }
 }
 
@@ -64,18 +98,22 @@ impl Iterator for DFAPrefixIterator {
 
     fn next(&mut self) -> Option<Self::Item> {
         let m = (self.dfa.len() - 1) as DFAState;
+        let k = self.dfa.symbols();
         // If the table wasn't full yet, but we've promised it can be filled, the next prefix
         // is the first extension of the current one.
-        if self.qb < 2 * self.dfa.len() && !self.skip_current {
+        if self.qb < k * self.dfa.len() && !self.skip_current {
             let (q, b) = self.qb_pair();
             // Case 1: the next entry must be an unvisited state (thus the first one).
             // That's the case if doing otherwise would close the transition graph, early -- i.e.,
             // states `> tmax[qb]` exist and would become unreachable from ones `<= tmax[qb]`.
             // Case 2: no such restriction, so the lex-next table fills in a 0.
-            if self.tmax[self.qb] < m && self.qb == 2 * (self.tmax[self.qb] as usize) + 1 {
-                self.dfa.t[q][b] = self.tmax[self.qb] + 1;
-            } else {
-                self.dfa.t[q][b] = 0 as DFAState;
+            // (Entries fixed in advance are already filled in.)
+            if self.qb >= self.fixed {
+                if self.tmax[self.qb] < m && self.qb == k * (self.tmax[self.qb] as usize + 1) - 1 {
+                    self.dfa.t[q][b] = self.tmax[self.qb] + 1;
+                } else {
+                    self.dfa.t[q][b] = 0 as DFAState;
+                }
             }
             self.qb += 1;
             self.tmax[self.qb] = std::cmp::max(self.tmax[self.qb - 1], self.dfa.t[q][b]);
@@ -85,7 +123,7 @@ impl Iterator for DFAPrefixIterator {
         // If instead the table was full, we have to backtrack (or "carry" as we count?)
         // After we backtrack, either incrementing the current transition is the lex-first option,
         // or raising it any further would violate rules 1-2 or exit 0..n - backtrack further if so.
-        while self.qb > 1 {
+        while self.qb > std::cmp::max(1, self.fixed) {
             self.qb -= 1;
             let (q, b) = self.qb_pair();
             if self.dfa.t[q][b] <= self.tmax[self.qb] && self.dfa.t[q][b] < m {
@@ -105,7 +143,8 @@ impl Iterator for DFAIterator {
     fn next(&mut self) -> Option<Self::Item> {
         loop {
             let q_max = (self.0.dfa.len() - 1) as DFAState;
-            if self.0.next()? == (q_max, 1) {
+            let b_max = (self.0.dfa.symbols() - 1) as u8;
+            if self.0.next()? == (q_max, b_max) {
                 return Some(());
             }
         }
@@ -119,7 +158,7 @@ mod tests {
     #[test]
     fn test_1_state() {
         // It's actually pretty easy to screw this up. :-)
-        let mut it = DFAIterator::new(1);
+        let mut it = DFAIterator::new(1, 2);
         assert_eq!(it.next(), Some(()));
         assert_eq!(it.0.dfa.t, [[0, 0]]);
         assert_eq!(it.next(), None);
@@ -127,7 +166,7 @@ mod tests {
 
     #[test]
     fn test_2_states() {
-        let mut it = DFAIterator::new(2);
+        let mut it = DFAIterator::new(2, 2);
         assert_eq!(it.next(), Some(()));
         assert_eq!(it.0.dfa.t, [[0, 1], [0, 0]]);
         assert_eq!(it.next(), Some(()));
@@ -141,7 +180,7 @@ mod tests {
 
     #[test]
     fn test_skips() {
-        let mut it = DFAPrefixIterator::new(3);
+        let mut it = DFAPrefixIterator::new(3, 2);
         assert_eq!(it.next(), Some((0, 0)));
         assert_eq!(it.dfa.t, [[0, 0], [0, 0], [0, 0]]);
         assert_eq!(it.next(), Some((0, 1)));
@@ -161,12 +200,78 @@ mod tests {
         assert_eq!(it.next(), None);
     }
 
+    #[test]
+    fn test_subtrees() {
+        // Listing the complete DFAs, splitting the search at various points changes nothing.
+        let complete = |mut it: DFAPrefixIterator| {
+            let mut dfas = vec![];
+            while let Some((q, b)) = it.next() {
+                if (q, b) == (3, 1) {
+                    dfas.push(it.dfa.t.clone());
+                }
+            }
+            dfas
+        };
+        let all = complete(DFAPrefixIterator::new(4, 2));
+        for len in [1, 2, 3, 5, 8, 20] {
+            let roots = DFAPrefixIterator::subtrees(4, 2, len);
+            assert_eq!(
+                roots.into_iter().flat_map(complete).collect::<Vec<_>>(),
+                all
+            );
+        }
+        assert_eq!(DFAPrefixIterator::subtrees(4, 2, 8).len(), 816);
+        // Skipping part of the fixed prefix ends the subtree.
+        let mut it = DFAPrefixIterator::subtrees(3, 2, 3).pop().unwrap();
+        assert_eq!(it.next(), Some((0, 0)));
+        assert_eq!(it.next(), Some((0, 1)));
+        assert_eq!(it.dfa.t[0], [0, 1]);
+        it.skip_current_subtree();
+        assert_eq!(it.next(), None);
+    }
+
+    #[test]
+    fn test_order() {
+        // The documented order: strictly increasing transition tables.
+        let mut it = DFAIterator::new(4, 2);
+        let mut previous = None;
+        while it.next().is_some() {
+            let table: Vec<DFAState> = it.0.dfa.t.iter().flatten().copied().collect();
+            assert!(previous < Some(table.clone()));
+            previous = Some(table);
+        }
+    }
+
     #[test]
     fn test_counts() {
         // Counts of complete 0-insensitive binary DFAs follow https://oeis.org/A107668
-        assert_eq!(DFAIterator::new(1).count(), 1);
-        assert_eq!(DFAIterator::new(2).count(), 4);
-        assert_eq!(DFAIterator::new(3).count(), 45);
-        assert_eq!(DFAIterator::new(4).count(), 816);
+        assert_eq!(DFAIterator::new(1, 2).count(), 1);
+        assert_eq!(DFAIterator::new(2, 2).count(), 4);
+        assert_eq!(DFAIterator::new(3, 2).count(), 45);
+        assert_eq!(DFAIterator::new(4, 2).count(), 816);
+    }
+
+    #[test]
+    fn test_other_alphabets() {
+        // Brute force: count the 0-insensitive DFAs whose states first appear in order.
+        fn brute_force_count(n: usize, k: usize) -> usize {
+            (0..n.pow((n * k) as u32))
+                .filter(|&code| {
+                    let t: Vec<usize> = (0..n * k).map(|i| code / n.pow(i as u32) % n).collect();
+                    let mut seen = 1;
+                    t[0] == 0
+                        && t.iter().enumerate().all(|(i, &s)| {
+                            seen += (s == seen) as usize;
+                            s < seen && (seen == n || i + 1 < k * seen)
+                        })
+                })
+                .count()
+        }
+        for (n, k) in [(1, 3), (2, 3), (3, 3), (2, 4), (3, 2)] {
+            assert_eq!(DFAIterator::new(n, k).count(), brute_force_count(n, k));
+        }
+        let mut it = DFAIterator::new(2, 3);
+        assert_eq!(it.next(), Some(()));
+        assert_eq!(it.0.dfa.t, [[0, 0, 1], [0, 0, 0]]);
     }
 }
@@ -1,7 +1,7 @@
 //! This prover searches directly for a successful `TapeAutomaton`.
 //! The depth parameter controls the DFA size.
-//! As `../README.md` explains, we know an NFA only needs `(depth*TM_STATES+1)` states:
-//! `nfa_start(q, f)` as defined in proof.rs, plus a special HALT state.
+//! As `../README.md` explains, we know an NFA only needs `(depth*tm.states()+1)` states:
+//! `nfa_start(q, f, tm.states())` as defined in proof.rs, plus a special HALT state.
 //! HALT is automatically an "accepted steady state", suggesting the following `Proof` search:
 //! pick a direction, then pick a DFA, then construct the minimal NFA (in terms of added transitions
 //! and accepted states) satisfying the closure conditions in proof.rs.
@@ -11,67 +11,187 @@
 //! If we only know it through a fixed `(q, b)`, we can still compute the minimal NFA satisfying
 //! the closure criteria we know about. This might already accept `nfa_start(0, 0)`, in which case
 //! we needn't bother to complete the DFA; otherwise, we've at least made progress building the NFA.
+//! The search tree's branches are independent, so for a single hard TM, `direct_par` splits it into
+//! subtrees and searches them on all of rayon's threads at once.
 
-use super::{DFAPrefixIterator, Prover, ProverOptions};
+use super::{DFAPrefixIterator, Deadline, Prover, ProverOptions, ProverResult, TimedOut};
 use crate::core::{
-    col, nfa_start, row, DFAState, Machine, NFAState, Proof, Rule, Side, DFA, NFA, TM_STATES,
+    col, max_dfa, nfa_start, row, Bits, DFAState, Machine, MaskWidth, NFAState, NFAStateMask,
+    Proof, Rule, Side, DFA, NFA,
 };
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// In a parallel search, how many subtrees to aim for per thread: enough to balance the load,
+/// since some subtrees are much bigger than others.
+const SUBTREES_PER_THREAD: usize = 16;
 
 /// A prover which attempts a direct search for a `TapeAutomaton` meeting the proof criteria.
 /// If one exists with at most `depth` DFA states, the prover will find it.
 pub struct DirectProver {
     /// The DFA size to use.
     depth: usize,
+    /// Whether to search subtrees in parallel.
+    parallel: bool,
+    /// Whether to skip subtrees as `--features sink_heuristic` does (by default, if built with it).
+    sink_heuristic: bool,
 }
 
 impl Prover for DirectProver {
     fn name(&self) -> String {
-        format!("direct-{}", self.depth)
+        let suffix = if self.parallel { "_par" } else { "" };
+        format!("direct{}-{}", suffix, self.depth)
     }
 
-    fn prove(&mut self, tm: &Machine) -> Option<Proof> {
-        self.prove_side(tm, Side::R)
-            .or_else(|| self.prove_side(tm, Side::L))
+    fn prove_within(&mut self, tm: &Machine, deadline: Deadline) -> ProverResult {
+        if self.depth > max_dfa(tm.states()) {
+            return Ok(None);
+        }
+        // The NFA states fit in a machine word at low depths, which makes for a much faster search.
+        match MaskWidth::fitting(self.depth * tm.states() + 1) {
+            Some(MaskWidth::U64) => self.prove_with::<u64>(tm, deadline),
+            Some(MaskWidth::U128) => self.prove_with::<u128>(tm, deadline),
+            Some(MaskWidth::Max) => self.prove_with::<NFAStateMask>(tm, deadline),
+            None => Ok(None),
+        }
     }
 }
 
 impl ProverOptions for DirectProver {
     fn new(depth: usize) -> Self {
-        DirectProver { depth }
+        DirectProver {
+            depth,
+            parallel: false,
+            sink_heuristic: cfg!(feature = "sink_heuristic"),
+        }
     }
 }
 
 impl DirectProver {
+    /// The same prover, searching on all of rayon's (current pool's) threads.
+    pub fn in_parallel(self) -> Self {
+        DirectProver {
+            parallel: true,
+            ..self
+        }
+    }
+
+    /// The first proof for `tm` in a canonical order, independent of how (or whether) any other
+    /// prover found one: the least DFA size, then direction R before L, then the first DFA in
+    /// `DFAIterator` order, with the NFA `complete_unverified()` builds. So it's the proof a
+    /// `direct` search would find at increasing depths, without `sink_heuristic`.
+    /// Only depths up to `max_depth` are searched.
+    pub fn first_proof(tm: &Machine, max_depth: usize, deadline: Deadline) -> ProverResult {
+        for depth in 1..=max_depth {
+            let mut prover = DirectProver {
+                sink_heuristic: false,
+                ..DirectProver::new(depth)
+            };
+            if let Some(proof) = prover.prove_within(tm, deadline)? {
+                // Rebuild the NFA, so the result is a function of the direction and DFA alone.
+                let (direction, dfa) = (proof.automaton.direction, proof.automaton.dfa);
+                return Ok(DirectProver::complete_unverified(tm, direction, dfa));
+            }
+        }
+        Ok(None)
+    }
+
     /// The basic algorithm: try to complete a `TapeAutomaton` from the deterministic part.
     pub fn complete_unverified(tm: &Machine, direction: Side, dfa: DFA) -> Option<Proof> {
-        let mut nfa = NFA::new(dfa.len() * TM_STATES + 1);
-        let halt = (dfa.len() * TM_STATES) as NFAState;
+        if dfa.symbols() != tm.symbols() || dfa.len() > max_dfa(tm.states()) {
+            return None;
+        }
+        // As in the search, narrow masks make for faster saturation.
+        Some(match MaskWidth::fitting(dfa.len() * tm.states() + 1)? {
+            MaskWidth::U64 => Self::complete_with::<u64>(tm, direction, dfa).widen(),
+            MaskWidth::U128 => Self::complete_with::<u128>(tm, direction, dfa).widen(),
+            MaskWidth::Max => Self::complete_with::<NFAStateMask>(tm, direction, dfa),
+        })
+    }
+
+    /// `complete_unverified()`, with NFA state sets stored as `B`.
+    fn complete_with<B: Bits>(tm: &Machine, direction: Side, dfa: DFA) -> Proof<B> {
+        let mut nfa = NFA::new(dfa.len() * tm.states() + 1, tm.symbols());
+        let halt = (dfa.len() * tm.states()) as NFAState;
         Self::init(&dfa, &mut nfa, tm, halt);
         for q_new in 0..dfa.len() as DFAState {
-            for b_new in 0..2 {
+            for b_new in 0..tm.symbols() as u8 {
                 Self::saturate(&dfa, &mut nfa, tm, direction, q_new, b_new);
             }
         }
         let steady_state = row(halt);
-        Some(Proof::new(direction, dfa, nfa, steady_state))
+        Proof::new(direction, dfa, nfa, steady_state)
     }
 
-    /// Try to return a Proof for `tm`, given the choice of scan direction.
-    fn prove_side(&mut self, tm: &Machine, direction: Side) -> Option<Proof> {
-        let greatest_pow2_bound = 1usize << (8 * usize::BITS - self.depth.leading_zeros() - 1);
-        let mut dfas = DFAPrefixIterator::new(self.depth);
-        let mut nfas = vec![NFA::new(self.depth * TM_STATES + 1); 2 * self.depth];
+    /// Try to return a Proof for `tm`, searching with NFA state sets stored as `B`.
+    fn prove_with<B: Bits>(&self, tm: &Machine, deadline: Deadline) -> ProverResult {
+        let (depth, k) = (self.depth, tm.symbols());
+        let cancel = AtomicBool::new(false);
+        let proof = if self.parallel {
+            // Split the search as finely as it takes to give each thread several subtrees.
+            let target = SUBTREES_PER_THREAD * rayon::current_num_threads();
+            let roots = (1..=k * depth)
+                .map(|len| DFAPrefixIterator::subtrees(depth, k, len))
+                .find(|roots| roots.len() >= target)
+                .unwrap_or_else(|| DFAPrefixIterator::subtrees(depth, k, k * depth));
+            let jobs: Vec<(Side, DFAPrefixIterator)> = [Side::R, Side::L]
+                .into_iter()
+                .flat_map(|side| roots.iter().map(move |dfas| (side, dfas.clone())))
+                .collect();
+            // The first proof found (or timeout) stops the other threads' searches.
+            let result = jobs.into_par_iter().find_map_any(|(side, dfas)| {
+                match self.prove_side::<B>(tm, side, dfas, deadline, &cancel) {
+                    Ok(None) => None,
+                    result => {
+                        cancel.store(true, Ordering::Relaxed);
+                        Some(result)
+                    }
+                }
+            });
+            result.transpose()?.flatten()
+        } else {
+            let dfas = || DFAPrefixIterator::new(depth, k);
+            match self.prove_side::<B>(tm, Side::R, dfas(), deadline, &cancel)? {
+                Some(proof) => Some(proof),
+                None => self.prove_side::<B>(tm, Side::L, dfas(), deadline, &cancel)?,
+            }
+        };
+        Ok(proof.map(|proof| proof.widen()))
+    }
+
+    /// Try to return a Proof for `tm`, given the choice of scan direction, from the DFAs `dfas`
+    /// yields. Gives up early if `cancel` is set.
+    fn prove_side<B: Bits>(
+        &self,
+        tm: &Machine,
+        direction: Side,
+        mut dfas: DFAPrefixIterator,
+        deadline: Deadline,
+        cancel: &AtomicBool,
+    ) -> Result<Option<Proof<B>>, TimedOut> {
+        let greatest_pow2_bound = 1usize << (usize::BITS - self.depth.leading_zeros() - 1);
+        let (n, k) = (tm.states(), tm.symbols());
+        let mut nfas = vec![NFA::<B>::new(self.depth * n + 1, k); k * self.depth];
         let mut initial_non_sink_states = 0;
-        let halt = (TM_STATES * self.depth) as NFAState;
-        loop {
-            let (q_new, b_new) = dfas.next()?;
-            let ply = (2 * q_new + b_new) as usize;
+        let halt = (n * self.depth) as NFAState;
+        for step in 0usize.. {
+            // Checking the clock is cheap, but not free compared to a step of the search.
+            if step % 256 == 0 {
+                deadline.check()?;
+                if cancel.load(Ordering::Relaxed) {
+                    return Ok(None);
+                }
+            }
+            let Some((q_new, b_new)) = dfas.next() else {
+                break;
+            };
+            let ply = k * q_new as usize + b_new as usize;
             if ply == 0 {
                 Self::init(&dfas.dfa, &mut nfas[0], tm, halt);
             } else {
                 nfas[ply] = nfas[ply - 1].clone();
             }
-            if cfg!(feature = "sink_heuristic") {
+            if self.sink_heuristic {
                 // Heuristic: Nearly all solutions have a "sink state" transitioning only to itself,
                 // typically recognizing a bit-pattern the TM never writes in its infinite lifetime.
                 // Our DFAs are ordered breadth-first, so one may assume one of the first ~2^k
@@ -80,8 +200,9 @@ impl DirectProver {
                 // far more time than it loses on higher-depth re-searches.
                 initial_non_sink_states = std::cmp::min(initial_non_sink_states, q_new as usize);
                 if initial_non_sink_states == q_new as usize
-                    && (dfas.dfa.t[initial_non_sink_states][0] != q_new
-                        || b_new == 1 && dfas.dfa.t[initial_non_sink_states][1] != q_new)
+                    && dfas.dfa.t[initial_non_sink_states][..=b_new as usize]
+                        .iter()
+                        .any(|&t| t != q_new)
                 {
                     initial_non_sink_states += 1;
                 }
@@ -91,28 +212,30 @@ impl DirectProver {
                 }
             }
             Self::saturate(&dfas.dfa, &mut nfas[ply], tm, direction, q_new, b_new);
-            if row(nfa_start(0, 0)) * nfas[ply].accepted {
+            if row(nfa_start(0, 0, n)) * nfas[ply].accepted {
                 dfas.skip_current_subtree();
                 continue;
             }
             let steady_state = row(halt);
             let nfa = nfas[ply].clone();
-            if (q_new as usize, b_new) == (self.depth - 1, 1) {
-                return Some(Proof::new(direction, dfas.dfa, nfa, steady_state));
+            if ply == k * self.depth - 1 {
+                return Ok(Some(Proof::new(direction, dfas.dfa, nfa, steady_state)));
             }
         }
+        Ok(None)
     }
 
     /// Initialize the NFA from the halt rules, which are independent of our DFA choices.
-    fn init(dfa: &DFA, nfa: &mut NFA, tm: &Machine, halt: NFAState) {
+    fn init<B: Bits>(dfa: &DFA, nfa: &mut NFA<B>, tm: &Machine, halt: NFAState) {
+        let n = tm.states();
         nfa.accepted = col(halt);
-        for b in 0..2 {
-            nfa.t[b][halt] = row(halt);
+        for m in nfa.t.iter_mut() {
+            m[halt] = row(halt);
         }
         tm.rules().for_each(|rule| {
             if let Rule::Halt { f, r } = rule {
                 for q in 0..dfa.len() {
-                    nfa.t[r as usize][nfa_start(q as NFAState, f)] |= row(halt);
+                    nfa.t[r as usize][nfa_start(q as NFAState, f, n)] |= row(halt);
                 }
             }
         })
@@ -122,10 +245,18 @@ impl DirectProver {
     /// given that `dfa` is known up to the `(q_new, b_new)` transition.
     /// The closure conditions for Move rules in the direction opposite our scan direction
     /// depend on the allowed NFA transitions, so this process repeats until there's nothing new.
-    fn saturate(dfa: &DFA, nfa: &mut NFA, tm: &Machine, a_dir: Side, q_new: DFAState, b_new: u8) {
+    fn saturate<B: Bits>(
+        dfa: &DFA,
+        nfa: &mut NFA<B>,
+        tm: &Machine,
+        a_dir: Side,
+        q_new: DFAState,
+        b_new: u8,
+    ) {
+        let start = |q, f| nfa_start(q, f, tm.states());
         tm.rules().for_each(|rule| match rule {
             Rule::Move { f, r, w, d, t } if d == a_dir && w == b_new => {
-                nfa.t[r as usize][nfa_start(q_new, f)] |= row(nfa_start(dfa.step(q_new, w), t));
+                nfa.t[r as usize][start(q_new, f)] |= row(start(dfa.step(q_new, w), t));
             }
             _ => {}
         });
@@ -134,15 +265,15 @@ impl DirectProver {
             tm.rules().for_each(|rule| match rule {
                 Rule::Move { f, r, w, d, t } if d != a_dir => {
                     'qb: for q in 0.. {
-                        for b in 0..2 {
+                        for b in 0..tm.symbols() as u8 {
                             if (q, b) > (q_new, b_new) {
                                 break 'qb;
                             }
                             let q2 = dfa.step(q, b);
-                            let t_r_q2 = nfa.t[r as usize][nfa_start(q2, f)];
-                            let new = nfa.step_vec(nfa.step(nfa_start(q, t), b), w);
-                            nfa.t[r as usize][nfa_start(q2, f)] |= new;
-                            grew |= nfa.t[r as usize][nfa_start(q2, f)] != t_r_q2;
+                            let t_r_q2 = nfa.t[r as usize][start(q2, f)];
+                            let new = nfa.step_vec(nfa.step(start(q, t), b), w);
+                            nfa.t[r as usize][start(q2, f)] |= new;
+                            grew |= nfa.t[r as usize][start(q2, f)] != t_r_q2;
                         }
                     }
                 }
@@ -161,3 +292,53 @@ impl DirectProver {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parallel_matches_sequential() {
+        // The subtrees cover the same search, so the same TMs are solved at each depth.
+        for text in [
+            "1RB1LB_1LA0LB",
+            "1RB---_0RC---_0RD---_0LD1RB",
+            "1RB1LC_0LA0LB_1RC0RA",
+            "1RB0RB_1LC1RA_0LA1LC",
+            "1RB1LA_0LA1RC_1LB0RC",
+        ] {
+            let tm: Machine = text.parse().unwrap();
+            for depth in 1..=4 {
+                let sequential = DirectProver::new(depth).prove(&tm);
+                let parallel = DirectProver::new(depth).in_parallel().prove(&tm);
+                assert_eq!(parallel.is_some(), sequential.is_some(), "{text}");
+                if let Some(proof) = parallel {
+                    assert_eq!(proof.validate(&tm), Ok(()), "{text}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_first_proof() {
+        // The proof `direct` finds at the least depth, and the same from any starting point.
+        let tm: Machine = "1RB1LC_0LA0LB_1RC0RA".parse().unwrap();
+        let expected = (1..=4)
+            .find_map(|depth| DirectProver::new(depth).prove(&tm))
+            .unwrap();
+        for max_depth in [4, expected.automaton.dfa.len()] {
+            let proof = DirectProver::first_proof(&tm, max_depth, Deadline::default())
+                .unwrap()
+                .unwrap();
+            assert_eq!(proof.automaton.direction, expected.automaton.direction);
+            assert_eq!(proof.automaton.dfa.t, expected.automaton.dfa.t);
+            assert_eq!(proof.validate(&tm), Ok(()));
+        }
+        let too_shallow = expected.automaton.dfa.len() - 1;
+        assert!(
+            DirectProver::first_proof(&tm, too_shallow, Deadline::default())
+                .unwrap()
+                .is_none()
+        );
+    }
+}
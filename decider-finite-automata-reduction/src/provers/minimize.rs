@@ -0,0 +1,145 @@
+//! Provers return the first proof they find, which needn't be the smallest.
+//! This post-processing step looks for a smaller one, using the same DFA-first approach as
+//! `DirectProver`: given the DFA and scan direction, `DirectProver::complete_unverified()` builds the
+//! NFA with the fewest transitions and accepted states the closure conditions allow.
+//! So we merge DFA states for as long as the completed proofs stay valid, then tidy up the NFA.
+
+use super::DirectProver;
+use crate::core::{row, DFAState, Machine, NFAState, Proof, RowVector, DFA, NFA};
+
+/// A proof for `tm` no larger than the given (valid) one, and hopefully smaller.
+pub fn minimize(tm: &Machine, proof: &Proof) -> Proof {
+    let direction = proof.automaton.direction;
+    let complete = |dfa| {
+        DirectProver::complete_unverified(tm, direction, dfa).filter(|p| p.validate(tm).is_ok())
+    };
+    let size = |p: &Proof| (p.automaton.dfa.len(), p.automaton.nfa.len());
+    let mut best = proof.clone();
+    if let Some(rebuilt) = complete(renumber(&proof.automaton.dfa)) {
+        if size(&rebuilt) <= size(&best) {
+            best = rebuilt;
+        }
+    }
+    // Greedily merge DFA states -- the later state into the earlier, so state 0 keeps `t[0][0] == 0`.
+    'merge: loop {
+        let dfa = &best.automaton.dfa;
+        for q in (1..dfa.len()).rev() {
+            for p in 0..q {
+                if let Some(merged) = complete(renumber(&merge(dfa, q, p))) {
+                    best = merged;
+                    continue 'merge;
+                }
+            }
+        }
+        break;
+    }
+    let pruned = shrink_steady_state(tm, prune(tm, best.clone()));
+    if pruned.validate(tm).is_ok() {
+        pruned
+    } else {
+        best
+    }
+}
+
+/// The DFA with state `q` merged into state `p`. (State `q` becomes unreachable.)
+fn merge(dfa: &DFA, q: usize, p: usize) -> DFA {
+    let t = (dfa.t.iter())
+        .map(|row| {
+            row.iter()
+                .map(|&s| if s as usize == q { p as DFAState } else { s })
+        })
+        .map(|row| row.collect())
+        .collect();
+    DFA { t }
+}
+
+/// The DFA's reachable states, renumbered in breadth-first order (as in `DFAIterator`).
+fn renumber(dfa: &DFA) -> DFA {
+    let (mut order, mut id) = (vec![0usize], vec![None; dfa.len()]);
+    id[0] = Some(0);
+    let mut i = 0;
+    while i < order.len() {
+        for &s in dfa.t[order[i]].iter() {
+            if id[s as usize].is_none() {
+                id[s as usize] = Some(order.len() as DFAState);
+                order.push(s as usize);
+            }
+        }
+        i += 1;
+    }
+    let t = (order.iter())
+        .map(|&q| dfa.t[q].iter().map(|&s| id[s as usize].unwrap()).collect())
+        .collect();
+    DFA { t }
+}
+
+/// Drop the NFA states no start state can reach (other than the states `nfa_start(q, f)`,
+/// which must exist, and the steady state).
+fn prune(tm: &Machine, mut proof: Proof) -> Proof {
+    let (dfa, nfa) = (&proof.automaton.dfa, &proof.automaton.nfa);
+    let starts = dfa.len() * tm.states();
+    let mut reachable: RowVector = (0..starts as NFAState).collect();
+    loop {
+        let next = (0..nfa.symbols() as u8).fold(reachable, |v, b| v | nfa.step_vec(reachable, b));
+        if next == reachable {
+            break;
+        }
+        reachable = next;
+    }
+    let keep = |s: usize| s < starts || reachable | proof.steady_state >= row(s as NFAState);
+    let mut id = vec![None; nfa.len()];
+    let kept: Vec<usize> = (0..nfa.len()).filter(|&s| keep(s)).collect();
+    for (new, &old) in kept.iter().enumerate() {
+        id[old] = Some(new as NFAState);
+    }
+    let map_row = |v: RowVector| v.into_iter().filter_map(|s| id[s as usize]).collect();
+    let mut pruned = NFA::new(kept.len(), nfa.symbols());
+    for (m_new, m_old) in pruned.t.iter_mut().zip(nfa.t.iter()) {
+        for (new, &old) in kept.iter().enumerate() {
+            m_new[new as NFAState] = map_row(m_old[old as NFAState]);
+        }
+    }
+    pruned.accepted = (nfa.accepted.into_iter())
+        .filter_map(|s| id[s as usize])
+        .collect();
+    proof.steady_state = map_row(proof.steady_state);
+    proof.automaton.nfa = pruned;
+    proof
+}
+
+/// Drop states from the steady state, for as long as the proof stays valid.
+fn shrink_steady_state(tm: &Machine, mut proof: Proof) -> Proof {
+    for s in proof.steady_state {
+        let old = proof.steady_state;
+        proof.steady_state = old.into_iter().filter(|&s2| s2 != s).collect();
+        if proof.validate(tm).is_err() {
+            proof.steady_state = old;
+        }
+    }
+    proof
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provers::{Prover, ProverOptions};
+
+    #[test]
+    fn test_minimize() {
+        let tm: Machine = "1RB1LB_1LA0LB".parse().unwrap();
+        let small = DirectProver::new(1)
+            .prove(&tm)
+            .expect("solvable at depth 1");
+        // Pad the proof with a copy of each DFA state, then see if minimizing undoes the damage.
+        let mut dfa = small.automaton.dfa.clone();
+        dfa.t.extend(dfa.t.clone());
+        dfa.t[0][1] = 1;
+        let padded =
+            DirectProver::complete_unverified(&tm, small.automaton.direction, dfa).unwrap();
+        assert!(padded.validate(&tm).is_ok());
+        let minimized = minimize(&tm, &padded);
+        assert!(minimized.validate(&tm).is_ok());
+        assert_eq!(minimized.automaton.dfa.len(), 1);
+        assert_eq!(minimized.automaton.nfa.len(), tm.states() + 1);
+    }
+}
@@ -10,40 +10,75 @@
 //! - @djmati1111 (https://github.com/colette-b/bbchallenge)
 //! - @Mateon1 (https://discuss.bbchallenge.org/u/mateon1)
 
-use super::{DirectProver, Prover, ProverOptions};
-use crate::core::{DFAState, Machine, Proof, Rule, Side, TMState, DFA, TM_STATES};
-use cat_solver::Solver;
+use super::sat::{ClauseSink, Dimacs, SatBackend, SatSolver};
+use super::{Deadline, DirectProver, Prover, ProverOptions, ProverResult};
+use crate::core::{max_dfa, DFAState, Machine, Rule, Side, TMState, DFA};
 use std::cmp::min;
 
 /// A prover which searches for "Meet-in-the-Middle DFA" recognizers.
 pub struct MitMDFAProver {
     n: i32,
+    /// The number of TM states, for the TM currently being proven.
+    t: i32,
+    /// The number of TM symbols, for the TM currently being proven.
+    k: i32,
+    backend: SatBackend,
 }
 
 impl Prover for MitMDFAProver {
     fn name(&self) -> String {
-        format!("mitm_dfa-{}", self.n)
+        format!("mitm_dfa{}-{}", self.backend.suffix(), self.n)
     }
 
-    fn prove(&mut self, tm: &Machine) -> Option<Proof> {
-        let mut solver = self.init(self.n, tm);
-        if solver.solve() == Some(true) {
-            let mut dfa = DFA::new(self.n as usize);
+    fn prove_within(&mut self, tm: &Machine, deadline: Deadline) -> ProverResult {
+        if self.n as usize > max_dfa(tm.states()) {
+            return Ok(None);
+        }
+        self.t = tm.states() as L;
+        self.k = tm.symbols() as L;
+        deadline.check()?;
+        let mut solver = self.backend.solver(deadline);
+        self.init(self.n, tm, &mut solver);
+        if solver.solve(&[], deadline)? {
+            let mut dfa = DFA::new(self.n as usize, tm.symbols());
             for q in 0..dfa.len() {
-                for b in 0..2 {
+                for b in 0..dfa.symbols() {
                     dfa.t[q][b] = self.dfa_eval(&solver, FROM_LEFT, q as DFAState, b as u8);
                 }
             }
-            DirectProver::complete_unverified(tm, Side::R, dfa)
+            Ok(DirectProver::complete_unverified(tm, Side::R, dfa))
         } else {
-            None
+            Ok(None)
+        }
+    }
+
+    fn cnf(&mut self, tm: &Machine) -> Option<String> {
+        if self.n as usize > max_dfa(tm.states()) {
+            return None;
         }
+        self.t = tm.states() as L;
+        self.k = tm.symbols() as L;
+        let mut cnf = Dimacs::default();
+        self.init(self.n, tm, &mut cnf);
+        Some(cnf.to_string())
     }
 }
 
 impl ProverOptions for MitMDFAProver {
     fn new(depth: usize) -> Self {
-        MitMDFAProver { n: depth as L }
+        MitMDFAProver {
+            n: depth as L,
+            t: 0,
+            k: 0,
+            backend: SatBackend::default(),
+        }
+    }
+}
+
+impl MitMDFAProver {
+    /// This prover, using the given SAT solver.
+    pub fn with_backend(self, backend: SatBackend) -> Self {
+        MitMDFAProver { backend, ..self }
     }
 }
 
@@ -58,7 +93,6 @@ impl ProverOptions for MitMDFAProver {
 type L = i32;
 const TRUE: L = 1;
 const FALSE: L = -TRUE;
-const T: L = TM_STATES as L;
 const FROM_LEFT: L = 0;
 const FROM_RIGHT: L = 1;
 
@@ -68,32 +102,37 @@ fn a(b: L, h: L) -> L {
     (s * (s - 1)) / 2 + (b - s) * h
 }
 
+// With `k` symbols, `qb = k*q + b` indexes a DFA transition, and `dfa_t_eq`/`dfa_t_le` variables
+// exist for `(qb, t)` in a trapezoid. The counts per L/R DFA are `_n_dfa_t_eq` and `_n_dfa_t_le`.
 #[rustfmt::skip]
 impl MitMDFAProver {
-    fn _dfa_t_eq(n: L, lr: L, qb: L, t: L) -> L { lr + 2*(qb-1 + a(2*n  , t  )) + 2 }
-    fn _dfa_t_le(n: L, lr: L, qb: L, t: L) -> L { lr + 2*(qb-2 + a(2*n-2, t-1)) + n*(1+3*n) }
-    fn _accepted(n: L, ql: L, f: L, r: L, qr: L) -> L { ql + n*(f + T*(r + 2*qr)) + 6*n*(n-1) + 1 }
-    fn _aux_var0(n: L) -> L { n*T*2*n + 6*n*(n-1) + 1 }
+    fn _n_dfa_t_eq(n: L, k: L) -> L { k*n*n - 1 - n*(n-1)/2 }
+    fn _n_dfa_t_le(n: L, k: L) -> L { if n < 2 { 0 } else { (n-2)*(k*n-1) - (n-2)*(n-1)/2 } }
+    fn _dfa_t_eq(n: L, k: L, lr: L, qb: L, t: L) -> L { lr + 2*(qb-1 + a(k*n  , t  )) + 2 }
+    fn _dfa_t_le(n: L, k: L, lr: L, qb: L, t: L) -> L { lr + 2*(qb-2 + a(k*n-2, t-1)) + 2 + 2*Self::_n_dfa_t_eq(n, k) }
+    fn _accepted(n: L, k: L, t: L, ql: L, f: L, r: L, qr: L) -> L { ql + n*(f + t*(r + k*qr)) + Self::_accepted0(n, k) }
+    fn _accepted0(n: L, k: L) -> L { 1 + 2*(Self::_n_dfa_t_eq(n, k) + Self::_n_dfa_t_le(n, k)) }
+    fn _aux_var0(n: L, k: L, t: L) -> L { n*t*k*n + Self::_accepted0(n, k) }
 }
 
 impl MitMDFAProver {
     fn dfa(&self, lr: i32, q: DFAState, b: u8, t: L) -> L {
-        let qb = 2 * (q as L) + (b as L);
+        let qb = self.k * (q as L) + (b as L);
         if (qb, t) == (0, 0) {
             TRUE
         } else if 0 <= t && t <= qb && t < self.n {
-            Self::_dfa_t_eq(self.n, lr, qb, t)
+            Self::_dfa_t_eq(self.n, self.k, lr, qb, t)
         } else {
             FALSE
         }
     }
 
     fn dfa_le(&self, lr: i32, q: DFAState, b: u8, t: L) -> L {
-        let qb = 2 * (q as L) + (b as L);
+        let qb = self.k * (q as L) + (b as L);
         if t <= 0 {
             self.dfa(lr, q, b, t)
         } else if t < qb && t < self.n - 1 {
-            Self::_dfa_t_le(self.n, lr, qb, t)
+            Self::_dfa_t_le(self.n, self.k, lr, qb, t)
         } else {
             TRUE
         }
@@ -103,11 +142,11 @@ impl MitMDFAProver {
         if (ql, f, r, qr) == (0, 0, 0, 0) {
             FALSE
         } else {
-            Self::_accepted(self.n, ql as L, f as L, r as L, qr as L)
+            Self::_accepted(self.n, self.k, self.t, ql as L, f as L, r as L, qr as L)
         }
     }
 
-    fn dfa_eval(&self, solver: &Solver, lr: i32, q: DFAState, b: u8) -> DFAState {
+    fn dfa_eval<S: SatSolver + ?Sized>(&self, solver: &S, lr: i32, q: DFAState, b: u8) -> DFAState {
         // We have the less-or-equal variables solved, but a linear search is more than fast enough.
         let max_val = (self.n - 1) as DFAState;
         for t in 0..max_val {
@@ -119,24 +158,25 @@ impl MitMDFAProver {
         max_val
     }
 
-    fn init(&mut self, n: L, tm: &Machine) -> Solver {
-        let mut solver = Solver::new();
-        solver.add_clause([TRUE]);
+    fn init<S: ClauseSink + ?Sized>(&self, n: L, tm: &Machine, solver: &mut S) {
+        solver.add_clause(&[TRUE]);
+        let k = self.k;
         // DFA transitions:
         for lr in 0..2 {
             for q in 0..(n as DFAState) {
-                for b in 0..2 {
+                for b in 0..(k as u8) {
                     // Outcomes are mutually exclusive.
                     for t in 0..n {
-                        solver.add_clause([-self.dfa(lr, q, b, t), self.dfa_le(lr, q, b, t)]);
+                        solver.add_clause(&[-self.dfa(lr, q, b, t), self.dfa_le(lr, q, b, t)]);
                         solver
-                            .add_clause([-self.dfa_le(lr, q, b, t), self.dfa_le(lr, q, b, t + 1)]);
-                        solver.add_clause([-self.dfa(lr, q, b, t + 1), -self.dfa_le(lr, q, b, t)]);
+                            .add_clause(&[-self.dfa_le(lr, q, b, t), self.dfa_le(lr, q, b, t + 1)]);
+                        solver.add_clause(&[-self.dfa(lr, q, b, t + 1), -self.dfa_le(lr, q, b, t)]);
                     }
                     // An outcome occurs.
                     if (q, b) != (0, 0) {
-                        let tmax = min(2 * (q as L) + (b as L) + 1, n);
-                        solver.add_clause((0..tmax).map(|t| self.dfa(lr, q, b, t)));
+                        let tmax = min(k * (q as L) + (b as L) + 1, n);
+                        let clause: Vec<L> = (0..tmax).map(|t| self.dfa(lr, q, b, t)).collect();
+                        solver.add_clause(&clause);
                     }
                 }
             }
@@ -145,14 +185,14 @@ impl MitMDFAProver {
         for ql in 0..(n as DFAState) {
             for qr in 0..(n as DFAState) {
                 tm.rules().for_each(|rule| match rule {
-                    Rule::Halt { f, r } => solver.add_clause([self.accept(ql, f, r, qr)]),
+                    Rule::Halt { f, r } => solver.add_clause(&[self.accept(ql, f, r, qr)]),
                     Rule::Move { f, r, w, d, t } => {
-                        for b in 0..2 {
+                        for b in 0..(k as u8) {
                             for qw in 0..(n as DFAState) {
                                 for qb in 0..(n as DFAState) {
                                     if d == Side::L {
                                         // Transition: b f@r -> t@b w
-                                        solver.add_clause([
+                                        solver.add_clause(&[
                                             -self.dfa(FROM_LEFT, ql, b, qb as L),
                                             -self.dfa(FROM_RIGHT, qr, w, qw as L),
                                             -self.accept(ql, t, b, qw),
@@ -160,7 +200,7 @@ impl MitMDFAProver {
                                         ]);
                                     } else {
                                         // Transition: f@r b -> w t@b
-                                        solver.add_clause([
+                                        solver.add_clause(&[
                                             -self.dfa(FROM_RIGHT, qr, b, qb as L),
                                             -self.dfa(FROM_LEFT, ql, w, qw as L),
                                             -self.accept(qw, t, b, qr),
@@ -180,49 +220,89 @@ impl MitMDFAProver {
         // (See also a simpler version in z3py by colette-b/@djmati1111:
         // https://github.com/colette-b/bbchallenge/blob/main/sat2_cfl.py#L64 plus chat explanation
         // https://discord.com/channels/960643023006490684/1028746861395316776/1030907938249912431.)
-        // As in dfa_iterator.rs, for each qb>0, define `tmax[qb] = max{dfa.t[q][b] | 2*q+b < qb}`.
-        // `tmax[qb]` is `>= qb/2` (this state must be reachable) and `< min(n, qb)`
+        // As in dfa_iterator.rs, for each qb>0, define `tmax[qb] = max{dfa.t[q][b] | k*q+b < qb}`.
+        // `tmax[qb]` is `>= qb/k` (this state must be reachable) and `< min(n, qb)`
         // (transition values can't go out of bounds or increase at a rate above 1).
 
         // Let's set up variables for the L/R `tmax[qb] == m`, with index base[qb]+lr+2*m.
-        let mut base = vec![Self::_aux_var0(n); 2 * n as usize];
-        for qb in 1..(2 * n as usize) {
-            let choices = min(n as usize, qb) - (qb / 2) + 1;
-            if choices > 1 && qb + 1 < 2 * (n as usize) {
+        let mut base = vec![Self::_aux_var0(n, k, self.t); (k * n) as usize];
+        for qb in 1..((k * n) as usize) {
+            let choices = min(n as usize, qb) - (qb / k as usize) + 1;
+            if choices > 1 && qb + 1 < (k * n) as usize {
                 base[qb + 1] = base[qb] + 2 * (choices as L);
             }
-            base[qb] -= 2 * (qb as L / 2);
+            base[qb] -= 2 * (qb as L / k);
         }
-        fn tmax_eq(n: L, lr: L, qb: L, m: L, base: &[L]) -> L {
-            if (qb, m) == (2 * n, n - 1) {
+        fn tmax_eq(n: L, k: L, lr: L, qb: L, m: L, base: &[L]) -> L {
+            if (qb, m) == (k * n, n - 1) {
                 TRUE
-            } else if m < (qb / 2) || m >= min(n, qb) {
+            } else if m < (qb / k) || m >= min(n, qb) {
                 FALSE
-            } else if min(n, qb) - (qb / 2) <= 1 {
+            } else if min(n, qb) - (qb / k) <= 1 {
                 TRUE
             } else {
                 base[qb as usize] + lr + 2 * m
             }
         }
-        for qb in 1..2 * n {
-            let (q, b) = ((qb / 2) as DFAState, (qb % 2) as u8);
-            for m in qb / 2..min(n, qb) {
+        for qb in 1..k * n {
+            let (q, b) = ((qb / k) as DFAState, (qb % k) as u8);
+            for m in qb / k..min(n, qb) {
                 for lr in 0..2 {
-                    solver
-                        .add_clause([-tmax_eq(n, lr, qb, m, &base), self.dfa_le(lr, q, b, m + 1)]);
-                    solver.add_clause([
-                        -tmax_eq(n, lr, qb, m, &base),
-                        -self.dfa_le(lr, q, b, m),
-                        tmax_eq(n, lr, qb + 1, m, &base),
-                    ]);
-                    solver.add_clause([
-                        -tmax_eq(n, lr, qb, m, &base),
-                        -self.dfa(lr, q, b, m + 1),
-                        tmax_eq(n, lr, qb + 1, m + 1, &base),
-                    ]);
+                    let eq = |qb, m| tmax_eq(n, k, lr, qb, m, &base);
+                    solver.add_clause(&[-eq(qb, m), self.dfa_le(lr, q, b, m + 1)]);
+                    solver.add_clause(&[-eq(qb, m), -self.dfa_le(lr, q, b, m), eq(qb + 1, m)]);
+                    solver.add_clause(&[-eq(qb, m), -self.dfa(lr, q, b, m + 1), eq(qb + 1, m + 1)]);
                 }
             }
         }
-        solver
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_variable_numbering() {
+        // Every DFA and acceptance variable gets its own index, below the auxiliary variables.
+        // (`dfa_le(.., 0)` is the same condition as `dfa(.., 0)`, so it shares that variable.)
+        for (n, k, t) in [
+            (1, 2, 5),
+            (2, 2, 5),
+            (3, 2, 5),
+            (5, 2, 5),
+            (1, 3, 2),
+            (4, 3, 2),
+            (3, 4, 3),
+        ] {
+            let prover = MitMDFAProver {
+                n,
+                t,
+                k,
+                backend: SatBackend::default(),
+            };
+            let mut vars = Vec::new();
+            for lr in 0..2 {
+                for q in 0..n as DFAState {
+                    for b in 0..k as u8 {
+                        vars.extend((0..n).map(|v| prover.dfa(lr, q, b, v)));
+                        vars.extend((1..n).map(|v| prover.dfa_le(lr, q, b, v)));
+                    }
+                }
+            }
+            for ql in 0..n as DFAState {
+                for f in 0..t as TMState {
+                    for r in 0..k as u8 {
+                        vars.extend((0..n as DFAState).map(|qr| prover.accept(ql, f, r, qr)));
+                    }
+                }
+            }
+            let mut seen = HashSet::new();
+            for var in vars.into_iter().filter(|var| var.abs() != TRUE) {
+                assert!(1 < var && var < MitMDFAProver::_aux_var0(n, k, t));
+                assert!(seen.insert(var), "variable {var} reused (n={n}, k={k})");
+            }
+        }
     }
 }
@@ -0,0 +1,386 @@
+//! An incremental variant of the search in mitm_dfa.rs: rather than set up a fresh SAT instance for
+//! each DFA size, we keep one solver per TM, and add a DFA state (with its variables and clauses)
+//! each time the search moves from depth `n` to `n+1`. The solver keeps what it has learned.
+//!
+//! For that to be sound, no clause may depend on the number of DFA states. The non-incremental
+//! encoding bakes `n` in (e.g., "transitions go to a state `< n`" and "the last transition leaves
+//! `n-1` as the highest state used so far"), so we rephrase those conditions in terms of variables
+//! `exists(q)`: "the DFAs have a state `q`". Searching at depth `n`, we pass the solver assumptions
+//! `exists(n-1)` and `!exists(n)`, which disable the states we haven't added yet.
+//!
+//! Each variable and clause is concerned with a few DFA states. A clause gets added at step `s`,
+//! when state `s` is added, if `s` is the largest state it involves -- so the instance after step
+//! `s` is the full encoding for DFAs with states `0..=s`.
+
+use super::sat::{ClauseSink, Dimacs, SatBackend, SatSolver};
+use super::{Deadline, DirectProver, Prover, ProverBox, ProverOptions, ProverResult, TimedOut};
+use crate::core::{max_dfa, DFAState, Machine, Rule, Side, DFA};
+use std::cmp::{max, min};
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// A prover which searches for "Meet-in-the-Middle DFA" recognizers at each depth up to its own,
+/// with one incremental SAT instance.
+pub struct IncrementalMitMDFAProver {
+    n: usize,
+    /// The first depth to search at. (The instance still grows from depth 1, without solving.)
+    start: usize,
+    backend: SatBackend,
+}
+
+impl Prover for IncrementalMitMDFAProver {
+    /// E.g. `mitm_dfa_inc-12`, or `mitm_dfa_inc-6..=12` if it skips depths 1 to 5.
+    fn name(&self) -> String {
+        let depths = match self.start {
+            0 | 1 => self.n.to_string(),
+            start => format!("{}..={}", start, self.n),
+        };
+        format!("mitm_dfa_inc{}-{}", self.backend.suffix(), depths)
+    }
+
+    fn prove_within(&mut self, tm: &Machine, deadline: Deadline) -> ProverResult {
+        let mut encoding = Encoding::new(tm, self.backend.solver(deadline));
+        for n in 1..=min(self.n, max_dfa(tm.states())) {
+            deadline.check()?;
+            encoding.add_state(tm);
+            if n < self.start {
+                continue;
+            }
+            if let Some(dfa) = encoding.solve(n as L, deadline)? {
+                return Ok(DirectProver::complete_unverified(tm, Side::R, dfa));
+            }
+        }
+        Ok(None)
+    }
+
+    /// The instance at this prover's depth, with the assumptions as unit clauses.
+    fn cnf(&mut self, tm: &Machine) -> Option<String> {
+        let n = min(self.n, max_dfa(tm.states()));
+        let mut encoding = Encoding::new(tm, Dimacs::default());
+        (0..n).for_each(|_| encoding.add_state(tm));
+        let (assumptions, mut bytes) = (encoding.assumptions(n as L), vec![]);
+        encoding.solver.write(&assumptions, &mut bytes).ok()?;
+        String::from_utf8(bytes).ok()
+    }
+}
+
+impl ProverOptions for IncrementalMitMDFAProver {
+    fn new(depth: usize) -> Self {
+        IncrementalMitMDFAProver {
+            n: depth,
+            start: 1,
+            backend: SatBackend::default(),
+        }
+    }
+
+    /// One instance covers the whole range, up to its highest depth.
+    fn new_range(range: Range<usize>, tm_states: usize) -> Vec<ProverBox> {
+        let depths = Self::legal_depths(range, tm_states);
+        (depths.clone().last())
+            .map(|depth| Box::new(Self::new(depth).starting_at(depths.start)) as _)
+            .into_iter()
+            .collect()
+    }
+}
+
+impl IncrementalMitMDFAProver {
+    /// This prover, skipping the depths below `start`.
+    pub fn starting_at(self, start: usize) -> Self {
+        IncrementalMitMDFAProver { start, ..self }
+    }
+
+    /// This prover, using the given SAT solver. (An external solver starts over at each depth.)
+    pub fn with_backend(self, backend: SatBackend) -> Self {
+        IncrementalMitMDFAProver { backend, ..self }
+    }
+}
+
+// Literals are as in mitm_dfa.rs, but variables are allocated as they're first used.
+type L = i32;
+const TRUE: L = 1;
+const FALSE: L = -TRUE;
+const FROM_LEFT: L = 0;
+const FROM_RIGHT: L = 1;
+
+/// The meaning of a SAT variable. Transitions `(q, b)` are indexed by `qb = k*q + b`.
+#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq)]
+enum Var {
+    /// The DFA has a state `q`.
+    Exists(L),
+    /// The DFA (`lr` = FROM_LEFT/FROM_RIGHT) transition `qb` goes to state `t`.
+    Dfa(L, L, L),
+    /// The DFA transition `qb` goes to a state `<= t`.
+    DfaLe(L, L, L),
+    /// The tuple `(ql, f, r, qr)` is accepted.
+    Accepted(L, L, L, L),
+    /// The highest state reached by the DFA's transitions before `qb` is `m`.
+    TMax(L, L, L),
+}
+
+/// A SAT instance for the MitM-DFA conditions, which grows a DFA state at a time.
+struct Encoding<S> {
+    solver: S,
+    vars: HashMap<Var, L>,
+    /// The number of TM symbols.
+    k: L,
+    /// The number of DFA states added so far.
+    n: L,
+}
+
+impl<S: ClauseSink> Encoding<S> {
+    fn new(tm: &Machine, mut solver: S) -> Encoding<S> {
+        solver.add_clause(&[TRUE]);
+        Encoding {
+            solver,
+            vars: HashMap::new(),
+            k: tm.symbols() as L,
+            n: 0,
+        }
+    }
+
+    fn var(&mut self, var: Var) -> L {
+        let next = TRUE + 1 + self.vars.len() as L;
+        *self.vars.entry(var).or_insert(next)
+    }
+
+    fn exists(&mut self, q: L) -> L {
+        if q == 0 {
+            TRUE
+        } else {
+            self.var(Var::Exists(q))
+        }
+    }
+
+    fn dfa(&mut self, lr: L, q: L, b: L, t: L) -> L {
+        let qb = self.k * q + b;
+        if (qb, t) == (0, 0) {
+            TRUE
+        } else if t < 0 || t > qb {
+            FALSE
+        } else {
+            self.var(Var::Dfa(lr, qb, t))
+        }
+    }
+
+    fn dfa_le(&mut self, lr: L, q: L, b: L, t: L) -> L {
+        let qb = self.k * q + b;
+        if t <= 0 {
+            self.dfa(lr, q, b, t)
+        } else if t >= qb {
+            TRUE
+        } else {
+            self.var(Var::DfaLe(lr, qb, t))
+        }
+    }
+
+    fn accept(&mut self, ql: L, f: L, r: L, qr: L) -> L {
+        if (ql, f, r, qr) == (0, 0, 0, 0) {
+            FALSE
+        } else {
+            self.var(Var::Accepted(ql, f, r, qr))
+        }
+    }
+
+    /// As in mitm_dfa.rs, `tmax[qb]` is `>= qb/k` and `< qb`. This involves states `m` and
+    /// `(qb-1)/k`. The exception: after the last transition of state `s-1`, `tmax[k*s]` may be
+    /// `s-1` if state `s` doesn't exist.
+    fn tmax_eq(&mut self, lr: L, qb: L, m: L) -> L {
+        if (qb, m) == (1, 0) {
+            TRUE
+        } else if m >= qb {
+            FALSE
+        } else if m < qb / self.k {
+            if qb % self.k == 0 && m == qb / self.k - 1 {
+                -self.exists(qb / self.k)
+            } else {
+                FALSE
+            }
+        } else {
+            self.var(Var::TMax(lr, qb, m))
+        }
+    }
+
+    /// Add DFA state `s = self.n` -- that is, the clauses whose largest DFA state is `s`.
+    fn add_state(&mut self, tm: &Machine) {
+        let (s, k) = (self.n, self.k);
+        self.n += 1;
+        let mut clauses: Vec<Vec<L>> = vec![];
+        let exists = [self.exists(s + 1), self.exists(s)];
+        clauses.push(vec![-exists[0], exists[1]]);
+        // DFA transitions: the outcome `t` of transition `(q, b)` involves states `q` and `t`.
+        for lr in 0..2 {
+            for q in 0..=s {
+                for b in 0..k {
+                    let qb = k * q + b;
+                    let ts = if q == s {
+                        0..=min(s, qb)
+                    } else {
+                        s..=min(s, qb)
+                    };
+                    for t in ts {
+                        let eq = self.dfa(lr, q, b, t);
+                        let [le, le_prev] =
+                            [self.dfa_le(lr, q, b, t), self.dfa_le(lr, q, b, t - 1)];
+                        // Outcomes are mutually exclusive, and one occurs.
+                        clauses.push(vec![-eq, le]);
+                        clauses.push(vec![-le_prev, le]);
+                        clauses.push(vec![-eq, -le_prev]);
+                        clauses.push(vec![-le, le_prev, eq]);
+                        // Transitions only go to states which exist.
+                        if t < qb {
+                            clauses.push(vec![le, self.exists(t + 1)]);
+                        }
+                    }
+                }
+            }
+        }
+        // Closure conditions, as in mitm_dfa.rs:
+        for ql in 0..=s {
+            for qr in 0..=s {
+                for rule in tm.rules() {
+                    match rule {
+                        Rule::Halt { f, r } if max(ql, qr) == s => {
+                            clauses.push(vec![self.accept(ql, f as L, r as L, qr)]);
+                        }
+                        Rule::Halt { .. } => {}
+                        Rule::Move { f, r, w, d, t } => {
+                            let (f, r, w, t) = (f as L, r as L, w as L, t as L);
+                            for b in 0..k {
+                                for qw in 0..=s {
+                                    for qb in 0..=s {
+                                        if max(max(ql, qr), max(qw, qb)) < s {
+                                            continue;
+                                        }
+                                        clauses.push(if d == Side::L {
+                                            // Transition: b f@r -> t@b w
+                                            vec![
+                                                -self.dfa(FROM_LEFT, ql, b, qb),
+                                                -self.dfa(FROM_RIGHT, qr, w, qw),
+                                                -self.accept(ql, t, b, qw),
+                                                self.accept(qb, f, r, qr),
+                                            ]
+                                        } else {
+                                            // Transition: f@r b -> w t@b
+                                            vec![
+                                                -self.dfa(FROM_RIGHT, qr, b, qb),
+                                                -self.dfa(FROM_LEFT, ql, w, qw),
+                                                -self.accept(qw, t, b, qr),
+                                                self.accept(ql, f, r, qb),
+                                            ]
+                                        });
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        // DFA ordering criteria, as in mitm_dfa.rs. The clauses for `(qb, m)` involve states
+        // `max(q, m)` or `max(q, m+1)`, where `q = qb/k`.
+        for lr in 0..2 {
+            for q in 0..=s {
+                for b in 0..k {
+                    let qb = k * q + b;
+                    for m in 0..=s {
+                        let eq = self.tmax_eq(lr, qb, m);
+                        if eq == FALSE {
+                            continue;
+                        }
+                        if max(q, m + 1) == s {
+                            let le_next = self.dfa_le(lr, q, b, m + 1);
+                            let eq_next = self.dfa(lr, q, b, m + 1);
+                            let tmax_next = self.tmax_eq(lr, qb + 1, m + 1);
+                            clauses.push(vec![-eq, le_next]);
+                            clauses.push(vec![-eq, -eq_next, tmax_next]);
+                        }
+                        if max(q, m) == s {
+                            let le = self.dfa_le(lr, q, b, m);
+                            let tmax_same = self.tmax_eq(lr, qb + 1, m);
+                            clauses.push(vec![-eq, -le, tmax_same]);
+                        }
+                    }
+                }
+            }
+        }
+        for clause in clauses {
+            self.solver.add_clause(&clause);
+        }
+    }
+
+    /// The assumptions which limit the DFAs to exactly `n` states (which must have been added).
+    fn assumptions(&mut self, n: L) -> [L; 2] {
+        [self.exists(n - 1), -self.exists(n)]
+    }
+}
+
+impl<S: SatSolver> Encoding<S> {
+    /// Solve for DFAs with exactly `n` states.
+    fn solve(&mut self, n: L, deadline: Deadline) -> Result<Option<DFA>, TimedOut> {
+        let assumptions = self.assumptions(n);
+        if !self.solver.solve(&assumptions, deadline)? {
+            return Ok(None);
+        }
+        let mut dfa = DFA::new(n as usize, self.k as usize);
+        for q in 0..n {
+            for b in 0..self.k {
+                // As in mitm_dfa.rs, a linear search is more than fast enough.
+                let mut t = 0;
+                while t < n - 1 {
+                    let lit = self.dfa(FROM_LEFT, q, b, t);
+                    if self.solver.value(lit) != Some(false) {
+                        break;
+                    }
+                    t += 1;
+                }
+                dfa.t[q as usize][b as usize] = t as DFAState;
+            }
+        }
+        Ok(Some(dfa))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::Proof;
+    use crate::provers::MitMDFAProver;
+
+    #[test]
+    fn test_incremental_matches_mitm_dfa() {
+        // The incremental search finds a proof at the first depth where a fresh search would.
+        for text in [
+            "1RB1LB_1LA0LB",
+            "1RB---_0RC---_0RD---_0LD1RB",
+            "1RB1LC_0LA0LB_1RC0RA",
+            "1RB0RB_1LC1RA_0LA1LC",
+            "1RB1LA_0LA1RC_1LB0RC",
+        ] {
+            let tm: Machine = text.parse().unwrap();
+            let fresh = (1..=4).find(|&n| MitMDFAProver::new(n).prove(&tm).is_some());
+            let mut incremental = IncrementalMitMDFAProver::new(4);
+            let proof = incremental.prove(&tm);
+            assert_eq!(proof.is_some(), fresh.is_some(), "{text}");
+            if let (Some(proof), Some(n)) = (proof, fresh) {
+                assert_eq!(proof.automaton.dfa.len(), n, "{text}");
+                assert_eq!(proof.validate(&tm), Ok(()), "{text}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_starting_depth() {
+        // Solvable at depth 1, so a search from depth 2 finds a different proof (if any).
+        let tm: Machine = "1RB1LB_1LA0LB".parse().unwrap();
+        let dfa_len = |proof: Option<Proof>| proof.map(|p| p.automaton.dfa.len());
+        let mut skipping = IncrementalMitMDFAProver::new_range(2..3, 2).pop().unwrap();
+        assert_eq!(skipping.name(), "mitm_dfa_inc-2..=2");
+        let named = crate::provers::prover_by_name(skipping.name(), 2).map(|p| p.name());
+        assert_eq!(named, Some(skipping.name()));
+        let fresh = MitMDFAProver::new(2).prove(&tm);
+        assert_eq!(dfa_len(skipping.prove(&tm)), dfa_len(fresh));
+        let mut from_1 = IncrementalMitMDFAProver::new_range(0..3, 2).pop().unwrap();
+        assert_eq!(from_1.name(), "mitm_dfa_inc-2");
+        assert_eq!(dfa_len(from_1.prove(&tm)), Some(1));
+    }
+}
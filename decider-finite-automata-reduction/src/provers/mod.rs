@@ -5,12 +5,21 @@
 
 mod dfa_iterator;
 mod direct;
+mod minimize;
 mod mitm_dfa;
+mod mitm_dfa_incremental;
 mod prover;
+mod sat;
+mod transfer;
 
 pub use dfa_iterator::{DFAIterator, DFAPrefixIterator};
 pub use direct::DirectProver;
+pub use minimize::minimize;
 pub use mitm_dfa::MitMDFAProver;
+pub use mitm_dfa_incremental::IncrementalMitMDFAProver;
 pub use prover::{
-    prover_by_name, prover_names, prover_range_by_name, Prover, ProverBox, ProverOptions,
+    prover_by_name, prover_names, prover_range_by_name, Deadline, Prover, ProverBox, ProverOptions,
+    ProverResult, TimedOut,
 };
+pub use sat::{ClauseSink, Dimacs, ExternalSolver, Lit, SatBackend, SatSolver};
+pub use transfer::transfer;
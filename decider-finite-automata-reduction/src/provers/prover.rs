@@ -1,37 +1,93 @@
 //! A common interface for the actual Proof-finding code.
 
-use super::{DirectProver, MitMDFAProver};
-use crate::core::{Machine, Proof, MAX_DFA};
+use super::{DirectProver, IncrementalMitMDFAProver, MitMDFAProver, SatBackend};
+use crate::core::{max_dfa, Machine, Proof};
+use serde::{Deserialize, Serialize};
 use std::cmp::{max, min};
 use std::ops::Range;
+use std::time::{Duration, Instant};
 
 pub type ProverBox = Box<dyn Prover + Send>;
 
+/// The outcome of a time-limited proof attempt: a `Proof`, giving up, or running out of time.
+pub type ProverResult = Result<Option<Proof>, TimedOut>;
+
+/// The error for a proof attempt that reached its `Deadline` before finishing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimedOut;
+
+/// A point in time after which a `Prover` should stop working on a TM (or never, by default).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Deadline(Option<Instant>);
+
+impl Deadline {
+    /// The deadline `limit` from now (or never, if there's no limit).
+    pub fn after(limit: Option<Duration>) -> Deadline {
+        Deadline(limit.map(|limit| Instant::now() + limit))
+    }
+
+    /// Whether the deadline has passed.
+    pub fn expired(&self) -> bool {
+        self.0.is_some_and(|at| Instant::now() >= at)
+    }
+
+    /// The time left until the deadline (or `None`, if there's no deadline).
+    pub fn remaining(&self) -> Option<Duration> {
+        self.0
+            .map(|at| at.saturating_duration_since(Instant::now()))
+    }
+
+    /// `Err(TimedOut)` if the deadline has passed, for convenient use with `?`.
+    pub fn check(&self) -> Result<(), TimedOut> {
+        if self.expired() {
+            Err(TimedOut)
+        } else {
+            Ok(())
+        }
+    }
+}
+
 /// At this level, `Prover`s are object-oriented. An instance provides:
 pub trait Prover {
     /// An identifier for the proof strategy -- may be used in output file names or status displays.
     fn name(&self) -> String;
 
     /// Either return a `Proof` for `tm` -- should be valid, but caller must verify -- or give up.
-    fn prove(&mut self, tm: &Machine) -> Option<Proof>;
+    /// If the `deadline` passes first, stop early and return `Err(TimedOut)`.
+    fn prove_within(&mut self, tm: &Machine, deadline: Deadline) -> ProverResult;
+
+    /// Like `prove_within()`, with no time limit.
+    fn prove(&mut self, tm: &Machine) -> Option<Proof> {
+        self.prove_within(tm, Deadline::default()).unwrap_or(None)
+    }
+
+    /// For provers which hand their search to a SAT solver: the instance for `tm`, in DIMACS CNF.
+    fn cnf(&mut self, _tm: &Machine) -> Option<String> {
+        None
+    }
 }
 
 pub trait ProverOptions {
     /// A new instance. Should be inexpensive, but first prove() call may be expensive.
     fn new(depth: usize) -> Self;
 
-    /// The range of possible search-depth parameters.
-    fn depths() -> Range<usize> {
-        1..(MAX_DFA + 1)
+    /// The range of possible search-depth parameters, for TMs with `tm_states` states.
+    fn depths(tm_states: usize) -> Range<usize> {
+        1..(max_dfa(tm_states) + 1)
+    }
+
+    /// Whichever of the given depths are valid, for TMs with `tm_states` states.
+    fn legal_depths(range: Range<usize>, tm_states: usize) -> Range<usize> {
+        let legal = Self::depths(tm_states);
+        (max(range.start, legal.start))..min(range.end, legal.end)
     }
 
     /// Return a vector of Provers, for whichever of the given depths are valid.
-    fn new_range(range: Range<usize>) -> Vec<ProverBox>
+    fn new_range(range: Range<usize>, tm_states: usize) -> Vec<ProverBox>
     where
         Self: Prover + Sized + Send + 'static,
     {
-        let legal = Self::depths();
-        ((max(range.start, legal.start))..min(range.end, legal.end))
+        Self::legal_depths(range, tm_states)
             .map(|depth| Box::new(Self::new(depth)) as _)
             .collect()
     }
@@ -42,16 +98,77 @@ pub fn prover_names() -> impl Iterator<Item = String> {
 }
 
 /// Return a vector of Provers, for whichever of the given depths are valid.
-pub fn prover_range_by_name<S: AsRef<str>>(name: S, range: Range<usize>) -> Vec<ProverBox> {
-    match name.as_ref() {
-        "direct" => DirectProver::new_range(range),
-        "mitm_dfa" => MitMDFAProver::new_range(range),
+/// SAT-based provers' names may pick an external solver, e.g. `mitm_dfa@kissat` (see sat.rs).
+pub fn prover_range_by_name<S: AsRef<str>>(
+    name: S,
+    range: Range<usize>,
+    tm_states: usize,
+) -> Vec<ProverBox> {
+    let (class, backend) = match name.as_ref().split_once('@') {
+        Some((class, solver)) => match SatBackend::external(solver) {
+            Ok(backend) => (class, backend),
+            Err(_) => return vec![],
+        },
+        None => (name.as_ref(), SatBackend::default()),
+    };
+    match class {
+        "direct" if backend == SatBackend::default() => DirectProver::new_range(range, tm_states),
+        "direct_par" if backend == SatBackend::default() => {
+            DirectProver::legal_depths(range, tm_states)
+                .map(|depth| Box::new(DirectProver::new(depth).in_parallel()) as _)
+                .collect()
+        }
+        "mitm_dfa" => MitMDFAProver::legal_depths(range, tm_states)
+            .map(|depth| Box::new(MitMDFAProver::new(depth).with_backend(backend.clone())) as _)
+            .collect(),
+        // As in `IncrementalMitMDFAProver::new_range()`, one instance covers the whole range.
+        "mitm_dfa_inc" => {
+            let depths = IncrementalMitMDFAProver::legal_depths(range, tm_states);
+            (depths.clone().last())
+                .map(|depth| {
+                    let prover = IncrementalMitMDFAProver::new(depth).starting_at(depths.start);
+                    Box::new(prover.with_backend(backend)) as _
+                })
+                .into_iter()
+                .collect()
+        }
         _ => vec![],
     }
 }
 
-pub fn prover_by_name<S: AsRef<str>>(name: S) -> Option<ProverBox> {
+/// The prover with the given name: a depth, e.g. `direct-3`, or (for provers covering a range of
+/// depths) an inclusive range, e.g. `mitm_dfa_inc-6..=12`.
+pub fn prover_by_name<S: AsRef<str>>(name: S, tm_states: usize) -> Option<ProverBox> {
     let (class, depth_str) = name.as_ref().rsplit_once('-')?;
-    let depth = depth_str.parse::<usize>().ok()?;
-    prover_range_by_name(class, depth..depth + 1).pop()
+    let (start, depth) = match depth_str.split_once("..=") {
+        Some((start, depth)) => (start.parse::<usize>().ok()?, depth.parse::<usize>().ok()?),
+        None => (
+            depth_str.parse::<usize>().ok()?,
+            depth_str.parse::<usize>().ok()?,
+        ),
+    };
+    prover_range_by_name(class, start..depth + 1, tm_states).pop()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deadlines() {
+        assert_eq!(Deadline::default().check(), Ok(()));
+        assert_eq!(
+            Deadline::after(Some(Duration::from_secs(3600))).check(),
+            Ok(())
+        );
+        let past = Deadline::after(Some(Duration::ZERO));
+        assert_eq!(past.check(), Err(TimedOut));
+        // Provers give up on expired deadlines, but find the same proofs otherwise.
+        let tm: Machine = "1RB1LB_1LA0LB".parse().unwrap();
+        for mut prover in prover_range_by_name("direct", 1..3, 2) {
+            assert!(matches!(prover.prove_within(&tm, past), Err(TimedOut)));
+            let proof = prover.prove_within(&tm, Deadline::default()).unwrap();
+            assert_eq!(proof.is_some(), prover.prove(&tm).is_some());
+        }
+    }
 }
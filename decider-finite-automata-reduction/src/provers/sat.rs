@@ -0,0 +1,578 @@
+//! SAT solver backends, for the provers which encode their search as a CNF formula.
+//! The built-in backend is `cat_solver` (CaDiCaL, in-process -- on a thread of its own, given a
+//! deadline, as `AbandonableSolver`). Alternatively, `ExternalSolver`
+//! writes the formula as DIMACS CNF to the standard input of any solver binary (e.g. kissat or
+//! cadical) and reads back its standard output, as in the SAT competition format.
+
+use super::{Deadline, TimedOut};
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt::{Display, Formatter};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// A literal: a variable number `x > 0`, or its negation `-x`.
+pub type Lit = i32;
+
+/// Anything we can emit clauses to.
+pub trait ClauseSink {
+    /// Add the disjunction of the given literals to the formula.
+    fn add_clause(&mut self, clause: &[Lit]);
+}
+
+/// A SAT solver: a `ClauseSink` which can find a satisfying assignment.
+pub trait SatSolver: ClauseSink {
+    /// Whether the formula (with the given literals assumed true) is satisfiable.
+    /// If the `deadline` passes first, the solver should stop and return `Err(TimedOut)`. So should
+    /// an inconclusive answer (e.g. an external solver crashing): it's no proof of unsatisfiability,
+    /// so the caller should give up on the TM for now, as on a timeout, not rule it out.
+    fn solve(&mut self, assumptions: &[Lit], deadline: Deadline) -> Result<bool, TimedOut>;
+
+    /// The value of a literal in the satisfying assignment, after `solve()` returns true.
+    fn value(&self, lit: Lit) -> Option<bool>;
+}
+
+impl<S: ClauseSink + ?Sized> ClauseSink for Box<S> {
+    fn add_clause(&mut self, clause: &[Lit]) {
+        (**self).add_clause(clause)
+    }
+}
+
+impl<S: SatSolver + ?Sized> SatSolver for Box<S> {
+    fn solve(&mut self, assumptions: &[Lit], deadline: Deadline) -> Result<bool, TimedOut> {
+        (**self).solve(assumptions, deadline)
+    }
+
+    fn value(&self, lit: Lit) -> Option<bool> {
+        (**self).value(lit)
+    }
+}
+
+impl ClauseSink for cat_solver::Solver {
+    fn add_clause(&mut self, clause: &[Lit]) {
+        cat_solver::Solver::add_clause(self, clause.iter().copied())
+    }
+}
+
+impl SatSolver for cat_solver::Solver {
+    /// `cat_solver` offers no way to interrupt `solve_with()`: with a deadline, use
+    /// `AbandonableSolver` instead.
+    fn solve(&mut self, assumptions: &[Lit], deadline: Deadline) -> Result<bool, TimedOut> {
+        deadline.check()?;
+        self.solve_with(assumptions.iter().copied()).ok_or(TimedOut)
+    }
+
+    fn value(&self, lit: Lit) -> Option<bool> {
+        cat_solver::Solver::value(self, lit)
+    }
+}
+
+/// A request to the thread running an `AbandonableSolver`.
+enum Request {
+    AddClause(Vec<Lit>),
+    Solve(Vec<Lit>),
+    Value(Lit),
+}
+
+/// The built-in solver, running on a thread of its own, so `solve()` can return at the deadline.
+/// It can't stop the solver there, so the thread runs on in the background until the solver
+/// finishes, then exits. (After a timeout, the instance is no more use: every call fails.)
+/// At most `max_abandoned()` such threads run at once: past that, new instances wait for one.
+pub struct AbandonableSolver {
+    /// The channels to and from the solver's thread, until we abandon it.
+    thread: Option<(Sender<Request>, Receiver<Option<bool>>)>,
+    /// Set (while holding the `ABANDONED` lock) once we abandon the thread.
+    abandoned: Arc<AtomicBool>,
+}
+
+/// The number of abandoned solver threads still running, and a signal for when one exits.
+static ABANDONED: (Mutex<usize>, Condvar) = (Mutex::new(0), Condvar::new());
+
+/// The most abandoned solver threads allowed to run at once: one per CPU.
+fn max_abandoned() -> usize {
+    thread::available_parallelism().map_or(1, usize::from)
+}
+
+/// Held by a solver's thread: when the thread exits, if it had been abandoned, it makes room.
+struct ExitSignal(Arc<AtomicBool>);
+
+impl Drop for ExitSignal {
+    fn drop(&mut self) {
+        let (count, exited) = &ABANDONED;
+        let mut count = count.lock().unwrap();
+        if self.0.load(Ordering::Relaxed) {
+            *count -= 1;
+            exited.notify_all();
+        }
+    }
+}
+
+impl AbandonableSolver {
+    /// A new, empty solver. If `max_abandoned()` threads are already running in the background,
+    /// this first waits for one to exit -- or until the `deadline`, giving a solver which has
+    /// already timed out.
+    pub fn new(deadline: Deadline) -> AbandonableSolver {
+        let abandoned = Arc::new(AtomicBool::new(false));
+        if !AbandonableSolver::wait_for_room(deadline, max_abandoned()) {
+            return AbandonableSolver {
+                thread: None,
+                abandoned,
+            };
+        }
+        let (requests, inbox) = mpsc::channel();
+        let (outbox, replies) = mpsc::channel();
+        let signal = ExitSignal(abandoned.clone());
+        thread::spawn(move || {
+            let _signal = signal;
+            let mut solver = cat_solver::Solver::new();
+            for request in inbox {
+                let reply = match request {
+                    Request::AddClause(clause) => {
+                        solver.add_clause(clause);
+                        continue;
+                    }
+                    Request::Solve(assumptions) => solver.solve_with(assumptions),
+                    Request::Value(lit) => solver.value(lit),
+                };
+                if outbox.send(reply).is_err() {
+                    break;
+                }
+            }
+        });
+        AbandonableSolver {
+            thread: Some((requests, replies)),
+            abandoned,
+        }
+    }
+
+    /// Wait until fewer than `max` abandoned threads are running, or until the `deadline`.
+    /// Returns whether there's room.
+    fn wait_for_room(deadline: Deadline, max: usize) -> bool {
+        let (count, exited) = &ABANDONED;
+        let mut count = count.lock().unwrap();
+        while *count >= max {
+            count = match deadline.remaining() {
+                None => exited.wait(count).unwrap(),
+                Some(left) if left.is_zero() => return false,
+                Some(left) => exited.wait_timeout(count, left).unwrap().0,
+            };
+        }
+        true
+    }
+
+    /// Leave the solver's thread to finish in the background, counting it as abandoned.
+    fn abandon(&mut self) {
+        let mut count = ABANDONED.0.lock().unwrap();
+        *count += 1;
+        self.abandoned.store(true, Ordering::Relaxed);
+        // Only now can the thread exit (and find it's been abandoned): its channels close.
+        self.thread = None;
+    }
+
+    /// Send the solver's thread a request, and wait for the reply until the deadline.
+    fn ask(&mut self, request: Request, deadline: Deadline) -> Result<Option<bool>, TimedOut> {
+        let (requests, replies) = self.thread.as_ref().ok_or(TimedOut)?;
+        if requests.send(request).is_err() {
+            return Err(TimedOut);
+        }
+        match deadline.remaining() {
+            None => replies.recv().map_err(|_| TimedOut),
+            Some(left) => match replies.recv_timeout(left) {
+                Ok(reply) => Ok(reply),
+                Err(RecvTimeoutError::Disconnected) => Err(TimedOut),
+                Err(RecvTimeoutError::Timeout) => {
+                    self.abandon();
+                    Err(TimedOut)
+                }
+            },
+        }
+    }
+}
+
+impl Default for AbandonableSolver {
+    fn default() -> Self {
+        Self::new(Deadline::default())
+    }
+}
+
+impl ClauseSink for AbandonableSolver {
+    fn add_clause(&mut self, clause: &[Lit]) {
+        if let Some((requests, _)) = &self.thread {
+            // If the thread is gone, `solve()` will find out.
+            let _ = requests.send(Request::AddClause(clause.to_vec()));
+        }
+    }
+}
+
+impl SatSolver for AbandonableSolver {
+    fn solve(&mut self, assumptions: &[Lit], deadline: Deadline) -> Result<bool, TimedOut> {
+        deadline.check()?;
+        self.ask(Request::Solve(assumptions.to_vec()), deadline)?
+            .ok_or(TimedOut)
+    }
+
+    fn value(&self, lit: Lit) -> Option<bool> {
+        let (requests, replies) = self.thread.as_ref()?;
+        requests.send(Request::Value(lit)).ok()?;
+        replies.recv().ok().flatten()
+    }
+}
+
+/// A formula in conjunctive normal form, which displays as DIMACS CNF.
+#[derive(Clone, Debug, Default)]
+pub struct Dimacs {
+    /// The clauses' literals, each clause terminated by a 0 (as in the file format).
+    literals: Vec<Lit>,
+    clauses: usize,
+    vars: Lit,
+}
+
+impl ClauseSink for Dimacs {
+    fn add_clause(&mut self, clause: &[Lit]) {
+        self.literals.extend(clause.iter().chain([&0]));
+        self.clauses += 1;
+        self.vars = clause
+            .iter()
+            .fold(self.vars, |vars, lit| vars.max(lit.abs()));
+    }
+}
+
+impl Dimacs {
+    /// Write the formula, plus a unit clause for each assumption, in DIMACS CNF format.
+    pub fn write<W: Write>(&self, assumptions: &[Lit], mut out: W) -> io::Result<()> {
+        let vars = assumptions
+            .iter()
+            .fold(self.vars, |vars, a| vars.max(a.abs()));
+        writeln!(out, "p cnf {} {}", vars, self.clauses + assumptions.len())?;
+        for clause in self.literals.split_inclusive(|&lit| lit == 0) {
+            for lit in clause {
+                write!(out, "{}{}", lit, if *lit == 0 { "\n" } else { " " })?;
+            }
+        }
+        for a in assumptions {
+            writeln!(out, "{} 0", a)?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for Dimacs {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        let mut bytes = vec![];
+        self.write(&[], &mut bytes).map_err(|_| std::fmt::Error)?;
+        f.write_str(&String::from_utf8_lossy(&bytes))
+    }
+}
+
+/// A solver which runs a separate solver process for each `solve()` call, and kills it if the
+/// deadline passes. (Unlike the built-in solver, it can't reuse work between calls.)
+pub struct ExternalSolver {
+    command: PathBuf,
+    formula: Dimacs,
+    /// The satisfying assignment's true literals, by variable.
+    model: Vec<Option<bool>>,
+}
+
+/// How often to check whether an external solver has finished or run out of time.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+impl ExternalSolver {
+    pub fn new(command: PathBuf) -> ExternalSolver {
+        ExternalSolver {
+            command,
+            formula: Dimacs::default(),
+            model: vec![],
+        }
+    }
+
+    /// Read a solver's output: `s SATISFIABLE`, then `v` lines listing the literals which are true;
+    /// or `s UNSATISFIABLE`. Anything else (e.g. `s UNKNOWN`, or no output) is inconclusive.
+    fn parse(&mut self, output: &str) -> Option<bool> {
+        let mut sat = None;
+        self.model.clear();
+        for line in output.lines() {
+            if let Some(status) = line.strip_prefix("s ") {
+                sat = match status.trim() {
+                    "SATISFIABLE" => Some(true),
+                    "UNSATISFIABLE" => Some(false),
+                    _ => None,
+                };
+            } else if let Some(values) = line.strip_prefix("v ") {
+                for lit in values
+                    .split_whitespace()
+                    .filter_map(|v| v.parse::<Lit>().ok())
+                {
+                    let var = lit.unsigned_abs() as usize;
+                    if var >= self.model.len() {
+                        self.model.resize(var + 1, None);
+                    }
+                    self.model[var] = Some(lit > 0);
+                }
+            }
+        }
+        sat
+    }
+
+    /// Start the solver, in a process group of its own (on Unix), so `kill()` can stop any
+    /// processes it starts in turn (e.g. if it's a wrapper script).
+    fn spawn(&self) -> io::Result<Child> {
+        let mut command = Command::new(&self.command);
+        command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null());
+        #[cfg(unix)]
+        std::os::unix::process::CommandExt::process_group(&mut command, 0);
+        command.spawn()
+    }
+
+    /// Stop a solver process, and any others in its process group.
+    fn kill(child: &mut Child) {
+        // The child hasn't been waited for, so its ID (which is also the group's) isn't reused yet.
+        #[cfg(unix)]
+        unsafe {
+            libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+        }
+        // Ignore errors: the solver may just have finished.
+        let _ = child.kill();
+        let _ = child.wait();
+    }
+}
+
+impl ClauseSink for ExternalSolver {
+    fn add_clause(&mut self, clause: &[Lit]) {
+        self.formula.add_clause(clause)
+    }
+}
+
+impl SatSolver for ExternalSolver {
+    fn solve(&mut self, assumptions: &[Lit], deadline: Deadline) -> Result<bool, TimedOut> {
+        deadline.check()?;
+        // If the solver can't be run, we can't conclude anything.
+        let mut child = self.spawn().map_err(|_| TimedOut)?;
+        // The pipes are serviced by threads, so neither the solver nor we block on a full buffer.
+        let mut input = vec![];
+        self.formula.write(assumptions, &mut input).unwrap();
+        let mut stdin = child.stdin.take().unwrap();
+        let writer = thread::spawn(move || stdin.write_all(&input));
+        let mut stdout = child.stdout.take().unwrap();
+        let reader = thread::spawn(move || {
+            let mut output = String::new();
+            stdout.read_to_string(&mut output).map(|_| output)
+        });
+        let finished = loop {
+            match child.try_wait() {
+                Ok(Some(_)) => break true,
+                Ok(None) if !deadline.expired() => thread::sleep(POLL_INTERVAL),
+                _ => {
+                    ExternalSolver::kill(&mut child);
+                    break false;
+                }
+            }
+        };
+        // Once the solver's processes are gone, so are their ends of the pipes, and these return.
+        // (The solver may exit without reading all its input, e.g. on a trivial formula.)
+        let _ = writer.join();
+        let output = reader.join().ok().and_then(Result::ok);
+        match output.filter(|_| finished) {
+            Some(output) => self.parse(&output).ok_or(TimedOut),
+            None => Err(TimedOut),
+        }
+    }
+
+    fn value(&self, lit: Lit) -> Option<bool> {
+        let value = (*self.model.get(lit.unsigned_abs() as usize)?)?;
+        Some(value == (lit > 0))
+    }
+}
+
+/// The paths of the external solvers used so far, by their names in prover names.
+static NAMED_SOLVERS: Mutex<BTreeMap<String, PathBuf>> = Mutex::new(BTreeMap::new());
+
+/// The choice of SAT solver for a prover.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum SatBackend {
+    /// The built-in `cat_solver`.
+    #[default]
+    CatSolver,
+    /// An external solver binary, given by name (as written in a prover name) and path.
+    External(String, PathBuf),
+}
+
+impl SatBackend {
+    /// The external solver with the given name: a path, or a binary found on the `PATH`.
+    /// In prover names (and so in file names), it's known by its file name, e.g. `kissat` for
+    /// `../kissat/build/kissat`, with any unusual characters replaced by `_`. That name then
+    /// refers to the same solver for the rest of the run; two solvers can't share one.
+    pub fn external(name: &str) -> io::Result<SatBackend> {
+        let path = Path::new(name);
+        let mut named = NAMED_SOLVERS.lock().unwrap();
+        let found = if path.components().count() > 1 {
+            path.is_file().then(|| path.to_path_buf())
+        } else if let Some(path) = named.get(name) {
+            Some(path.clone())
+        } else {
+            let dirs = env::var_os("PATH").unwrap_or_default();
+            env::split_paths(&dirs)
+                .map(|dir| dir.join(name))
+                .find(|path| path.is_file())
+        };
+        let found = found.ok_or_else(|| {
+            let msg = format!("SAT solver '{}' not found", name);
+            io::Error::new(io::ErrorKind::NotFound, msg)
+        })?;
+        let short: String = path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .chars()
+            .map(|c| match c {
+                'A'..='Z' | 'a'..='z' | '0'..='9' | '_' | '-' | '.' => c,
+                _ => '_',
+            })
+            .collect();
+        let path = named.entry(short.clone()).or_insert_with(|| found.clone());
+        if *path != found {
+            let msg = format!(
+                "SAT solvers '{}' and '{}' would both be named '{}'",
+                path.display(),
+                found.display(),
+                short
+            );
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, msg));
+        }
+        Ok(SatBackend::External(short, found))
+    }
+
+    /// A new, empty solver instance, for use within the `deadline`.
+    pub fn solver(&self, deadline: Deadline) -> Box<dyn SatSolver> {
+        match self {
+            SatBackend::CatSolver if deadline.remaining().is_none() => {
+                Box::new(cat_solver::Solver::new())
+            }
+            SatBackend::CatSolver => Box::new(AbandonableSolver::new(deadline)),
+            SatBackend::External(_, path) => Box::new(ExternalSolver::new(path.clone())),
+        }
+    }
+
+    /// The suffix distinguishing a prover's name, when it uses this backend (e.g. `@kissat`).
+    pub fn suffix(&self) -> String {
+        match self {
+            SatBackend::CatSolver => String::new(),
+            SatBackend::External(name, _) => format!("@{}", name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dimacs() {
+        let mut cnf = Dimacs::default();
+        cnf.add_clause(&[1, -3]);
+        cnf.add_clause(&[2, 3, -1]);
+        assert_eq!(cnf.to_string(), "p cnf 3 2\n1 -3 0\n2 3 -1 0\n");
+        let mut bytes = vec![];
+        cnf.write(&[-4], &mut bytes).unwrap();
+        assert_eq!(bytes, b"p cnf 4 3\n1 -3 0\n2 3 -1 0\n-4 0\n");
+    }
+
+    #[test]
+    fn test_abandonable_solver() {
+        let mut solver = AbandonableSolver::default();
+        solver.add_clause(&[1, 2]);
+        let minute = Deadline::after(Some(Duration::from_secs(60)));
+        assert_eq!(solver.solve(&[-2], minute), Ok(true));
+        assert_eq!(
+            (solver.value(1), solver.value(2)),
+            (Some(true), Some(false))
+        );
+        assert_eq!(solver.solve(&[-1, -2], Deadline::default()), Ok(false));
+        // A hard instance, which it gives up on at the deadline: 11 pigeons in 10 holes.
+        let mut solver = AbandonableSolver::default();
+        let pigeon_in = |p: Lit, h: Lit| 10 * p + h + 1;
+        for p in 0..11 {
+            solver.add_clause(&(0..10).map(|h| pigeon_in(p, h)).collect::<Vec<_>>());
+            for q in 0..p {
+                (0..10).for_each(|h| solver.add_clause(&[-pigeon_in(p, h), -pigeon_in(q, h)]));
+            }
+        }
+        let deadline = Deadline::after(Some(Duration::from_millis(50)));
+        let start = std::time::Instant::now();
+        assert_eq!(solver.solve(&[], deadline), Err(TimedOut));
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(solver.solve(&[], Deadline::default()), Err(TimedOut));
+        // Its thread still runs, and counts against the limit: with no room, new solvers wait.
+        assert!(*ABANDONED.0.lock().unwrap() >= 1);
+        let deadline = Deadline::after(Some(Duration::from_millis(50)));
+        let start = std::time::Instant::now();
+        assert!(!AbandonableSolver::wait_for_room(deadline, 1));
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert!(AbandonableSolver::wait_for_room(
+            Deadline::default(),
+            usize::MAX
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_external_solver() {
+        use std::os::unix::fs::PermissionsExt;
+        // Stand-in "solvers": one answers immediately, the other would take too long.
+        let script = |name: &str, body: &str| {
+            let path = env::temp_dir().join(format!("{}-{}.sh", name, std::process::id()));
+            std::fs::write(&path, format!("#!/bin/sh\n{}\n", body)).unwrap();
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+            path
+        };
+        let quick = script(
+            "sat",
+            "cat >/dev/null; echo 's SATISFIABLE'; echo 'v 1 -2 0'",
+        );
+        // A wrapper script whose solver would outlast it, if not killed along with it.
+        let slow = script("slow", "sleep 10 & wait");
+        let crash = script("crash", "cat >/dev/null; exit 1");
+        let unknown = script("unknown", "cat >/dev/null; echo 's UNKNOWN'");
+        let unsat = script("unsat", "cat >/dev/null; echo 's UNSATISFIABLE'");
+        let mut solver = ExternalSolver::new(quick.clone());
+        solver.add_clause(&[1, 2]);
+        assert_eq!(solver.solve(&[-2], Deadline::default()), Ok(true));
+        assert_eq!(
+            (solver.value(1), solver.value(-2), solver.value(3)),
+            (Some(true), Some(true), None)
+        );
+        let mut solver = ExternalSolver::new(slow.clone());
+        let deadline = Deadline::after(Some(Duration::from_millis(50)));
+        let start = std::time::Instant::now();
+        assert_eq!(solver.solve(&[], deadline), Err(TimedOut));
+        assert!(start.elapsed() < Duration::from_secs(5));
+        // Only an actual `s UNSATISFIABLE` counts as unsatisfiable.
+        let missing = env::temp_dir().join("no-such-sat-solver");
+        for (path, result) in [
+            (&crash, Err(TimedOut)),
+            (&unknown, Err(TimedOut)),
+            (&missing, Err(TimedOut)),
+            (&unsat, Ok(false)),
+        ] {
+            let mut solver = ExternalSolver::new(path.clone());
+            assert_eq!(solver.solve(&[], Deadline::default()), result);
+        }
+        let backend = SatBackend::external(quick.to_str().unwrap()).unwrap();
+        let name = quick.file_name().unwrap().to_str().unwrap();
+        assert_eq!(backend.suffix(), format!("@{}", name));
+        // The name now refers to the same solver, even if it's not on the `PATH`.
+        assert_eq!(SatBackend::external(name).unwrap(), backend);
+        assert!(SatBackend::external("no-such-sat-solver").is_err());
+        for path in [quick, slow, crash, unknown, unsat] {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+}
@@ -0,0 +1,53 @@
+//! Reusing a known proof for an equivalent TM, e.g. when the seed database is regenerated in a
+//! different order. `Symmetry::apply_proof()` handles mirroring and renaming states exactly.
+//! If the TMs also differ in transitions from states that are unreachable from A, the transformed
+//! NFA may not be closed under those rules. The DFA still works, though, so we complete it again.
+
+use super::DirectProver;
+use crate::core::{Machine, Proof};
+
+/// A valid proof for `to`, derived from a (valid) proof for a TM `from` which, up to mirroring and
+/// renaming states, agrees with `to` on all reachable states. `None` if the TMs aren't related,
+/// or (rarely) if no proof with the same DFA works for the unreachable transitions of `to`.
+pub fn transfer(proof: &Proof, from: &Machine, to: &Machine) -> Option<Proof> {
+    let symmetry = from.symmetry_to(to)?;
+    let image = symmetry.apply_proof(proof, from);
+    if image.validate(to).is_ok() {
+        return Some(image);
+    }
+    let automaton = image.automaton;
+    DirectProver::complete_unverified(to, automaton.direction, automaton.dfa)
+        .filter(|proof| proof.validate(to).is_ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provers::{Prover, ProverOptions};
+    use std::str::FromStr;
+
+    #[test]
+    fn test_transfer() {
+        let tm = Machine::from_str("1RB1LC_0LA0LB_1RC0RA").unwrap();
+        let proof = DirectProver::new(3)
+            .prove(&tm)
+            .expect("solvable at depth 3");
+        // Mirrored, with B and C swapped:
+        let renamed = Machine::from_str("1LC1RB_1LB0LA_0RA0RC").unwrap();
+        let renamed_proof = transfer(&proof, &tm, &renamed).expect("an equivalent TM");
+        assert_eq!(renamed_proof.validate(&renamed), Ok(()));
+        assert!(transfer(&renamed_proof, &renamed, &tm).is_some());
+        // A TM with an unreachable state C, whose rules are arbitrary:
+        let tm = Machine::from_str("1RB1LB_1LA0LB_------").unwrap();
+        let proof = DirectProver::new(1)
+            .prove(&tm)
+            .expect("solvable at depth 1");
+        let padded = Machine::from_str("1RB1LB_1LA0LB_1RC0LA").unwrap();
+        assert!(proof.validate(&padded).is_err());
+        let padded_proof = transfer(&proof, &tm, &padded).expect("an equivalent TM");
+        assert_eq!(padded_proof.validate(&padded), Ok(()));
+        // TMs which really differ:
+        let other = Machine::from_str("1RB1LB_1LA1LB_------").unwrap();
+        assert!(transfer(&proof, &tm, &other).is_none());
+    }
+}